@@ -130,6 +130,7 @@ fn deployment_strategy_name(strategy: DeploymentStrategy) -> &'static str {
     match strategy {
         DeploymentStrategy::Merge => "merge",
         DeploymentStrategy::Put => "put",
+        DeploymentStrategy::Copy => "copy",
     }
 }
 
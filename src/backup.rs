@@ -0,0 +1,126 @@
+//! Backups of files that were in the way when deploying an egg.
+//!
+//! Every deployment that backs up files gets its own timestamped directory inside `.backups/<egg>/`.
+//! The backed up files are stored under `files/`, mirroring their original absolute path,
+//! and the original paths are listed in a `paths` index file, so they can be restored later.
+use std::{
+    io::Write as _,
+    path::{Component, Path, PathBuf},
+};
+
+use fs_err::PathExt as _;
+use miette::{Context as _, IntoDiagnostic as _, Result};
+
+use crate::util::PathExt as _;
+
+const INDEX_FILE: &str = "paths";
+const FILES_DIR: &str = "files";
+
+/// Callback that decides whether the backed up files of an egg (given by name and original paths) should be restored on undeploy.
+pub type RestoreHandler = dyn Fn(&str, &[PathBuf]) -> Result<bool> + Send + Sync;
+
+/// All backups that were made while deploying a single egg.
+pub struct EggBackups {
+    dir: PathBuf,
+}
+
+impl EggBackups {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Path to a fresh backup directory for a deployment happening right now.
+    ///
+    /// The directory is named after the current time with sub-second precision,
+    /// with a numeric suffix added if a backup directory of that name already exists.
+    pub fn new_backup_dir(&self) -> PathBuf {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let timestamp = format!("{}.{:09}", now.as_secs(), now.subsec_nanos());
+        let mut dir = self.dir.join(&timestamp);
+        let mut suffix = 1;
+        while dir.exists() {
+            dir = self.dir.join(format!("{timestamp}-{suffix}"));
+            suffix += 1;
+        }
+        dir
+    }
+
+    /// List all backups of this egg, newest first.
+    pub fn list(&self) -> Result<Vec<BackupSet>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut sets = Vec::new();
+        for entry in self.dir.fs_err_read_dir().into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            let index_path = entry.path().join(INDEX_FILE);
+            if !index_path.is_file() {
+                continue;
+            }
+            let paths = fs_err::read_to_string(index_path)
+                .into_diagnostic()?
+                .lines()
+                .map(PathBuf::from)
+                .collect();
+            sets.push(BackupSet {
+                dir: entry.path(),
+                paths,
+            });
+        }
+        sets.sort_by(|a, b| b.dir.cmp(&a.dir));
+        Ok(sets)
+    }
+}
+
+/// The files backed up during a single deployment.
+#[derive(Debug)]
+pub struct BackupSet {
+    pub dir: PathBuf,
+    /// The original locations of the backed up files.
+    pub paths: Vec<PathBuf>,
+}
+
+impl BackupSet {
+    pub fn backup_path_of(&self, original: impl AsRef<Path>) -> PathBuf {
+        backup_path_in(&self.dir, original)
+    }
+}
+
+/// The path a file at `original` is moved to when backing it up into `backup_dir`.
+pub fn backup_path_in(backup_dir: impl AsRef<Path>, original: impl AsRef<Path>) -> PathBuf {
+    let relative: PathBuf = original
+        .as_ref()
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    backup_dir.as_ref().join(FILES_DIR).join(relative)
+}
+
+/// Add `original` to the index of files that were backed up into `backup_dir`.
+pub fn record_backup(backup_dir: impl AsRef<Path>, original: impl AsRef<Path>) -> Result<()> {
+    let index_path = backup_dir.as_ref().join(INDEX_FILE);
+    let mut file = fs_err::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&index_path)
+        .into_diagnostic()?;
+    writeln!(file, "{}", original.as_ref().to_string_lossy())
+        .into_diagnostic()
+        .with_context(|| format!("Failed to update backup index {}", index_path.abbr()))
+}
+
+/// Remove `backup_dir` entirely if it no longer contains any backed up files.
+pub fn remove_if_empty(backup_dir: impl AsRef<Path>) -> Result<()> {
+    let backup_dir = backup_dir.as_ref();
+    let files_dir = backup_dir.join(FILES_DIR);
+    let has_files = files_dir.exists()
+        && crate::util::file_entries_recursive(&files_dir)
+            .next()
+            .is_some();
+    if backup_dir.exists() && !has_files {
+        fs_err::remove_dir_all(backup_dir).into_diagnostic()?;
+    }
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     process::Stdio,
+    str::FromStr,
 };
 
 use fs_err::PathExt as _;
@@ -8,20 +10,126 @@ use miette::{Context as _, IntoDiagnostic};
 use normalize_path::NormalizePath as _;
 use which::which_global;
 
-use crate::util::PathExt as _;
+use crate::{
+    backup,
+    eggs_config::DeploymentStrategy,
+    manifest::ManifestEntry,
+    multi_error::MultiError,
+    permissions::FilePermissions,
+    util::{self, print_dry_run, PathExt as _},
+    yolk_paths::EggIgnore,
+};
+
+/// What to do with a copied file whose deployed version was modified since yolk copied it there.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DriftResolution {
+    /// Copy the modified file back into the egg.
+    Pull,
+    /// Replace the modified file with the version from the egg.
+    Overwrite,
+    /// Leave the modified file alone for now.
+    #[default]
+    Skip,
+}
+
+impl FromStr for DriftResolution {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pull" => Ok(DriftResolution::Pull),
+            "overwrite" => Ok(DriftResolution::Overwrite),
+            "skip" => Ok(DriftResolution::Skip),
+            _ => miette::bail!(
+                help = "must be one of 'pull', 'overwrite' or 'skip'",
+                "Invalid drift resolution {}",
+                s
+            ),
+        }
+    }
+}
+
+/// A copied file whose deployed version no longer matches what yolk copied there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriftedCopy {
+    /// The file inside the egg
+    pub in_egg: PathBuf,
+    /// The deployed copy of the file
+    pub deployed: PathBuf,
+}
+
+/// Callback that decides how to deal with a [`DriftedCopy`].
+pub type DriftHandler = dyn Fn(&DriftedCopy) -> miette::Result<DriftResolution> + Send + Sync;
+
+/// A change to the filesystem made by a [`Deployer`], recorded so it can be undone by [`Deployer::rollback()`].
+#[derive(Debug)]
+enum JournalEntry {
+    /// A symlink was created at `link`.
+    CreatedSymlink { link: PathBuf, elevated: bool },
+    /// The symlink at `link`, pointing to `original`, was removed.
+    DeletedSymlink {
+        link: PathBuf,
+        original: PathBuf,
+        elevated: bool,
+    },
+    /// A directory that did not exist before was created.
+    CreatedDir(PathBuf),
+    /// An empty directory was removed, with `permissions` being its mode before.
+    RemovedDir {
+        path: PathBuf,
+        permissions: FilePermissions,
+    },
+    /// A file was moved from `original` into the backup directory at `backup`.
+    BackedUp { original: PathBuf, backup: PathBuf },
+    /// The file at `path` was written, with `previous` being its content before, if it existed.
+    WroteFile {
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    /// The file at `path`, which had the given `content`, was removed.
+    DeletedFile { path: PathBuf, content: Vec<u8> },
+    /// The mode or owner of the file at `path` was changed, with `previous` being what it was before.
+    ChangedPermissions {
+        path: PathBuf,
+        previous: FilePermissions,
+        elevated: bool,
+    },
+}
 
 /// Struct that keeps track of the deployment and undeployment process of multiple symlinks.
 ///
 /// We keep track of all created symlinks, as well as all symlinks where the creation or deletion failed due to insufficient permissions.
 /// In case of missing permissions, you can then use [`Deployer::try_run_elevated()`] to retry the operation with elevated privileges.
+///
+/// Every change to the filesystem is journaled, so that a failed deployment can be undone via [`Deployer::rollback()`].
 #[derive(Default, Debug)]
 pub struct Deployer {
-    /// Symlinks that were successfully created
-    created_symlinks: Vec<PathBuf>,
+    /// Symlinks (actual_path, symlink_path) that were successfully created
+    created_symlinks: Vec<(PathBuf, PathBuf)>,
+    /// Symlinks (actual_path, symlink_path) that were created with elevated privileges
+    elevated_symlinks: Vec<(PathBuf, PathBuf)>,
+    /// Copied files that are now managed by yolk
+    copied_files: Vec<ManifestEntry>,
+    /// Directories (actual_path, dir_path) that were created instead of symlinking the directory inside the egg
+    created_dirs: Vec<(PathBuf, PathBuf)>,
     /// Symlink creation mappings (actual_path, symlink_path) that failed due to insufficient permissions
     missing_permissions_create: Vec<(PathBuf, PathBuf)>,
     /// Symlink deletion paths (symlink_path) that failed due to insufficient permissions
     missing_permissions_remove: Vec<PathBuf>,
+    /// Files whose mode or owner could not be changed due to insufficient permissions
+    missing_permissions_chmod: Vec<(PathBuf, FilePermissions)>,
+    /// Files (original_path, backup_path) that were moved out of the way of the deployment
+    backed_up_files: Vec<(PathBuf, PathBuf)>,
+    /// When set, conflicting files are moved into this directory instead of failing the deployment.
+    backup_dir: Option<PathBuf>,
+    /// Only print the operations that would be performed, without touching the filesystem.
+    dry_run: bool,
+    /// Files inside the egg that should not be deployed.
+    ignore: Option<EggIgnore>,
+    /// Create symlinks with a target relative to the directory containing them.
+    relative_symlinks: bool,
+    /// All changes made to the filesystem so far, in order
+    journal: Vec<JournalEntry>,
 }
 
 impl Deployer {
@@ -29,10 +137,93 @@ impl Deployer {
         Self::default()
     }
 
-    pub fn created_symlinks(&self) -> &Vec<PathBuf> {
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Back up any conflicting files into the given directory, rather than failing the deployment.
+    pub fn with_backup_dir(mut self, backup_dir: impl Into<PathBuf>) -> Self {
+        self.backup_dir = Some(backup_dir.into());
+        self
+    }
+
+    /// Skip files matched by the given ignore patterns when deploying or undeploying.
+    pub fn with_ignore(mut self, ignore: EggIgnore) -> Self {
+        self.ignore = Some(ignore);
+        self
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.is_ignored(path))
+    }
+
+    /// Check whether the given directory contains any ignored files, meaning it can't be symlinked as a whole.
+    pub fn contains_ignored(&self, dir: &Path) -> bool {
+        self.ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.contains_ignored(dir))
+    }
+
+    pub fn backs_up_conflicts(&self) -> bool {
+        self.backup_dir.is_some()
+    }
+
+    pub fn backed_up_files(&self) -> &Vec<(PathBuf, PathBuf)> {
+        &self.backed_up_files
+    }
+
+    pub fn with_relative_symlinks(mut self, relative_symlinks: bool) -> Self {
+        self.relative_symlinks = relative_symlinks;
+        self
+    }
+
+    pub fn created_symlinks(&self) -> &Vec<(PathBuf, PathBuf)> {
         &self.created_symlinks
     }
 
+    pub fn copied_files(&self) -> &Vec<ManifestEntry> {
+        &self.copied_files
+    }
+
+    pub fn created_dirs(&self) -> &Vec<(PathBuf, PathBuf)> {
+        &self.created_dirs
+    }
+
+    /// Everything that is deployed by this deployer, as entries for the deployment manifest.
+    ///
+    /// `strategy_of` determines the strategy a symlink was deployed with from the path inside the egg it points to.
+    pub fn manifest_entries(
+        &self,
+        strategy_of: impl Fn(&Path) -> DeploymentStrategy,
+    ) -> Vec<ManifestEntry> {
+        let symlinks = self
+            .created_symlinks
+            .iter()
+            .map(|(original, link)| (original, link, false))
+            .chain(
+                self.elevated_symlinks
+                    .iter()
+                    .map(|(original, link)| (original, link, true)),
+            )
+            .map(|(original, link, elevated)| {
+                ManifestEntry::symlink(link.clone(), original.clone(), strategy_of(original))
+                    .with_elevated(elevated)
+            });
+        let dirs = self.created_dirs.iter().map(|(original, dir)| {
+            ManifestEntry::directory(dir.clone(), original.clone(), strategy_of(original))
+        });
+        dirs.chain(symlinks)
+            .chain(self.copied_files.iter().cloned())
+            .collect()
+    }
+
     pub fn failed_creations(&self) -> &Vec<(PathBuf, PathBuf)> {
         &self.missing_permissions_create
     }
@@ -41,8 +232,14 @@ impl Deployer {
         &self.missing_permissions_remove
     }
 
+    pub fn failed_permission_changes(&self) -> &Vec<(PathBuf, FilePermissions)> {
+        &self.missing_permissions_chmod
+    }
+
     pub fn has_pending_elevated_operations(&self) -> bool {
-        !self.missing_permissions_create.is_empty() || !self.missing_permissions_remove.is_empty()
+        !self.missing_permissions_create.is_empty()
+            || !self.missing_permissions_remove.is_empty()
+            || !self.missing_permissions_chmod.is_empty()
     }
 
     pub fn pending_elevated_operations_summary(&self) -> String {
@@ -67,11 +264,21 @@ impl Deployer {
                     .join(", ")
             ));
         }
+        if !self.missing_permissions_chmod.is_empty() {
+            parts.push(format!(
+                "set permissions of {}",
+                self.missing_permissions_chmod
+                    .iter()
+                    .map(|(path, _)| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
         parts.join("; ")
     }
 
-    pub fn add_created_symlink(&mut self, link_path: PathBuf) {
-        self.created_symlinks.push(link_path);
+    pub fn add_created_symlink(&mut self, actual_path: PathBuf, link_path: PathBuf) {
+        self.created_symlinks.push((actual_path, link_path));
     }
 
     /// Create a symlink from at the path `link` pointing to the `original` file.
@@ -83,8 +290,17 @@ impl Deployer {
         let link = link.as_ref();
         let original = original.as_ref();
         tracing::trace!("Creating symlink at {} -> {}", link.abbr(), original.abbr());
+        if self.dry_run {
+            print_dry_run(format!(
+                "create symlink {}",
+                format_symlink(link.abbr(), original.abbr())
+            ));
+            self.created_symlinks
+                .push((original.to_path_buf(), link.to_path_buf()));
+            return Ok(());
+        }
 
-        if let Err(err) = symlink::symlink_auto(original, link) {
+        if let Err(err) = symlink_to(&self.symlink_target(original, link), link) {
             if err.kind() != std::io::ErrorKind::PermissionDenied {
                 return Err(err).into_diagnostic().wrap_err_with(|| {
                     format!(
@@ -96,10 +312,60 @@ impl Deployer {
             self.missing_permissions_create
                 .push((original.to_path_buf(), link.to_path_buf()));
         } else {
-            self.created_symlinks.push(link.to_path_buf());
+            self.created_symlinks
+                .push((original.to_path_buf(), link.to_path_buf()));
+            self.journal.push(JournalEntry::CreatedSymlink {
+                link: link.to_path_buf(),
+                elevated: false,
+            });
         }
         Ok(())
     }
+
+    /// The target a symlink at `link` pointing to `original` should be created with.
+    ///
+    /// This is `original` itself, or, when creating relative symlinks, the path to it relative to the directory containing `link`.
+    pub fn symlink_target(&self, original: &Path, link: &Path) -> PathBuf {
+        if !self.relative_symlinks {
+            return original.to_path_buf();
+        }
+        let Some(parent) = link.parent() else {
+            return original.to_path_buf();
+        };
+        let parent = parent.canonical().unwrap_or_else(|_| parent.to_path_buf());
+        util::relative_path(original, parent).unwrap_or_else(|| original.to_path_buf())
+    }
+
+    /// Recreate the given symlink if its target is absolute but should be relative, or vice versa.
+    /// Returns whether the symlink was recreated.
+    ///
+    /// Symlinks that can't be expressed relative to their directory, such as ones pointing to another drive on windows,
+    /// are left alone, as recreating them would produce the same symlink again.
+    pub fn update_symlink_style(&mut self, link: impl AsRef<Path>) -> miette::Result<bool> {
+        let link = link.as_ref();
+        let target = link.fs_err_read_link().into_diagnostic()?;
+        if target.is_relative() == self.relative_symlinks {
+            return Ok(false);
+        }
+        let original = link.read_link_absolute()?;
+        if self.symlink_target(&original, link) == target {
+            return Ok(false);
+        }
+        cov_mark::hit!(update_symlink_style);
+        tracing::info!(
+            "Recreating symlink {} as {} symlink",
+            format_symlink(link.abbr(), original.abbr()),
+            if self.relative_symlinks {
+                "relative"
+            } else {
+                "absolute"
+            }
+        );
+        self.delete_symlink(link)?;
+        self.create_symlink(original, link)?;
+        Ok(true)
+    }
+
     /// Remove a symlink from at the path `link` pointing to the `original` file.
     pub fn delete_symlink(&mut self, path: impl AsRef<Path>) -> miette::Result<()> {
         let path = path.as_ref();
@@ -107,6 +373,11 @@ impl Deployer {
         if !path.is_symlink() {
             miette::bail!("Path is not a symlink: {}", path.abbr());
         }
+        if self.dry_run {
+            print_dry_run(format!("delete symlink {}", path.abbr()));
+            return Ok(());
+        }
+        let original = path.fs_err_read_link().into_diagnostic()?;
         let result = if path.symlink_metadata().into_diagnostic()?.is_dir() {
             symlink::remove_symlink_dir(path)
         } else {
@@ -122,7 +393,11 @@ impl Deployer {
             }
         };
         match result {
-            Ok(()) => {}
+            Ok(()) => self.journal.push(JournalEntry::DeletedSymlink {
+                link: path.to_path_buf(),
+                original,
+                elevated: false,
+            }),
             Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
                 self.missing_permissions_remove.push(path.to_path_buf());
             }
@@ -135,17 +410,301 @@ impl Deployer {
         Ok(())
     }
 
+    /// Create the given directory and all of its parents, if they don't exist yet.
+    pub fn create_dir_all(&mut self, path: impl AsRef<Path>) -> miette::Result<()> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Ok(());
+        }
+        if self.dry_run {
+            print_dry_run(format!("create directory {}", path.abbr()));
+            return Ok(());
+        }
+        let missing = path
+            .ancestors()
+            .take_while(|dir| !dir.exists())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        fs_err::create_dir_all(path).into_diagnostic()?;
+        self.journal
+            .extend(missing.into_iter().rev().map(JournalEntry::CreatedDir));
+        Ok(())
+    }
+
+    /// Apply the given mode and owner to the file at `path`, following symlinks.
+    ///
+    /// Does nothing if the file already has these permissions.
+    /// If changing them requires elevated privileges, the change is queued for [`Deployer::try_run_elevated()`].
+    pub fn set_permissions(
+        &mut self,
+        path: impl AsRef<Path>,
+        permissions: &FilePermissions,
+    ) -> miette::Result<()> {
+        let path = path.as_ref();
+        if permissions.is_empty() || (path.exists() && permissions.differences(path)?.is_empty()) {
+            return Ok(());
+        }
+        tracing::trace!("Setting {permissions} on {}", path.abbr());
+        if self.dry_run {
+            print_dry_run(format!("set {permissions} on {}", path.abbr()));
+            return Ok(());
+        }
+        let previous = FilePermissions::current(path)?;
+        match permissions.apply(path) {
+            Ok(()) => self.journal.push(JournalEntry::ChangedPermissions {
+                path: path.to_path_buf(),
+                previous,
+                elevated: false,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                self.missing_permissions_chmod
+                    .push((path.to_path_buf(), permissions.clone()));
+            }
+            Err(e) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to set {permissions} on {}", path.abbr()))
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a directory that yolk created, if it is empty.
+    /// Returns whether the directory was removed.
+    pub fn remove_dir_if_empty(&mut self, path: impl AsRef<Path>) -> miette::Result<bool> {
+        let path = path.as_ref();
+        if path.is_symlink() || !path.is_dir() {
+            return Ok(false);
+        }
+        if path.fs_err_read_dir().into_diagnostic()?.next().is_some() {
+            return Ok(false);
+        }
+        tracing::trace!("Removing empty directory {}", path.abbr());
+        if self.dry_run {
+            print_dry_run(format!("remove empty directory {}", path.abbr()));
+            return Ok(true);
+        }
+        // Only the mode is restored on rollback, as the directory may belong to someone else.
+        let permissions = FilePermissions {
+            mode: FilePermissions::current(path)?.mode,
+            owner: None,
+        };
+        fs_err::remove_dir(path).into_diagnostic()?;
+        self.journal.push(JournalEntry::RemovedDir {
+            path: path.to_path_buf(),
+            permissions,
+        });
+        Ok(true)
+    }
+
+    /// Move a file or directory that is in the way of the deployment into the backup directory.
+    ///
+    /// Fails if no backup directory was configured via [`Deployer::with_backup_dir`].
+    pub fn backup_file(&mut self, path: impl AsRef<Path>) -> miette::Result<()> {
+        let path = path.as_ref();
+        let Some(backup_dir) = self.backup_dir.clone() else {
+            miette::bail!("Tried to back up {}, but backups are disabled", path.abbr());
+        };
+        let backup_path = backup::backup_path_in(&backup_dir, path);
+        tracing::trace!("Backing up {} to {}", path.abbr(), backup_path.abbr());
+        if self.dry_run {
+            print_dry_run(format!("back up {} to {}", path.abbr(), backup_path.abbr()));
+            self.backed_up_files.push((path.to_path_buf(), backup_path));
+            return Ok(());
+        }
+        if let Some(parent) = backup_path.parent() {
+            fs_err::create_dir_all(parent).into_diagnostic()?;
+        }
+        util::rename_safely(path, &backup_path)
+            .wrap_err_with(|| format!("Failed to back up {}", path.abbr()))?;
+        self.journal.push(JournalEntry::BackedUp {
+            original: path.to_path_buf(),
+            backup: backup_path.clone(),
+        });
+        backup::record_backup(&backup_dir, path)?;
+        tracing::info!("Backed up {} to {}", path.abbr(), backup_path.abbr());
+        self.backed_up_files.push((path.to_path_buf(), backup_path));
+        Ok(())
+    }
+
+    /// Copy a file from `from` to `to`, creating any missing parent directories.
+    pub fn copy_file(
+        &mut self,
+        from: impl AsRef<Path>,
+        to: impl AsRef<Path>,
+    ) -> miette::Result<()> {
+        let from = from.as_ref();
+        let to = to.as_ref();
+        tracing::trace!("Copying {} to {}", from.abbr(), to.abbr());
+        if self.dry_run {
+            print_dry_run(format!("copy {} to {}", from.abbr(), to.abbr()));
+            return Ok(());
+        }
+        if let Some(parent) = to.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let previous = if to.is_file() {
+            Some(fs_err::read(to).into_diagnostic()?)
+        } else {
+            None
+        };
+        fs_err::copy(from, to)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to copy {} to {}", from.abbr(), to.abbr()))?;
+        self.journal.push(JournalEntry::WroteFile {
+            path: to.to_path_buf(),
+            previous,
+        });
+        Ok(())
+    }
+
+    /// Remove a file that was previously deployed as a copy.
+    pub fn delete_copy(&mut self, path: impl AsRef<Path>) -> miette::Result<()> {
+        let path = path.as_ref();
+        tracing::trace!("Deleting copied file at {}", path.abbr());
+        if self.dry_run {
+            print_dry_run(format!("delete copied file {}", path.abbr()));
+            return Ok(());
+        }
+        let content = fs_err::read(path).into_diagnostic()?;
+        fs_err::remove_file(path).into_diagnostic()?;
+        self.journal.push(JournalEntry::DeletedFile {
+            path: path.to_path_buf(),
+            content,
+        });
+        Ok(())
+    }
+
+    /// Deploy `in_egg` to `deployed` by copying it, recursing into directories.
+    ///
+    /// `previous_copies` maps deployed paths to the hash they had when yolk last copied them.
+    /// This is used to distinguish between files that changed in the egg, which are simply copied again,
+    /// and deployed files that were modified by something else, which are handed to `on_drift`.
+    pub fn copy_recursive(
+        &mut self,
+        in_egg: impl AsRef<Path>,
+        deployed: impl AsRef<Path>,
+        previous_copies: &HashMap<PathBuf, String>,
+        on_drift: &DriftHandler,
+    ) -> miette::Result<()> {
+        let in_egg = in_egg.as_ref();
+        let deployed = deployed.as_ref();
+        // When switching over from a symlink-based strategy, the old symlink is in the way.
+        if deployed.is_symlink() && deployed.canonical().ok() == in_egg.canonical().ok() {
+            self.delete_symlink(deployed)?;
+        }
+        for (source, target) in expand_copy_targets(in_egg, deployed, self.ignore.as_ref())? {
+            self.copy_single_file(&source, &target, previous_copies.get(&target), on_drift)?;
+        }
+        Ok(())
+    }
+
+    fn copy_single_file(
+        &mut self,
+        source: &Path,
+        target: &Path,
+        previous_hash: Option<&String>,
+        on_drift: &DriftHandler,
+    ) -> miette::Result<()> {
+        if target.is_symlink() {
+            if !self.backs_up_conflicts() {
+                miette::bail!(
+                    "Failed to copy {} to {}, as a symlink already exists there",
+                    source.abbr(),
+                    target.abbr()
+                );
+            }
+            self.backup_file(target)?;
+        }
+        let egg_hash = util::file_content_hash(source)?;
+        if !target.exists() {
+            self.copy_file(source, target)?;
+            self.record_copy(source, target, egg_hash);
+            return Ok(());
+        }
+        let deployed_hash = util::file_content_hash(target)?;
+        if deployed_hash == egg_hash {
+            self.record_copy(source, target, egg_hash);
+            return Ok(());
+        }
+        match previous_hash {
+            None if self.backs_up_conflicts() => {
+                self.backup_file(target)?;
+                self.copy_file(source, target)?;
+                self.record_copy(source, target, egg_hash);
+            }
+            None => miette::bail!(
+                "Failed to copy {} to {}, as a different file already exists there",
+                source.abbr(),
+                target.abbr()
+            ),
+            Some(previous_hash) if *previous_hash == deployed_hash => {
+                cov_mark::hit!(copy_egg_changed);
+                self.copy_file(source, target)?;
+                self.record_copy(source, target, egg_hash);
+            }
+            Some(previous_hash) => {
+                cov_mark::hit!(copy_drifted);
+                let drifted = DriftedCopy {
+                    in_egg: source.to_path_buf(),
+                    deployed: target.to_path_buf(),
+                };
+                let resolution = if self.dry_run {
+                    print_dry_run(format!(
+                        "ask how to resolve modified copy {}",
+                        target.abbr()
+                    ));
+                    DriftResolution::Skip
+                } else {
+                    on_drift(&drifted)?
+                };
+                match resolution {
+                    DriftResolution::Pull => {
+                        tracing::info!("Pulling changes from {} into the egg", target.abbr());
+                        self.copy_file(target, source)?;
+                        self.record_copy(source, target, deployed_hash);
+                    }
+                    DriftResolution::Overwrite => {
+                        tracing::info!("Overwriting modified copy {}", target.abbr());
+                        self.copy_file(source, target)?;
+                        self.record_copy(source, target, egg_hash);
+                    }
+                    DriftResolution::Skip => {
+                        tracing::warn!(
+                            "{} was modified since it was copied from {}, leaving it as is",
+                            target.abbr(),
+                            source.abbr()
+                        );
+                        self.record_copy(source, target, previous_hash.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_copy(&mut self, source: &Path, target: &Path, hash: String) {
+        self.copied_files.push(ManifestEntry::copy(
+            target.to_path_buf(),
+            source.to_path_buf(),
+            hash,
+        ));
+    }
+
     /// Set up a symlink from the given `link_path` to the given `actual_path`, recursively.
     /// Also takes the `egg_root` dir, to ensure we can safely delete any stale symlinks on the way there.
     ///
     /// Requires all paths to be absolute, will panic otherwise.
     ///
     /// This means:
-    /// - If `link_path` exists and is a file, abort
+    /// - If `link_path` exists and is a file, abort, or move it into the backup directory if backups are enabled.
     /// - If `link_path` exists and is a symlink into the egg dir, remove the symlink and then continue.
     /// - If `actual_path` is a file, symlink.
     /// - If `actual_path` is a directory that does not exist in `link_path`, symlink it.
     /// - If `actual_path` is a directory that already exists in `link_path`, recurse into it and `symlink_recursive` `actual_path`s children.
+    ///
+    /// With `no_fold`, directories are always created and only the files within them are symlinked,
+    /// rather than symlinking directories that don't exist yet as a whole.
     #[tracing::instrument(skip_all, fields(
         egg_root = egg_root.as_ref().abbr(),
         actual_path = actual_path.as_ref().abbr(),
@@ -156,12 +715,14 @@ impl Deployer {
         egg_root: impl AsRef<Path>,
         actual_path: impl AsRef<Path>,
         link_path: &impl AsRef<Path>,
+        no_fold: bool,
     ) -> miette::Result<()> {
         fn inner(
             deployer: &mut Deployer,
             egg_root: PathBuf,
             actual_path: PathBuf,
             link_path: PathBuf,
+            no_fold: bool,
         ) -> miette::Result<()> {
             let actual_path = actual_path.normalize();
             let link_path = link_path.normalize();
@@ -174,14 +735,35 @@ impl Deployer {
                 actual_path.abbr(),
                 link_path.abbr()
             );
+            if deployer.is_ignored(&actual_path) {
+                cov_mark::hit!(skip_ignored_file);
+                tracing::debug!("Not deploying ignored file {}", actual_path.abbr());
+                return Ok(());
+            }
 
             let actual_path = actual_path.canonical()?;
 
             if link_path.is_symlink() {
-                let link_target = link_path.fs_err_read_link().into_diagnostic()?;
-                if link_target == actual_path {
-                    deployer.add_created_symlink(link_path);
+                let link_target = link_path.read_link_absolute()?;
+                if link_target == actual_path
+                    && actual_path.is_dir()
+                    && (no_fold || deployer.contains_ignored(&actual_path))
+                {
+                    cov_mark::hit!(unfold_symlinked_dir);
+                    tracing::info!(
+                        "Replacing symlink {} with a directory",
+                        format_symlink(link_path.abbr(), actual_path.abbr())
+                    );
+                    deployer.delete_symlink(&link_path)?;
+                    return unfold(deployer, &egg_root, &actual_path, &link_path, no_fold);
+                } else if link_target == actual_path {
+                    if !deployer.update_symlink_style(&link_path)? {
+                        deployer.add_created_symlink(actual_path, link_path);
+                    }
                     return Ok(());
+                } else if deployer.backs_up_conflicts() && !link_target.starts_with(&egg_root) {
+                    cov_mark::hit!(backup_conflicting_file);
+                    deployer.backup_file(&link_path)?;
                 } else if link_target.exists() {
                     miette::bail!(
                         "Failed to create symlink {}, as a file already exists there",
@@ -210,9 +792,13 @@ impl Deployer {
                             &egg_root,
                             entry.path(),
                             &link_path.join(entry.file_name()),
+                            no_fold,
                         )?;
                     }
                     return Ok(());
+                } else if deployer.backs_up_conflicts() {
+                    cov_mark::hit!(backup_conflicting_file);
+                    deployer.backup_file(&link_path)?;
                 } else if link_path.is_dir() || actual_path.is_dir() {
                     miette::bail!(
                         "Conflicting file or directory {} with {}",
@@ -221,18 +807,54 @@ impl Deployer {
                     );
                 }
             }
+            // When not folding, or when symlinking the whole directory would deploy ignored files within it,
+            // we create the directory and symlink its contents individually instead.
+            if actual_path.is_dir()
+                && !link_path.exists()
+                && (no_fold || deployer.contains_ignored(&actual_path))
+            {
+                return unfold(deployer, &egg_root, &actual_path, &link_path, no_fold);
+            }
             deployer.create_symlink(&actual_path, &link_path)?;
-            tracing::info!(
-                "created symlink {}",
-                format_symlink(link_path.abbr(), actual_path.abbr()),
-            );
+            if !deployer.dry_run {
+                tracing::info!(
+                    "created symlink {}",
+                    format_symlink(link_path.abbr(), actual_path.abbr()),
+                );
+            }
             Ok(())
         }
+
+        /// Create `link_path` as a directory, and deploy the contents of `actual_path` into it.
+        fn unfold(
+            deployer: &mut Deployer,
+            egg_root: &Path,
+            actual_path: &Path,
+            link_path: &Path,
+            no_fold: bool,
+        ) -> miette::Result<()> {
+            deployer.create_dir_all(link_path)?;
+            deployer
+                .created_dirs
+                .push((actual_path.to_path_buf(), link_path.to_path_buf()));
+            for entry in actual_path.fs_err_read_dir().into_diagnostic()? {
+                let entry = entry.into_diagnostic()?;
+                deployer.symlink_recursive(
+                    egg_root,
+                    entry.path(),
+                    &link_path.join(entry.file_name()),
+                    no_fold,
+                )?;
+            }
+            Ok(())
+        }
+
         inner(
             self,
             egg_root.as_ref().to_path_buf(),
             actual_path.as_ref().to_path_buf(),
             link_path.as_ref().to_path_buf(),
+            no_fold,
         )
     }
 
@@ -256,6 +878,9 @@ impl Deployer {
         } else if link_path.is_dir() && actual_path.is_dir() {
             for entry in actual_path.fs_err_read_dir().into_diagnostic()? {
                 let entry = entry.into_diagnostic()?;
+                if self.is_ignored(&entry.path()) {
+                    continue;
+                }
                 self.remove_symlink_recursive(entry.path(), &link_path.join(entry.file_name()))?;
             }
         } else if link_path.exists() {
@@ -275,50 +900,195 @@ impl Deployer {
             tracing::trace!("No privilege escalation necessary, all symlink operations succeeded");
             return Ok(());
         }
-        let yolk_binary = std::env::args().nth(0).unwrap_or("yolk".to_string());
-        let yolk_binary_path = if yolk_binary.starts_with('/') {
-            yolk_binary
-        } else {
-            which_global(yolk_binary)
-                .map(|x| x.to_string_lossy().to_string())
-                .unwrap_or_else(|_| "yolk".to_string())
-        };
-        let args = [yolk_binary_path, "root-manage-symlinks".to_string()]
-            .into_iter()
-            .chain(
-                self.missing_permissions_create
-                    .iter()
-                    .flat_map(|(original, symlink)| {
-                        [
-                            "--create-symlink".to_string(),
-                            format!("{}::::{}", original.display(), symlink.display()),
-                        ]
-                    }),
-            )
-            .chain(self.missing_permissions_remove.iter().flat_map(|symlink| {
-                [
-                    "--delete-symlink".to_string(),
-                    symlink.to_string_lossy().to_string(),
-                ]
-            }))
-            .collect::<Vec<_>>();
+        // Remember where the symlinks pointed to, so they can be restored on rollback.
+        let deletions = self
+            .missing_permissions_remove
+            .iter()
+            .map(|link| Ok((link.clone(), link.fs_err_read_link().into_diagnostic()?)))
+            .collect::<miette::Result<Vec<_>>>()?;
+        let previous_permissions = self
+            .missing_permissions_chmod
+            .iter()
+            .map(|(path, _)| Ok((path.clone(), FilePermissions::current(path)?)))
+            .collect::<miette::Result<Vec<_>>>()?;
         tracing::info!(
             "Some symlink operations require elevated permissions: {}",
             self.pending_elevated_operations_summary(),
         );
-        try_sudo(&args)?;
+        let creations = self
+            .missing_permissions_create
+            .iter()
+            .map(|(original, link)| (self.symlink_target(original, link), link.clone()))
+            .collect::<Vec<_>>();
+        run_elevated_helper(
+            &creations,
+            &self.missing_permissions_remove,
+            &self.missing_permissions_chmod,
+        )?;
+        for (original, link) in self.missing_permissions_create.drain(..) {
+            self.journal.push(JournalEntry::CreatedSymlink {
+                link: link.clone(),
+                elevated: true,
+            });
+            self.elevated_symlinks.push((original, link));
+        }
+        self.missing_permissions_remove.clear();
+        for (link, original) in deletions {
+            self.journal.push(JournalEntry::DeletedSymlink {
+                link,
+                original,
+                elevated: true,
+            });
+        }
+        self.missing_permissions_chmod.clear();
+        for (path, previous) in previous_permissions {
+            self.journal.push(JournalEntry::ChangedPermissions {
+                path,
+                previous,
+                elevated: true,
+            });
+        }
+        Ok(())
+    }
+
+    /// Undo every change this deployer made to the filesystem, in reverse order,
+    /// and drop any elevated operations that are still pending.
+    ///
+    /// Symlinks that were created or deleted via the elevated helper are restored via the elevated helper as well.
+    pub fn rollback(&mut self) -> miette::Result<()> {
         self.missing_permissions_create.clear();
         self.missing_permissions_remove.clear();
+        self.missing_permissions_chmod.clear();
+        self.created_symlinks.clear();
+        self.elevated_symlinks.clear();
+        self.copied_files.clear();
+        self.created_dirs.clear();
+        self.backed_up_files.clear();
+        if self.journal.is_empty() {
+            return Ok(());
+        }
+        cov_mark::hit!(rollback_deployment);
+        tracing::warn!("Rolling back {} filesystem changes", self.journal.len());
+        let journal = std::mem::take(&mut self.journal);
+        // Elevated operations always run last, so they are the first ones to be undone.
+        let (elevated, regular): (Vec<_>, Vec<_>) = journal.into_iter().partition(|entry| {
+            matches!(
+                entry,
+                JournalEntry::CreatedSymlink { elevated: true, .. }
+                    | JournalEntry::DeletedSymlink { elevated: true, .. }
+                    | JournalEntry::ChangedPermissions { elevated: true, .. }
+            )
+        });
+
+        let mut errs = Vec::new();
+        let mut recreate = Vec::new();
+        let mut delete = Vec::new();
+        let mut restore_permissions = Vec::new();
+        for entry in elevated.into_iter().rev() {
+            match entry {
+                JournalEntry::CreatedSymlink { link, .. } => delete.push(link),
+                JournalEntry::DeletedSymlink { link, original, .. } => {
+                    recreate.push((original, link))
+                }
+                JournalEntry::ChangedPermissions { path, previous, .. } => {
+                    restore_permissions.push((path, previous))
+                }
+                _ => unreachable!(),
+            }
+        }
+        if !recreate.is_empty() || !delete.is_empty() || !restore_permissions.is_empty() {
+            if let Err(e) = run_elevated_helper(&recreate, &delete, &restore_permissions) {
+                errs.push(e);
+            }
+        }
+
+        let mut restored_backup = false;
+        for entry in regular.into_iter().rev() {
+            let result = match entry {
+                JournalEntry::CreatedSymlink { link, .. } => remove_symlink(&link),
+                JournalEntry::DeletedSymlink { link, original, .. } => {
+                    create_symlink(&original, &link)
+                }
+                JournalEntry::CreatedDir(dir) => {
+                    // Only remove directories that are empty again, anything else is not ours to delete.
+                    if let Err(e) = fs_err::remove_dir(&dir) {
+                        tracing::debug!("Not removing directory {}: {e}", dir.abbr());
+                    }
+                    Ok(())
+                }
+                JournalEntry::RemovedDir { path, permissions } => fs_err::create_dir_all(&path)
+                    .and_then(|()| permissions.apply(&path))
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to restore directory {}", path.abbr())),
+                JournalEntry::BackedUp { original, backup } => {
+                    restored_backup = true;
+                    util::rename_safely(&backup, &original).wrap_err_with(|| {
+                        format!("Failed to restore backup of {}", original.abbr())
+                    })
+                }
+                JournalEntry::WroteFile { path, previous } => match previous {
+                    Some(content) => fs_err::write(&path, content).into_diagnostic(),
+                    None => fs_err::remove_file(&path).into_diagnostic(),
+                },
+                JournalEntry::DeletedFile { path, content } => {
+                    fs_err::write(&path, content).into_diagnostic()
+                }
+                JournalEntry::ChangedPermissions { path, previous, .. } => previous
+                    .apply(&path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to restore permissions of {}", path.abbr())),
+            };
+            if let Err(e) = result {
+                errs.push(e);
+            }
+        }
+        if restored_backup {
+            if let Some(backup_dir) = &self.backup_dir {
+                if let Err(e) = backup::remove_if_empty(backup_dir) {
+                    errs.push(e);
+                }
+            }
+        }
+        if !errs.is_empty() {
+            return Err(MultiError::new("Failed to roll back some changes", errs).into());
+        }
         Ok(())
     }
 }
 
+/// List all files that deploying `in_egg` to `deployed` via copying would produce, as (source, target) pairs.
+///
+/// Files matched by `ignore` are left out.
+pub fn expand_copy_targets(
+    in_egg: impl AsRef<Path>,
+    deployed: impl AsRef<Path>,
+    ignore: Option<&EggIgnore>,
+) -> miette::Result<Vec<(PathBuf, PathBuf)>> {
+    let in_egg = in_egg.as_ref();
+    let deployed = deployed.as_ref();
+    if !in_egg.is_dir() {
+        return Ok(vec![(in_egg.to_path_buf(), deployed.to_path_buf())]);
+    }
+    util::file_entries_recursive(in_egg)
+        .filter(|source| match (source, ignore) {
+            (Ok(source), Some(ignore)) => !ignore.is_ignored(source),
+            _ => true,
+        })
+        .map(|source| {
+            let source = source?;
+            let relative = source.strip_prefix(in_egg).into_diagnostic()?;
+            let target = deployed.join(relative);
+            Ok((source, target))
+        })
+        .collect()
+}
+
 /// Create a symlink at `link` pointing to `original`.
 pub fn create_symlink(original: impl AsRef<Path>, link: impl AsRef<Path>) -> miette::Result<()> {
     let link = link.as_ref();
     let original = original.as_ref();
     tracing::trace!("Creating symlink at {} -> {}", link.abbr(), original.abbr());
-    symlink::symlink_auto(original, link)
+    symlink_to(original, link)
         .into_diagnostic()
         .wrap_err_with(|| {
             format!(
@@ -329,6 +1099,19 @@ pub fn create_symlink(original: impl AsRef<Path>, link: impl AsRef<Path>) -> mie
     Ok(())
 }
 
+/// Create a symlink at `link` with the given `target`, which may be relative to the directory containing `link`.
+fn symlink_to(target: &Path, link: &Path) -> std::io::Result<()> {
+    let resolved = match link.parent() {
+        Some(parent) => parent.join(target),
+        None => target.to_path_buf(),
+    };
+    if resolved.is_dir() {
+        symlink::symlink_dir(target, link)
+    } else {
+        symlink::symlink_file(target, link)
+    }
+}
+
 /// Delete a symlink at `path`, but only if it actually is a symlink.
 pub fn remove_symlink(path: impl AsRef<Path>) -> miette::Result<()> {
     let path = path.as_ref();
@@ -353,6 +1136,57 @@ pub fn remove_symlink(path: impl AsRef<Path>) -> miette::Result<()> {
     Ok(())
 }
 
+/// Run `yolk root-manage-symlinks` with elevated privileges to create and delete the given symlinks,
+/// and to apply the given permissions.
+fn run_elevated_helper(
+    create: &[(PathBuf, PathBuf)],
+    delete: &[PathBuf],
+    permissions: &[(PathBuf, FilePermissions)],
+) -> miette::Result<()> {
+    let yolk_binary = std::env::args().next().unwrap_or("yolk".to_string());
+    let yolk_binary_path = if yolk_binary.starts_with('/') {
+        yolk_binary
+    } else {
+        which_global(yolk_binary)
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "yolk".to_string())
+    };
+    let args = [yolk_binary_path, "root-manage-symlinks".to_string()]
+        .into_iter()
+        .chain(create.iter().flat_map(|(original, symlink)| {
+            [
+                "--create-symlink".to_string(),
+                format!("{}::::{}", original.display(), symlink.display()),
+            ]
+        }))
+        .chain(delete.iter().flat_map(|symlink| {
+            [
+                "--delete-symlink".to_string(),
+                symlink.to_string_lossy().to_string(),
+            ]
+        }))
+        .chain(permissions.iter().flat_map(|(path, permissions)| {
+            [
+                "--set-permissions".to_string(),
+                format!(
+                    "{}::::{}::::{}",
+                    path.display(),
+                    permissions
+                        .mode
+                        .map(|mode| format!("{mode:o}"))
+                        .unwrap_or_default(),
+                    permissions
+                        .owner
+                        .as_ref()
+                        .map(|owner| owner.to_string())
+                        .unwrap_or_default()
+                ),
+            ]
+        }))
+        .collect::<Vec<_>>();
+    try_sudo(&args)
+}
+
 fn try_sudo(args: &[String]) -> miette::Result<()> {
     let sudo_command = which_global("sudo")
         .or_else(|_| which_global("doas"))
@@ -0,0 +1,378 @@
+//! Health checks for the yolk directory and its deployments, as run by `yolk doctor`.
+use std::{collections::BTreeSet, path::PathBuf};
+
+use fs_err::PathExt as _;
+use miette::{IntoDiagnostic as _, Result};
+
+use crate::{
+    deploy, eggs_config,
+    templating::document::Document,
+    util::{self, PathExt as _},
+    yolk::{Yolk, GITIGNORE_ENTRIES},
+    yolk_paths::Egg,
+};
+
+/// A problem found by [`diagnose`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// What is wrong.
+    pub problem: String,
+    /// How to resolve the problem.
+    pub suggestion: String,
+    /// A fix that is safe to apply automatically, if there is one.
+    pub fix: Option<Fix>,
+}
+
+impl Finding {
+    fn new(problem: impl Into<String>, suggestion: impl Into<String>) -> Self {
+        Self {
+            problem: problem.into(),
+            suggestion: suggestion.into(),
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+}
+
+/// A fix for a [`Finding`] that can be applied without risking any data loss.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    /// Remove a symlink into the yolk directory whose target no longer exists.
+    RemoveDanglingSymlink(PathBuf),
+    /// Remove an entry from the deployment manifest whose deployed path no longer exists.
+    RemoveManifestEntry { egg_name: String, path: PathBuf },
+    /// Rename `.git` to `.yolk_git`.
+    ///
+    /// This changes how every other git tool sees the repository, so it is only applied after confirmation.
+    SafeguardGit,
+    /// Add the given lines to the `.gitignore` of the yolk directory.
+    AddGitignoreEntries(Vec<String>),
+}
+
+impl Fix {
+    /// Whether the user has to confirm this fix before it is applied by `yolk doctor --fix`.
+    pub fn requires_confirmation(&self) -> bool {
+        matches!(self, Fix::SafeguardGit)
+    }
+}
+
+/// Check the yolk directory, its configuration and the deployed eggs for problems.
+pub fn diagnose(yolk: &Yolk) -> Result<Vec<Finding>> {
+    let paths = yolk.paths();
+    if let Err(err) = paths.check() {
+        return Ok(vec![Finding::new(
+            error_message(&err),
+            "Run `yolk init` to set up the yolk directory",
+        )]);
+    }
+    let mut findings = Vec::new();
+
+    match paths.active_yolk_git_dir() {
+        Ok(git_dir) if git_dir == paths.yolk_default_git_path() => findings.push(
+            Finding::new(
+                "Yolk git is not safeguarded",
+                "Run `yolk safeguard` to rename .git to .yolk_git",
+            )
+            .with_fix(Fix::SafeguardGit),
+        ),
+        Ok(_) => {}
+        Err(err) => findings.push(Finding::new(
+            error_message(&err),
+            "Run `yolk init` to initialize the git repository",
+        )),
+    }
+
+    let gitignore_path = paths.root_path().join(".gitignore");
+    let gitignore = if gitignore_path.exists() {
+        fs_err::read_to_string(&gitignore_path).into_diagnostic()?
+    } else {
+        String::new()
+    };
+    let missing_entries = GITIGNORE_ENTRIES
+        .iter()
+        .filter(|entry| !gitignore.lines().any(|line| line == **entry))
+        .map(|entry| entry.to_string())
+        .collect::<Vec<_>>();
+    if !missing_entries.is_empty() {
+        findings.push(
+            Finding::new(
+                format!(".gitignore is missing {}", missing_entries.join(", ")),
+                "Add the missing entries to the .gitignore in your yolk directory",
+            )
+            .with_fix(Fix::AddGitignoreEntries(missing_entries)),
+        );
+    }
+
+    if let Err(err) = yolk.validate_config_invariants() {
+        findings.push(Finding::new(
+            error_message(&err),
+            "Make sure the eggs in your yolk.rhai match the directories in your eggs directory",
+        ));
+    }
+
+    match yolk.list_eggs() {
+        Ok(mut eggs) => {
+            eggs.sort_by(|a, b| a.name().cmp(b.name()));
+            let configs = eggs
+                .iter()
+                .map(|egg| (egg.name().to_string(), egg.config().clone()))
+                .collect();
+            for err in eggs_config::unknown_dependency_errors(&configs) {
+                findings.push(Finding::new(
+                    error_message(&err),
+                    "Remove the dependency from `depends_on`, or fix it to name an existing egg",
+                ));
+            }
+            for egg in &eggs {
+                check_templates(egg, &mut findings)?;
+                check_partial_deployment(egg, &mut findings)?;
+            }
+            check_dangling_symlinks(yolk, &eggs, &mut findings)?;
+        }
+        Err(err) => findings.push(Finding::new(
+            error_message(&err),
+            "Fix the errors in your yolk.rhai",
+        )),
+    }
+
+    check_manifest(yolk, &mut findings);
+    Ok(findings)
+}
+
+/// Apply a fix suggested by [`diagnose`].
+pub fn apply_fix(yolk: &Yolk, fix: &Fix) -> Result<()> {
+    let paths = yolk.paths();
+    match fix {
+        Fix::RemoveDanglingSymlink(path) => {
+            deploy::remove_symlink(path)?;
+            let mut manifest = paths.load_deployment_manifest()?;
+            if manifest.remove_path(path) {
+                paths.save_deployment_manifest(&manifest)?;
+            }
+        }
+        Fix::RemoveManifestEntry { egg_name, path } => {
+            let mut manifest = paths.load_deployment_manifest()?;
+            manifest.remove_entry(egg_name, path);
+            paths.save_deployment_manifest(&manifest)?;
+        }
+        Fix::SafeguardGit => paths.safeguard_git_dir()?,
+        Fix::AddGitignoreEntries(entries) => {
+            let entries = entries.iter().map(String::as_str).collect::<Vec<_>>();
+            util::ensure_file_contains_lines(paths.root_path().join(".gitignore"), &entries)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_templates(egg: &Egg, findings: &mut Vec<Finding>) -> Result<()> {
+    for pattern in &egg.config().templates {
+        if eggs_config::is_template_exclusion(pattern) {
+            continue;
+        }
+        let pattern = egg.path().join(pattern);
+        let matches = glob::glob(&pattern.to_string_lossy())
+            .into_diagnostic()?
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            findings.push(Finding::new(
+                format!(
+                    "Template pattern {} of egg {} does not match any files",
+                    pattern.abbr(),
+                    egg.name()
+                ),
+                "Remove the pattern from the templates list, or fix it to point to an existing file",
+            ));
+        }
+    }
+    let templates = egg.templates_globexpanded()?;
+    for template in &templates {
+        if !template.is_file() {
+            continue;
+        }
+        let content = fs_err::read_to_string(template).into_diagnostic()?;
+        let comment_style = egg.config().comment_style_for(egg.path(), template);
+        if let Err(err) = Document::parse_string_named(
+            &template.to_string_lossy(),
+            &content,
+            comment_style.cloned(),
+        ) {
+            findings.push(Finding::new(
+                format!(
+                    "Template {} of egg {} failed to parse: {}",
+                    template.abbr(),
+                    egg.name(),
+                    error_message(&err)
+                ),
+                format!(
+                    "Fix the template tags in the file, see `yolk eval-template {}` for details",
+                    template.display()
+                ),
+            ));
+        }
+    }
+    for path in
+        egg.config()
+            .untemplated_files_with_tags(egg.path(), &templates, egg.ignore_matcher()?)?
+    {
+        findings.push(Finding::new(
+            format!(
+                "{} contains yolk tags, but is not a template of egg {}",
+                path.abbr(),
+                egg.name()
+            ),
+            "Add the file to the `templates` of the egg, or exclude it with a `!` pattern",
+        ));
+    }
+    Ok(())
+}
+
+fn check_partial_deployment(egg: &Egg, findings: &mut Vec<Finding>) -> Result<()> {
+    if !egg.config().enabled || egg.enabled_targets()?.is_empty() {
+        return Ok(());
+    }
+    let mut deployed = 0;
+    let mut missing = Vec::new();
+    for (in_egg, target) in egg.copy_targets()? {
+        if target.is_file() && !target.is_symlink() {
+            deployed += 1;
+        } else {
+            missing.push(in_egg);
+        }
+    }
+    for entry in egg.find_deployed_symlinks()? {
+        match entry? {
+            Ok(_) => deployed += 1,
+            Err(in_egg) => missing.push(in_egg),
+        }
+    }
+    if deployed > 0 && !missing.is_empty() {
+        cov_mark::hit!(doctor_partial_deployment);
+        findings.push(Finding::new(
+            format!(
+                "Egg {} is only partially deployed, {} is not deployed",
+                egg.name(),
+                missing
+                    .iter()
+                    .map(|x| x.abbr())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            "Run `yolk sync` to deploy the rest of the egg, and resolve any conflicts it reports",
+        ));
+    }
+    Ok(())
+}
+
+/// Look for symlinks into the yolk directory whose target no longer exists.
+///
+/// This checks every path recorded in the deployment manifest,
+/// as well as every directory an egg is deployed into, as files that were removed from an egg leave a dangling symlink there.
+fn check_dangling_symlinks(yolk: &Yolk, eggs: &[Egg], findings: &mut Vec<Finding>) -> Result<()> {
+    let paths = yolk.paths();
+    let mut candidates = BTreeSet::new();
+    if let Ok(manifest) = paths.load_deployment_manifest() {
+        for entries in manifest.eggs.values() {
+            candidates.extend(entries.iter().map(|entry| entry.path.clone()));
+        }
+    }
+    for egg in eggs {
+        let targets = egg
+            .config()
+            .targets_expanded(paths.home_path(), egg.path())?;
+        for (in_egg, deployed) in targets {
+            candidates.insert(deployed.clone());
+            if !in_egg.is_dir() {
+                continue;
+            }
+            for dir in walkdir::WalkDir::new(&in_egg)
+                .into_iter()
+                .filter_map(|x| x.ok())
+                .filter(|x| x.file_type().is_dir())
+            {
+                let relative = dir.path().strip_prefix(&in_egg).into_diagnostic()?;
+                let deployed_dir = deployed.join(relative);
+                if deployed_dir.is_symlink() || !deployed_dir.is_dir() {
+                    continue;
+                }
+                for entry in deployed_dir.fs_err_read_dir().into_diagnostic()? {
+                    candidates.insert(entry.into_diagnostic()?.path());
+                }
+            }
+        }
+    }
+
+    let yolk_dir = paths.root_path().to_path_buf();
+    let canonical_yolk_dir = yolk_dir.canonical().unwrap_or_else(|_| yolk_dir.clone());
+    for path in candidates {
+        if !path.is_symlink() || path.exists() {
+            continue;
+        }
+        let Ok(target) = path.read_link_absolute() else {
+            continue;
+        };
+        if target.starts_with(&yolk_dir) || target.starts_with(&canonical_yolk_dir) {
+            cov_mark::hit!(doctor_dangling_symlink);
+            findings.push(
+                Finding::new(
+                    format!(
+                        "Dangling symlink {} points to {}, which no longer exists",
+                        path.abbr(),
+                        target.abbr()
+                    ),
+                    "Remove the symlink",
+                )
+                .with_fix(Fix::RemoveDanglingSymlink(path)),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_manifest(yolk: &Yolk, findings: &mut Vec<Finding>) {
+    let manifest = match yolk.paths().load_deployment_manifest() {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            findings.push(Finding::new(
+                error_message(&err),
+                format!(
+                    "Remove {} and run `yolk sync` to recreate it",
+                    yolk.paths().deployment_manifest_path().abbr()
+                ),
+            ));
+            return;
+        }
+    };
+    for (egg_name, entries) in &manifest.eggs {
+        for entry in entries {
+            if entry.path.exists() || entry.path.is_symlink() {
+                continue;
+            }
+            findings.push(
+                Finding::new(
+                    format!(
+                        "Deployment manifest lists {} for egg {}, but it no longer exists",
+                        entry.path.abbr(),
+                        egg_name
+                    ),
+                    "Remove the entry from the deployment manifest, or run `yolk sync` to deploy it again",
+                )
+                .with_fix(Fix::RemoveManifestEntry {
+                    egg_name: egg_name.clone(),
+                    path: entry.path.clone(),
+                }),
+            );
+        }
+    }
+}
+
+/// Render an error and all of its causes on a single line.
+fn error_message(err: &miette::Report) -> String {
+    err.chain()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
@@ -1,15 +1,23 @@
 use normalize_path::NormalizePath;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, ExitStatus, Stdio},
     str::FromStr,
+    time::{Duration, Instant},
 };
 
-use miette::IntoDiagnostic as _;
-use rhai::Dynamic;
+use miette::{Context as _, IntoDiagnostic as _};
+use rhai::{Dynamic, FnPtr};
 
-use crate::{script::rhai_error::RhaiScriptError, util::PathExt as _};
+use crate::{
+    permissions::{self, FilePermissions, Owner},
+    script::rhai_error::RhaiScriptError,
+    templating::{comment_style::CommentStyle, document::Document},
+    util::PathExt as _,
+    yolk::{EvalMode, SyncSummary},
+    yolk_paths::EggIgnore,
+};
 
 macro_rules! rhai_error {
     ($($tt:tt)*) => {
@@ -17,8 +25,12 @@ macro_rules! rhai_error {
     };
 }
 
+/// How long a shell hook may run before it is killed, unless the egg sets a `hook_timeout`.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(300);
+
 /// How the contents of an egg should be deployed.
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DeploymentStrategy {
     /// Recursively traverse the directory structure until a directory / file doesn't exist yet, then symlink there.
     /// This allows stow-like behavior.
@@ -26,6 +38,9 @@ pub enum DeploymentStrategy {
     /// Simply deploy to the given target, or fail.
     #[default]
     Put,
+    /// Copy the files to the given target instead of symlinking them.
+    /// Yolk remembers a hash of every copied file, to detect when the deployed copy has been modified.
+    Copy,
 }
 
 impl FromStr for DeploymentStrategy {
@@ -35,8 +50,9 @@ impl FromStr for DeploymentStrategy {
         match s {
             "merge" => Ok(DeploymentStrategy::Merge),
             "put" => Ok(DeploymentStrategy::Put),
+            "copy" => Ok(DeploymentStrategy::Copy),
             _ => miette::bail!(
-                help = "strategy must be one of 'merge' or 'put'",
+                help = "strategy must be one of 'merge', 'put' or 'copy'",
                 "Invalid deployment strategy {}",
                 s
             ),
@@ -44,81 +60,691 @@ impl FromStr for DeploymentStrategy {
     }
 }
 
+/// What to do when deploying an egg finds a file that is in the way.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ConflictPolicy {
+    /// Refuse to deploy over the existing file.
+    #[default]
+    Fail,
+    /// Move the existing file into a timestamped backup directory inside the yolk dir, then deploy.
+    Backup,
+}
+
+/// What to do when a hook that runs after a change (`post_deploy`, `post_undeploy` or `post_template_change`) fails.
+///
+/// Failing `pre_` hooks always fail the egg, as they run before anything was changed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum HookFailurePolicy {
+    /// Report the egg as failed.
+    #[default]
+    Fail,
+    /// Only print a warning.
+    Warn,
+}
+
+impl FromStr for HookFailurePolicy {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(HookFailurePolicy::Fail),
+            "warn" => Ok(HookFailurePolicy::Warn),
+            _ => miette::bail!(
+                help = "on_hook_failure must be one of 'fail' or 'warn'",
+                "Invalid hook failure policy {}",
+                s
+            ),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "backup" => Ok(ConflictPolicy::Backup),
+            _ => miette::bail!(
+                help = "on_conflict must be one of 'fail' or 'backup'",
+                "Invalid conflict policy {}",
+                s
+            ),
+        }
+    }
+}
+
+/// A command run by a hook, either as a shell script or as a list of arguments that is run without a shell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookCommand {
+    /// A script that is run via `sh -c`.
+    Shell(String),
+    /// A program and its arguments, run directly.
+    Argv(Vec<String>),
+}
+
+impl HookCommand {
+    fn from_dynamic(value: &Dynamic) -> Result<Self, RhaiScriptError> {
+        if let Ok(script) = value.as_immutable_string_ref() {
+            return Ok(HookCommand::Shell(script.to_string()));
+        }
+        let Ok(args) = value.as_array_ref() else {
+            return Err(rhai_error!(
+                "hook must be a string or a list of arguments, but got {}",
+                value.type_name()
+            ));
+        };
+        let args = args
+            .iter()
+            .map(|x| {
+                x.clone()
+                    .into_string()
+                    .map_err(|e| rhai_error!("hook argument must be a string, but got {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if args.is_empty() {
+            return Err(rhai_error!("hook argument list must not be empty"));
+        }
+        Ok(HookCommand::Argv(args))
+    }
+
+    fn command(&self) -> Command {
+        match self {
+            HookCommand::Shell(script) => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(script);
+                command
+            }
+            HookCommand::Argv(args) => {
+                let mut command = Command::new(&args[0]);
+                command.args(&args[1..]);
+                command
+            }
+        }
+    }
+}
+
+impl From<String> for HookCommand {
+    fn from(script: String) -> Self {
+        HookCommand::Shell(script)
+    }
+}
+
+impl From<&str> for HookCommand {
+    fn from(script: &str) -> Self {
+        HookCommand::Shell(script.to_string())
+    }
+}
+
+impl std::fmt::Display for HookCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookCommand::Shell(script) => write!(f, "{script}"),
+            HookCommand::Argv(args) => write!(f, "{args:?}"),
+        }
+    }
+}
+
+/// Rhai functions or closures to run when an egg is deployed or undeployed, or its templates change.
+///
+/// These are called with the variables and functions of the yolk.rhai file they were declared in,
+/// see [`crate::script::eval_ctx::EvalCtx::call_hook`].
+#[derive(Debug, Clone, Default)]
+pub struct RhaiHooks {
+    pub post_deploy: Option<FnPtr>,
+    pub post_undeploy: Option<FnPtr>,
+    pub pre_deploy: Option<FnPtr>,
+    pub pre_undeploy: Option<FnPtr>,
+    pub post_template_change: Option<FnPtr>,
+}
+
+impl RhaiHooks {
+    /// Get the function configured for the given hook, if any.
+    pub fn get(&self, key: ShellHookKey) -> Option<&FnPtr> {
+        match key {
+            ShellHookKey::PostDeploy => self.post_deploy.as_ref(),
+            ShellHookKey::PostUndeploy => self.post_undeploy.as_ref(),
+            ShellHookKey::PreDeploy => self.pre_deploy.as_ref(),
+            ShellHookKey::PreUndeploy => self.pre_undeploy.as_ref(),
+            ShellHookKey::PostTemplateChange => self.post_template_change.as_ref(),
+        }
+    }
+
+    fn from_dynamic(value: &Dynamic) -> Result<Self, RhaiScriptError> {
+        let hooks = value
+            .as_map_ref()
+            .map_err(|t| rhai_error!("`hooks` must be a map, but got {t}"))?;
+        for (k, _v) in hooks.iter() {
+            let k: &str = k;
+            if ShellHookKey::from_str(k).is_none() {
+                tracing::warn!("unknown hook: {}", k);
+            }
+        }
+        let hook = |key: ShellHookKey| {
+            hooks
+                .get(key.name())
+                .map(|value| {
+                    value.clone().try_cast::<FnPtr>().ok_or_else(|| {
+                        rhai_error!(
+                            "hook `{}` must be a function, but got {}",
+                            key.name(),
+                            value.type_name()
+                        )
+                    })
+                })
+                .transpose()
+        };
+        Ok(RhaiHooks {
+            post_deploy: hook(ShellHookKey::PostDeploy)?,
+            post_undeploy: hook(ShellHookKey::PostUndeploy)?,
+            pre_deploy: hook(ShellHookKey::PreDeploy)?,
+            pre_undeploy: hook(ShellHookKey::PreUndeploy)?,
+            post_template_change: hook(ShellHookKey::PostTemplateChange)?,
+        })
+    }
+}
+
+// Function pointers can't be compared, so hooks are considered equal if they refer to the same function.
+impl PartialEq for RhaiHooks {
+    fn eq(&self, other: &Self) -> bool {
+        ShellHookKey::ALL
+            .into_iter()
+            .all(|key| self.get(key).map(FnPtr::fn_name) == other.get(key).map(FnPtr::fn_name))
+    }
+}
+
+impl Eq for RhaiHooks {}
+
+/// Information about an egg and the current sync, passed to hooks as environment variables.
+#[derive(Debug, Clone)]
+pub struct HookContext {
+    pub egg_name: String,
+    pub egg_path: PathBuf,
+    /// The paths the egg is deployed to.
+    pub targets: Vec<PathBuf>,
+    pub mode: EvalMode,
+    pub yolk_dir: PathBuf,
+    /// The `data` variable of the yolk.rhai file.
+    pub data: serde_json::Value,
+    /// The templates that were changed, for the `post_template_change` hook.
+    pub changed_files: Vec<PathBuf>,
+    /// How long the hook may run before it is killed.
+    pub timeout: Duration,
+}
+
+impl HookContext {
+    /// Run the hook inside the egg directory, with the context exposed as environment variables.
+    fn apply(&self, command: &mut Command) {
+        let join_paths = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|x| x.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        command
+            .current_dir(&self.egg_path)
+            .env("YOLK_EGG_NAME", &self.egg_name)
+            .env("YOLK_EGG_PATH", &self.egg_path)
+            .env("YOLK_EGG_TARGETS", join_paths(&self.targets))
+            .env("YOLK_EVAL_MODE", eval_mode_name(self.mode))
+            .env("YOLK_DIR", &self.yolk_dir)
+            .env("YOLK_DATA", self.data.to_string())
+            .env("YOLK_CHANGED_FILES", join_paths(&self.changed_files));
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct ShellHooks {
-    pub post_deploy: Option<String>,
-    pub post_undeploy: Option<String>,
-    pub pre_deploy: Option<String>,
-    pub pre_undeploy: Option<String>,
-    // pub post_sync: Option<String>,
+    pub post_deploy: Option<HookCommand>,
+    pub post_undeploy: Option<HookCommand>,
+    pub pre_deploy: Option<HookCommand>,
+    pub pre_undeploy: Option<HookCommand>,
+    pub post_template_change: Option<HookCommand>,
 }
 
 impl ShellHooks {
-    pub fn run_post_deploy(&self) -> miette::Result<()> {
-        if let Some(command) = &self.post_deploy {
-            tracing::debug!("Running post-deploy script");
-            run_hook(command)?;
+    /// Get the command configured for the given hook, if any.
+    pub fn get(&self, key: ShellHookKey) -> Option<&HookCommand> {
+        match key {
+            ShellHookKey::PostDeploy => self.post_deploy.as_ref(),
+            ShellHookKey::PostUndeploy => self.post_undeploy.as_ref(),
+            ShellHookKey::PreDeploy => self.pre_deploy.as_ref(),
+            ShellHookKey::PreUndeploy => self.pre_undeploy.as_ref(),
+            ShellHookKey::PostTemplateChange => self.post_template_change.as_ref(),
         }
-        Ok(())
     }
 
-    pub fn run_post_undeploy(&self) -> miette::Result<()> {
-        if let Some(command) = &self.post_undeploy {
-            tracing::debug!("Running post-undeploy script");
-            run_hook(command)?;
+    /// Run the given hook in the given context, if it is configured.
+    pub fn run(&self, key: ShellHookKey, context: &HookContext) -> Result<(), HookError> {
+        if let Some(command) = self.get(key) {
+            tracing::debug!("Running {} script", key.name());
+            run_hook(
+                key.into(),
+                format!("egg {}", context.egg_name),
+                command,
+                context.timeout,
+                |cmd| context.apply(cmd),
+            )?;
         }
         Ok(())
     }
-    pub fn run_pre_deploy(&self) -> miette::Result<()> {
-        if let Some(command) = &self.pre_deploy {
-            tracing::debug!("Running pre-deploy script");
-            run_hook(command)?;
+}
+
+/// A hook declared at the top level of the yolk.rhai file.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum GlobalHookKey {
+    PreSync,
+    PostSync,
+    PreGit,
+    PostGit,
+}
+
+impl GlobalHookKey {
+    pub const ALL: [GlobalHookKey; 4] = [
+        GlobalHookKey::PreSync,
+        GlobalHookKey::PostSync,
+        GlobalHookKey::PreGit,
+        GlobalHookKey::PostGit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GlobalHookKey::PreSync => "pre_sync",
+            GlobalHookKey::PostSync => "post_sync",
+            GlobalHookKey::PreGit => "pre_git",
+            GlobalHookKey::PostGit => "post_git",
         }
-        Ok(())
     }
-    pub fn run_pre_undeploy(&self) -> miette::Result<()> {
-        if let Some(command) = &self.pre_undeploy {
-            tracing::debug!("Running pre-undeploy script");
-            run_hook(command)?;
+
+    fn from_str(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.name() == s)
+    }
+}
+
+/// Any hook yolk runs, either of an egg or of the yolk.rhai file.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum HookKey {
+    Egg(ShellHookKey),
+    Global(GlobalHookKey),
+}
+
+impl HookKey {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookKey::Egg(key) => key.name(),
+            HookKey::Global(key) => key.name(),
         }
-        Ok(())
     }
+}
 
-    // pub fn run_post_sync(&self) -> miette::Result<()> {
-    //     if let Some(command) = &self.post_sync {
-    //         tracing::debug!("Running post-sync script");
-    //         run_hook(command)?;
-    //     }
-    //     Ok(())
-    // }
+impl From<ShellHookKey> for HookKey {
+    fn from(key: ShellHookKey) -> Self {
+        HookKey::Egg(key)
+    }
 }
 
-fn run_hook(command: &str) -> miette::Result<()> {
-    let status = Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .status()
-        .map_err(|e| miette::miette!("Failed to run post-deploy hook: {}", e))?;
+impl From<GlobalHookKey> for HookKey {
+    fn from(key: GlobalHookKey) -> Self {
+        HookKey::Global(key)
+    }
+}
 
-    if !status.success() {
-        miette::bail!(
-            "Post-deploy hook failed with status {}",
-            status.code().unwrap_or(-1)
-        );
+impl std::fmt::Display for HookKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Hooks declared in the `hooks` and `unsafe_shell_hooks` variables of the yolk.rhai file,
+/// which run once per `yolk sync` or `yolk git`.
+#[derive(Debug, Clone, Default)]
+pub struct GlobalHooks {
+    pub hooks: HashMap<GlobalHookKey, FnPtr>,
+    pub unsafe_shell_hooks: HashMap<GlobalHookKey, HookCommand>,
+}
+
+impl GlobalHooks {
+    pub fn from_dynamic(
+        hooks: Option<&Dynamic>,
+        unsafe_shell_hooks: Option<&Dynamic>,
+    ) -> Result<Self, RhaiScriptError> {
+        fn parse<T>(
+            name: &str,
+            value: Option<&Dynamic>,
+            parse_hook: impl Fn(GlobalHookKey, &Dynamic) -> Result<T, RhaiScriptError>,
+        ) -> Result<HashMap<GlobalHookKey, T>, RhaiScriptError> {
+            let Some(value) = value else {
+                return Ok(HashMap::new());
+            };
+            let map = value
+                .as_map_ref()
+                .map_err(|t| rhai_error!("`{name}` must be a map, but got {t}"))?;
+            let mut result = HashMap::new();
+            for (k, v) in map.iter() {
+                match GlobalHookKey::from_str(k) {
+                    Some(key) => {
+                        result.insert(key, parse_hook(key, v)?);
+                    }
+                    None => tracing::warn!("unknown hook: {}", k),
+                }
+            }
+            Ok(result)
+        }
+        Ok(Self {
+            hooks: parse("hooks", hooks, |key, value| {
+                value.clone().try_cast::<FnPtr>().ok_or_else(|| {
+                    rhai_error!(
+                        "hook `{}` must be a function, but got {}",
+                        key.name(),
+                        value.type_name()
+                    )
+                })
+            })?,
+            unsafe_shell_hooks: parse("unsafe_shell_hooks", unsafe_shell_hooks, |_, value| {
+                HookCommand::from_dynamic(value)
+            })?,
+        })
+    }
+}
+
+/// Information about the current sync, passed to hooks declared in the yolk.rhai file as environment variables.
+#[derive(Debug, Clone)]
+pub struct GlobalHookContext<'a> {
+    pub mode: EvalMode,
+    pub yolk_dir: PathBuf,
+    /// The `data` variable of the yolk.rhai file.
+    pub data: serde_json::Value,
+    /// What changed during the sync, for the `post_sync` hook.
+    pub summary: Option<&'a SyncSummary>,
+}
+
+impl GlobalHookContext<'_> {
+    /// Run the given hook command inside the yolk directory.
+    ///
+    /// As these hooks don't belong to any egg, they always use the [`DEFAULT_HOOK_TIMEOUT`].
+    pub fn run(&self, key: GlobalHookKey, command: &HookCommand) -> Result<(), HookError> {
+        tracing::debug!("Running {} script", key.name());
+        run_hook(
+            key.into(),
+            "yolk.rhai".to_string(),
+            command,
+            DEFAULT_HOOK_TIMEOUT,
+            |cmd| {
+                cmd.current_dir(&self.yolk_dir)
+                    .env("YOLK_EVAL_MODE", eval_mode_name(self.mode))
+                    .env("YOLK_DIR", &self.yolk_dir)
+                    .env("YOLK_DATA", self.data.to_string());
+                if let Some(summary) = self.summary {
+                    for (name, eggs) in summary.lists() {
+                        cmd.env(
+                            format!("YOLK_{}_EGGS", name.to_uppercase()),
+                            eggs.join("\n"),
+                        );
+                    }
+                }
+            },
+        )
+    }
+}
+
+fn eval_mode_name(mode: EvalMode) -> &'static str {
+    match mode {
+        EvalMode::Local => "local",
+        EvalMode::Canonical => "canonical",
+    }
+}
+
+/// A shell hook that failed, together with everything it printed.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{hook} hook of {owner} {reason}")]
+pub struct HookError {
+    /// What declared the hook, i.e. `egg foo` or `yolk.rhai`.
+    pub owner: String,
+    pub hook: HookKey,
+    pub reason: String,
+    #[help]
+    pub output: Option<String>,
+}
+
+/// Run a hook, killing it once it exceeds the timeout of the context.
+///
+/// The hook runs in its own process group without any input, so that on timeout,
+/// everything it started is killed along with it.
+/// The output of the hook is written to temporary files rather than pipes,
+/// so background processes started by the hook that keep the output open don't block yolk.
+/// It is printed once the hook is done, or attached to the error if the hook failed.
+fn run_hook(
+    hook: HookKey,
+    owner: String,
+    command: &HookCommand,
+    timeout: Duration,
+    configure: impl FnOnce(&mut Command),
+) -> Result<(), HookError> {
+    let error = |reason: String, output: Option<String>| HookError {
+        owner: owner.clone(),
+        hook,
+        reason,
+        output,
+    };
+    let stdout = HookOutputFile::new("stdout").map_err(|e| error(e, None))?;
+    let stderr = HookOutputFile::new("stderr").map_err(|e| error(e, None))?;
+    let mut cmd = command.command();
+    configure(&mut cmd);
+    cmd.stdout(stdout.stdio().map_err(|e| error(e, None))?)
+        .stderr(stderr.stdio().map_err(|e| error(e, None))?);
+    let mut child = spawn_hook_process(&mut cmd)
+        .map_err(|e| error(format!("could not be started: {e}"), None))?;
+    let status = wait_for_hook_process(&mut child, Instant::now() + timeout)
+        .map_err(|e| error(format!("could not be waited for: {e}"), None))?;
+    let (stdout, stderr) = (stdout.read(), stderr.read());
+    match status {
+        Some(status) if status.success() => {
+            print!("{stdout}");
+            eprint!("{stderr}");
+            Ok(())
+        }
+        Some(status) => Err(error(
+            match status.code() {
+                Some(code) => format!("failed with exit code {code}"),
+                None => "was terminated by a signal".to_string(),
+            },
+            format_hook_output(&stdout, &stderr),
+        )),
+        None => Err(error(
+            format!("timed out after {}s", timeout.as_secs()),
+            format_hook_output(&stdout, &stderr),
+        )),
+    }
+}
+
+/// Spawn a process for a hook in its own process group, with no input.
+pub(crate) fn spawn_hook_process(cmd: &mut Command) -> std::io::Result<Child> {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+    cmd.stdin(Stdio::null()).spawn()
+}
+
+/// Wait for a process started by [`spawn_hook_process`] to exit.
+///
+/// Once the deadline has passed, the whole process group is killed and `None` is returned.
+pub(crate) fn wait_for_hook_process(
+    child: &mut Child,
+    deadline: Instant,
+) -> std::io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            #[cfg(unix)]
+            // SAFETY: killpg has no memory safety requirements.
+            // The child is its own process group leader and hasn't been reaped yet, so the id still refers to its group.
+            unsafe {
+                libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+            }
+            #[cfg(not(unix))]
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn format_hook_output(stdout: &str, stderr: &str) -> Option<String> {
+    let sections = [("stdout", stdout.trim_end()), ("stderr", stderr.trim_end())]
+        .into_iter()
+        .filter(|(_, output)| !output.is_empty())
+        .map(|(name, output)| format!("{name}:\n{output}"))
+        .collect::<Vec<_>>();
+    (!sections.is_empty()).then(|| sections.join("\n"))
+}
+
+/// A temporary file the output of a hook is written to, which is removed again when dropped.
+struct HookOutputFile(tempfile::NamedTempFile);
+
+impl HookOutputFile {
+    fn new(name: &str) -> Result<Self, String> {
+        tempfile::Builder::new()
+            .prefix("yolk-hook-")
+            .suffix(&format!(".{name}"))
+            .tempfile()
+            .map(Self)
+            .map_err(|e| format!("could not capture its output: {e}"))
+    }
+
+    fn stdio(&self) -> Result<Stdio, String> {
+        let file = self
+            .0
+            .as_file()
+            .try_clone()
+            .map_err(|e| format!("could not capture its output: {e}"))?;
+        Ok(Stdio::from(file))
+    }
+
+    fn read(&self) -> String {
+        fs_err::read(self.0.path())
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Options for a single target, given by using a map like `#{ path: "~/.config/x", strategy: "merge" }` as the target value.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TargetOptions {
+    /// Overrides the `strategy` of the egg for this target.
+    pub strategy: Option<DeploymentStrategy>,
+    /// Overrides the `no_fold` setting of the egg for this target.
+    pub no_fold: Option<bool>,
+    /// Whether this target should be deployed. Only has an effect if the egg itself is enabled.
+    pub enabled: bool,
+    /// The mode and owner the target should have. Only supported for copied targets.
+    pub permissions: FilePermissions,
+}
+
+impl Default for TargetOptions {
+    fn default() -> Self {
+        TargetOptions {
+            strategy: None,
+            no_fold: None,
+            enabled: true,
+            permissions: FilePermissions::default(),
+        }
+    }
+}
+
+impl TargetOptions {
+    pub fn with_strategy(mut self, strategy: DeploymentStrategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn with_no_fold(mut self, no_fold: bool) -> Self {
+        self.no_fold = Some(no_fold);
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: FilePermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+}
+
+/// A target of an egg, with its paths expanded and its options resolved against the options of the egg.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExpandedTarget {
+    /// The absolute path inside the egg.
+    pub in_egg: PathBuf,
+    /// The absolute path it should be deployed to.
+    pub deployed: PathBuf,
+    pub strategy: DeploymentStrategy,
+    /// Whether directories should always be created rather than symlinked when merging.
+    pub no_fold: bool,
+    pub enabled: bool,
+    pub permissions: FilePermissions,
+}
+
+impl ExpandedTarget {
+    pub fn is_copy(&self) -> bool {
+        self.strategy == DeploymentStrategy::Copy
+    }
+
+    /// Find the target that deploys the given path inside the egg.
+    /// If targets are nested, this is the innermost one.
+    pub fn find_innermost<'a>(targets: &'a [ExpandedTarget], in_egg: &Path) -> Option<&'a Self> {
+        targets
+            .iter()
+            .filter(|target| in_egg.starts_with(&target.in_egg))
+            .max_by_key(|target| target.in_egg.components().count())
     }
-    Ok(())
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct EggConfig {
     /// The targets map is a map from `path-relative-to-egg-dir` -> `path-where-it-should-go`.
     pub targets: HashMap<PathBuf, PathBuf>,
+    /// Options of individual targets, keyed by the `path-relative-to-egg-dir` of the target.
+    pub target_options: HashMap<PathBuf, TargetOptions>,
     pub enabled: bool,
+    /// Glob patterns of templated files. Patterns starting with `!` exclude files from the templates.
     pub templates: HashSet<PathBuf>,
+    /// Treat every file in the egg that contains yolk tags as a template, as set by `templates: "auto"`.
+    pub auto_templates: bool,
+    /// Comment styles of templates, keyed by glob patterns relative to the egg directory.
+    /// Used instead of inferring the comment style from the template itself.
+    pub comment_styles: Vec<(String, CommentStyle)>,
+    /// Gitignore-style patterns of files in the egg that should not be deployed.
+    pub ignore: Vec<String>,
     /// The "main" file of this egg -- currently used to determine which path should be opened by `yolk edit`.
     pub main_file: Option<PathBuf>,
     pub strategy: DeploymentStrategy,
+    /// When merging, always create real directories and only symlink the files within them,
+    /// rather than symlinking directories that don't exist yet as a whole.
+    pub no_fold: bool,
+    /// Create symlinks with targets relative to the directory containing them.
+    /// When not set, the global `relative_symlinks` setting of the yolk.rhai file is used.
+    pub relative_symlinks: Option<bool>,
+    /// What to do with existing files that are in the way of the deployment.
+    pub on_conflict: ConflictPolicy,
+    /// Names of eggs that need to be synced before this one.
+    pub depends_on: Vec<String>,
+    /// Tags that can be used to select this egg, i.e. in `yolk sync --tag desktop`.
+    pub tags: Vec<String>,
+    /// Egg-specific data, available to the templates of this egg as `EGG.data`.
+    pub data: serde_json::Value,
     pub unsafe_shell_hooks: ShellHooks,
+    /// Rhai functions to run when the egg is deployed or undeployed.
+    pub hooks: RhaiHooks,
+    /// How long each shell hook may run before it is killed.
+    pub hook_timeout: Duration,
+    /// Whether a failing post hook fails the egg.
+    pub on_hook_failure: HookFailurePolicy,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -126,9 +752,20 @@ enum EggConfigKey {
     Targets,
     MainFile,
     Strategy,
+    NoFold,
+    RelativeSymlinks,
     Templates,
+    CommentStyles,
+    Ignore,
     Enabled,
+    OnConflict,
+    DependsOn,
+    Tags,
+    Data,
     UnsafeShellHooks,
+    Hooks,
+    HookTimeout,
+    OnHookFailure,
 }
 
 impl EggConfigKey {
@@ -137,29 +774,65 @@ impl EggConfigKey {
             "targets" => Some(EggConfigKey::Targets),
             "main_file" => Some(EggConfigKey::MainFile),
             "strategy" => Some(EggConfigKey::Strategy),
+            "no_fold" => Some(EggConfigKey::NoFold),
+            "relative_symlinks" => Some(EggConfigKey::RelativeSymlinks),
             "templates" => Some(EggConfigKey::Templates),
+            "comment_styles" => Some(EggConfigKey::CommentStyles),
+            "ignore" => Some(EggConfigKey::Ignore),
             "enabled" => Some(EggConfigKey::Enabled),
+            "on_conflict" => Some(EggConfigKey::OnConflict),
+            "depends_on" => Some(EggConfigKey::DependsOn),
+            "tags" => Some(EggConfigKey::Tags),
+            "data" => Some(EggConfigKey::Data),
             "unsafe_shell_hooks" => Some(EggConfigKey::UnsafeShellHooks),
+            "hooks" => Some(EggConfigKey::Hooks),
+            "hook_timeout" => Some(EggConfigKey::HookTimeout),
+            "on_hook_failure" => Some(EggConfigKey::OnHookFailure),
             _ => None,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum ShellHookKey {
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ShellHookKey {
     PostDeploy,
     PostUndeploy,
     PreDeploy,
     PreUndeploy,
+    PostTemplateChange,
 }
 
 impl ShellHookKey {
+    pub const ALL: [ShellHookKey; 5] = [
+        ShellHookKey::PreDeploy,
+        ShellHookKey::PostDeploy,
+        ShellHookKey::PreUndeploy,
+        ShellHookKey::PostUndeploy,
+        ShellHookKey::PostTemplateChange,
+    ];
+
+    /// Whether this hook runs after a change was made, rather than before.
+    pub fn is_post(&self) -> bool {
+        !matches!(self, ShellHookKey::PreDeploy | ShellHookKey::PreUndeploy)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShellHookKey::PostDeploy => "post_deploy",
+            ShellHookKey::PostUndeploy => "post_undeploy",
+            ShellHookKey::PreDeploy => "pre_deploy",
+            ShellHookKey::PreUndeploy => "pre_undeploy",
+            ShellHookKey::PostTemplateChange => "post_template_change",
+        }
+    }
+
     fn from_str(s: &str) -> Option<Self> {
         match s {
             "post_deploy" => Some(ShellHookKey::PostDeploy),
             "post_undeploy" => Some(ShellHookKey::PostUndeploy),
             "pre_deploy" => Some(ShellHookKey::PreDeploy),
             "pre_undeploy" => Some(ShellHookKey::PreUndeploy),
+            "post_template_change" => Some(ShellHookKey::PostTemplateChange),
             _ => None,
         }
     }
@@ -170,15 +843,29 @@ impl Default for EggConfig {
         EggConfig {
             enabled: true,
             targets: HashMap::new(),
+            target_options: HashMap::new(),
             templates: HashSet::new(),
+            auto_templates: false,
+            comment_styles: Vec::new(),
+            ignore: Vec::new(),
             main_file: None,
             strategy: Default::default(),
+            no_fold: false,
+            relative_symlinks: None,
+            on_conflict: Default::default(),
+            depends_on: Vec::new(),
+            tags: Vec::new(),
+            data: serde_json::Value::Null,
             unsafe_shell_hooks: ShellHooks {
                 post_deploy: None,
                 post_undeploy: None,
                 pre_deploy: None,
                 pre_undeploy: None,
+                post_template_change: None,
             },
+            hooks: RhaiHooks::default(),
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            on_hook_failure: HookFailurePolicy::default(),
         }
     }
 }
@@ -191,15 +878,29 @@ impl EggConfig {
             targets: maplit::hashmap! {
                 in_egg.to_path_buf() => deployed_to.as_ref().to_path_buf()
             },
+            target_options: HashMap::new(),
             templates: HashSet::new(),
+            auto_templates: false,
+            comment_styles: Vec::new(),
+            ignore: Vec::new(),
             main_file: None,
             strategy: DeploymentStrategy::default(),
+            no_fold: false,
+            relative_symlinks: None,
+            on_conflict: ConflictPolicy::default(),
+            depends_on: Vec::new(),
+            tags: Vec::new(),
+            data: serde_json::Value::Null,
             unsafe_shell_hooks: ShellHooks {
                 post_deploy: None,
                 post_undeploy: None,
                 pre_deploy: None,
                 pre_undeploy: None,
+                post_template_change: None,
             },
+            hooks: RhaiHooks::default(),
+            hook_timeout: DEFAULT_HOOK_TIMEOUT,
+            on_hook_failure: HookFailurePolicy::default(),
         }
     }
 
@@ -207,6 +908,21 @@ impl EggConfig {
         Self::new(in_egg, deployed_to).with_strategy(DeploymentStrategy::Merge)
     }
 
+    pub fn with_hooks(mut self, hooks: RhaiHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn with_hook_timeout(mut self, hook_timeout: Duration) -> Self {
+        self.hook_timeout = hook_timeout;
+        self
+    }
+
+    pub fn with_on_hook_failure(mut self, on_hook_failure: HookFailurePolicy) -> Self {
+        self.on_hook_failure = on_hook_failure;
+        self
+    }
+
     pub fn with_unsafe_hooks(mut self, unsafe_shell_hooks: ShellHooks) -> Self {
         self.unsafe_shell_hooks = unsafe_shell_hooks;
         self
@@ -222,11 +938,94 @@ impl EggConfig {
         self
     }
 
+    /// Treat every file in the egg that contains yolk tags as a template.
+    pub fn with_auto_templates(mut self) -> Self {
+        self.auto_templates = true;
+        self
+    }
+
+    /// Add a gitignore-style pattern of files that should not be deployed.
+    pub fn with_ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.ignore.push(pattern.into());
+        self
+    }
+
+    /// Use the given comment style for templates matching the glob pattern.
+    pub fn with_comment_style(
+        mut self,
+        pattern: impl Into<String>,
+        comment_style: CommentStyle,
+    ) -> Self {
+        self.comment_styles.push((pattern.into(), comment_style));
+        self
+    }
+
+    /// Find the configured comment style for the given template inside `in_dir`.
+    ///
+    /// Patterns without a `/` are matched against the file name, others against the path relative to `in_dir`.
+    /// If multiple patterns match, the longest one wins.
+    pub fn comment_style_for(
+        &self,
+        in_dir: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Option<&CommentStyle> {
+        let relative = path.as_ref().strip_prefix(in_dir.as_ref()).ok()?;
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        self.comment_styles
+            .iter()
+            .filter(|(pattern, _)| {
+                let Ok(glob) = glob::Pattern::new(pattern) else {
+                    return false;
+                };
+                match pattern.contains('/') {
+                    true => glob.matches_path_with(relative, options),
+                    false => relative
+                        .file_name()
+                        .is_some_and(|name| glob.matches_with(&name.to_string_lossy(), options)),
+                }
+            })
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, comment_style)| comment_style)
+    }
+
     pub fn with_strategy(mut self, strategy: DeploymentStrategy) -> Self {
         self.strategy = strategy;
         self
     }
 
+    pub fn with_no_fold(mut self, no_fold: bool) -> Self {
+        self.no_fold = no_fold;
+        self
+    }
+
+    pub fn with_relative_symlinks(mut self, relative_symlinks: bool) -> Self {
+        self.relative_symlinks = Some(relative_symlinks);
+        self
+    }
+
+    pub fn with_on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn with_dependency(mut self, egg_name: impl Into<String>) -> Self {
+        self.depends_on.push(egg_name.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = data;
+        self
+    }
+
     pub fn with_main_file(mut self, main_file: impl AsRef<Path>) -> Self {
         self.main_file = Some(main_file.as_ref().to_path_buf());
         self
@@ -241,44 +1040,151 @@ impl EggConfig {
         self
     }
 
-    /// Returns the targets map, but with any `~` expanded to the home directory.
-    ///
-    /// The targets map is a map from `path-relative-to-egg-dir` -> `path-where-it-should-go`.
-    pub fn targets_expanded(
+    /// Set the options of the target deployed from `in_egg`.
+    pub fn with_target_options(mut self, in_egg: impl AsRef<Path>, options: TargetOptions) -> Self {
+        self.target_options
+            .insert(in_egg.as_ref().to_path_buf(), options);
+        self
+    }
+
+    /// Set the mode and owner that the target deployed from `in_egg` should have.
+    pub fn with_permissions(
+        mut self,
+        in_egg: impl AsRef<Path>,
+        permissions: FilePermissions,
+    ) -> Self {
+        self.target_options
+            .entry(in_egg.as_ref().to_path_buf())
+            .or_default()
+            .permissions = permissions;
+        self
+    }
+
+    /// Returns all targets, including disabled ones, with any `~` expanded to the home directory
+    /// and the options of the egg applied to them. The targets are sorted by their path inside the egg.
+    pub fn expanded_targets(
         &self,
         home: impl AsRef<Path>,
         egg_root: impl AsRef<Path>,
-    ) -> miette::Result<HashMap<PathBuf, PathBuf>> {
-        let egg_root = egg_root.as_ref();
-        self.targets
+    ) -> miette::Result<Vec<ExpandedTarget>> {
+        let egg_root = egg_root.as_ref().canonical()?;
+        let mut targets = self
+            .targets
             .iter()
             .map(|(source, target)| {
-                let source = egg_root.canonical()?.join(source);
+                let options = self.target_options.get(source).cloned().unwrap_or_default();
                 let target = expanduser_with_home(target, home.as_ref());
                 let target = if target.is_absolute() {
                     target
                 } else {
                     home.as_ref().join(target)
                 };
-                Ok((source.normalize(), target.normalize()))
+                ExpandedTarget {
+                    in_egg: egg_root.join(source).normalize(),
+                    deployed: target.normalize(),
+                    strategy: options.strategy.unwrap_or(self.strategy),
+                    no_fold: options.no_fold.unwrap_or(self.no_fold),
+                    enabled: options.enabled,
+                    permissions: options.permissions,
+                }
             })
-            .collect()
+            .collect::<Vec<_>>();
+        targets.sort_by(|a, b| a.in_egg.cmp(&b.in_egg));
+        Ok(targets)
+    }
+
+    /// Returns the targets map of all enabled targets, but with any `~` expanded to the home directory.
+    ///
+    /// The targets map is a map from `path-relative-to-egg-dir` -> `path-where-it-should-go`.
+    pub fn targets_expanded(
+        &self,
+        home: impl AsRef<Path>,
+        egg_root: impl AsRef<Path>,
+    ) -> miette::Result<HashMap<PathBuf, PathBuf>> {
+        Ok(self
+            .expanded_targets(home, egg_root)?
+            .into_iter()
+            .filter(|target| target.enabled)
+            .map(|target| (target.in_egg, target.deployed))
+            .collect())
     }
 
     /// Expand the glob patterns in the `templates` field to a list of paths.
     /// The globbed paths are considered relative to `in_dir`. The resulting list of paths will contain absolute paths.
+    ///
+    /// Files matching a `!` pattern, or inside a directory matching one, are left out.
+    /// With `auto_templates`, every file in `in_dir` that contains yolk tags and isn't ignored is included as well.
     pub fn templates_globexpanded(&self, in_dir: impl AsRef<Path>) -> miette::Result<Vec<PathBuf>> {
         let in_dir = in_dir.as_ref();
+        let ignore = match self.auto_templates {
+            true => Some(EggIgnore::new(in_dir, &self.ignore)?),
+            false => None,
+        };
+        self.expand_templates(in_dir, ignore.as_ref())
+    }
+
+    /// Like [`EggConfig::templates_globexpanded`], reusing an ignore matcher that was already built for `in_dir`.
+    pub fn templates_globexpanded_with_ignore(
+        &self,
+        in_dir: &Path,
+        ignore: &EggIgnore,
+    ) -> miette::Result<Vec<PathBuf>> {
+        self.expand_templates(in_dir, Some(ignore))
+    }
+
+    fn expand_templates(
+        &self,
+        in_dir: &Path,
+        ignore: Option<&EggIgnore>,
+    ) -> miette::Result<Vec<PathBuf>> {
+        let exclusions = TemplateExclusions::new(in_dir, &self.templates)?;
         let mut paths = Vec::new();
+        if let (true, Some(ignore)) = (self.auto_templates, ignore) {
+            for path in files_in_dir(in_dir, ignore)? {
+                if !exclusions.is_excluded(&path) && contains_yolk_tags(&path) {
+                    paths.push(path);
+                }
+            }
+        }
         for globbed in &self.templates {
+            if is_template_exclusion(globbed) {
+                continue;
+            }
             let expanded = glob::glob(&in_dir.join(globbed).to_string_lossy()).into_diagnostic()?;
             for path in expanded {
-                paths.push(path.into_diagnostic()?);
+                let path = path.into_diagnostic()?;
+                if !exclusions.is_excluded(&path) && !paths.contains(&path) {
+                    paths.push(path);
+                }
             }
         }
         Ok(paths)
     }
 
+    /// Find the files in `in_dir` that contain yolk tags, but are neither templates nor excluded from the templates.
+    /// `templates` are the templates as returned by [`EggConfig::templates_globexpanded`].
+    ///
+    /// These were most likely meant to be templates, but never added to the `templates` list.
+    /// Files matched by `ignore` are not considered.
+    pub fn untemplated_files_with_tags(
+        &self,
+        in_dir: impl AsRef<Path>,
+        templates: &[PathBuf],
+        ignore: &EggIgnore,
+    ) -> miette::Result<Vec<PathBuf>> {
+        let in_dir = in_dir.as_ref();
+        if self.auto_templates {
+            return Ok(Vec::new());
+        }
+        let exclusions = TemplateExclusions::new(in_dir, &self.templates)?;
+        let templates = templates.iter().collect::<HashSet<_>>();
+        Ok(files_in_dir(in_dir, ignore)?
+            .into_iter()
+            .filter(|path| !templates.contains(path) && !exclusions.is_excluded(path))
+            .filter(|path| contains_yolk_tags(path))
+            .collect())
+    }
+
     pub fn from_dynamic(value: Dynamic) -> Result<Self, RhaiScriptError> {
         if let Ok(target_path) = value.as_immutable_string_ref() {
             return Ok(EggConfig::new(".", target_path.to_string()));
@@ -297,21 +1203,27 @@ impl EggConfig {
         let empty_map = Dynamic::from(rhai::Map::new());
         let targets = map.get("targets").unwrap_or(&empty_map);
 
+        let mut target_options = HashMap::new();
         let targets = if let Ok(targets) = targets.as_immutable_string_ref() {
             maplit::hashmap! { PathBuf::from(".") => PathBuf::from(targets.to_string()) }
         } else if let Ok(targets) = targets.as_map_ref() {
-            targets
-                .clone()
-                .into_iter()
-                .map(|(k, v)| {
-                    Ok::<_, RhaiScriptError>((
-                        PathBuf::from(&*k),
-                        PathBuf::from(&v.into_string().map_err(|e| {
-                            rhai_error!("target file value must be a path, but got {e}")
-                        })?),
-                    ))
-                })
-                .collect::<Result<_, _>>()?
+            let mut parsed = HashMap::new();
+            for (k, v) in targets.clone() {
+                let in_egg = PathBuf::from(&*k);
+                let target = if v.is_map() {
+                    let (target, options) = parse_target_map(v)?;
+                    if options != TargetOptions::default() {
+                        target_options.insert(in_egg.clone(), options);
+                    }
+                    target
+                } else {
+                    PathBuf::from(&v.into_string().map_err(|e| {
+                        rhai_error!("target file value must be a path or a map, but got {e}")
+                    })?)
+                };
+                parsed.insert(in_egg, target);
+            }
+            parsed
         } else {
             return Err(rhai_error!("egg `targets` must be a string or a map"));
         };
@@ -331,23 +1243,128 @@ impl EggConfig {
                 .map_err(RhaiScriptError::from_report)?,
             None => DeploymentStrategy::default(),
         };
+        for (in_egg, options) in &target_options {
+            if !options.permissions.is_empty()
+                && options.strategy.unwrap_or(strategy) != DeploymentStrategy::Copy
+            {
+                return Err(rhai_error!(
+                    "target `{}` sets a `mode` or `owner`, which is only supported with the copy strategy",
+                    in_egg.display()
+                ));
+            }
+        }
 
-        let templates =
-            if let Some(templates) = map.get("templates") {
-                templates
-                    .as_array_ref()
-                    .map_err(|t| rhai_error!("`templates` must be a list, but got {t}"))?
-                    .iter()
-                    .map(|x| {
-                        Ok::<_, RhaiScriptError>(PathBuf::from(x.clone().into_string().map_err(
-                            |e| rhai_error!("template entry must be a path, but got {e}"),
-                        )?))
-                    })
-                    .collect::<Result<HashSet<_>, _>>()?
-            } else {
-                HashSet::new()
+        let no_fold = if let Some(x) = map.get("no_fold") {
+            x.as_bool()
+                .map_err(|t| rhai_error!("`no_fold` must be a boolean, but got {t}"))?
+        } else {
+            false
+        };
+
+        let relative_symlinks =
+            match map.get("relative_symlinks") {
+                Some(x) => Some(x.as_bool().map_err(|t| {
+                    rhai_error!("`relative_symlinks` must be a boolean, but got {t}")
+                })?),
+                None => None,
             };
 
+        let on_conflict = match map.get("on_conflict") {
+            Some(on_conflict) => ConflictPolicy::from_str(&on_conflict.to_string())
+                .map_err(RhaiScriptError::from_report)?,
+            None => ConflictPolicy::default(),
+        };
+
+        let mut auto_templates = false;
+        let templates = match map.get("templates") {
+            Some(templates) if templates.is_string() => {
+                if templates.to_string() != "auto" {
+                    return Err(rhai_error!(
+                        "`templates` must be a list or \"auto\", but got \"{templates}\""
+                    ));
+                }
+                auto_templates = true;
+                HashSet::new()
+            }
+            Some(templates) => templates
+                .as_array_ref()
+                .map_err(|t| rhai_error!("`templates` must be a list or \"auto\", but got {t}"))?
+                .iter()
+                .map(|x| {
+                    Ok::<_, RhaiScriptError>(PathBuf::from(
+                        x.clone().into_string().map_err(|e| {
+                            rhai_error!("template entry must be a path, but got {e}")
+                        })?,
+                    ))
+                })
+                .collect::<Result<HashSet<_>, _>>()?,
+            None => HashSet::new(),
+        };
+
+        let mut comment_styles = Vec::new();
+        if let Some(styles) = map.get("comment_styles") {
+            let styles = styles
+                .as_map_ref()
+                .map_err(|t| rhai_error!("`comment_styles` must be a map, but got {t}"))?;
+            for (pattern, style) in styles.iter() {
+                glob::Pattern::new(pattern).map_err(|e| {
+                    rhai_error!("Invalid pattern `{pattern}` in `comment_styles`: {e}")
+                })?;
+                comment_styles.push((pattern.to_string(), comment_style_from_dynamic(style)?));
+            }
+        }
+
+        let ignore = if let Some(ignore) = map.get("ignore") {
+            ignore
+                .as_array_ref()
+                .map_err(|t| rhai_error!("`ignore` must be a list, but got {t}"))?
+                .iter()
+                .map(|x| {
+                    x.clone()
+                        .into_string()
+                        .map_err(|e| rhai_error!("ignore entry must be a string, but got {e}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let depends_on = if let Some(depends_on) = map.get("depends_on") {
+            depends_on
+                .as_array_ref()
+                .map_err(|t| rhai_error!("`depends_on` must be a list, but got {t}"))?
+                .iter()
+                .map(|x| {
+                    x.clone().into_string().map_err(|e| {
+                        rhai_error!("depends_on entry must be an egg name, but got {e}")
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let tags = if let Some(tags) = map.get("tags") {
+            tags.as_array_ref()
+                .map_err(|t| rhai_error!("`tags` must be a list, but got {t}"))?
+                .iter()
+                .map(|x| {
+                    x.clone()
+                        .into_string()
+                        .map_err(|e| rhai_error!("tag must be a string, but got {e}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        let data = match map.get("data") {
+            Some(data) => serde_json::to_value(data).map_err(|e| {
+                rhai_error!("`data` must be plain data, but could not be converted: {e}")
+            })?,
+            None => serde_json::Value::Null,
+        };
+
         let enabled = if let Some(x) = map.get("enabled") {
             x.as_bool()
                 .map_err(|t| rhai_error!("`enabled` must be a boolean, but got {t}"))?
@@ -366,25 +1383,381 @@ impl EggConfig {
                     tracing::warn!("unknown key: {}", k);
                 }
             }
+            let hook = |key: ShellHookKey| {
+                shell_hooks
+                    .get(key.name())
+                    .map(HookCommand::from_dynamic)
+                    .transpose()
+            };
             ShellHooks {
-                post_deploy: shell_hooks.get("post_deploy").map(|v| v.to_string()),
-                post_undeploy: shell_hooks.get("post_undeploy").map(|v| v.to_string()),
-                pre_deploy: shell_hooks.get("pre_deploy").map(|v| v.to_string()),
-                pre_undeploy: shell_hooks.get("pre_undeploy").map(|v| v.to_string()),
+                post_deploy: hook(ShellHookKey::PostDeploy)?,
+                post_undeploy: hook(ShellHookKey::PostUndeploy)?,
+                pre_deploy: hook(ShellHookKey::PreDeploy)?,
+                pre_undeploy: hook(ShellHookKey::PreUndeploy)?,
+                post_template_change: hook(ShellHookKey::PostTemplateChange)?,
             }
         } else {
             ShellHooks::default()
         };
 
+        let hooks = match map.get("hooks") {
+            Some(hooks) => RhaiHooks::from_dynamic(hooks)?,
+            None => RhaiHooks::default(),
+        };
+
+        let hook_timeout = match map.get("hook_timeout") {
+            Some(x) => match x.as_int() {
+                Ok(seconds) if seconds > 0 => Duration::from_secs(seconds as u64),
+                _ => {
+                    return Err(rhai_error!(
+                        "`hook_timeout` must be a positive number of seconds, but got {x}"
+                    ))
+                }
+            },
+            None => DEFAULT_HOOK_TIMEOUT,
+        };
+
+        let on_hook_failure = match map.get("on_hook_failure") {
+            Some(x) => {
+                HookFailurePolicy::from_str(&x.to_string()).map_err(RhaiScriptError::from_report)?
+            }
+            None => HookFailurePolicy::default(),
+        };
+
         Ok(EggConfig {
             targets,
+            target_options,
             enabled,
             templates,
+            auto_templates,
+            comment_styles,
+            ignore,
             main_file,
             strategy,
+            no_fold,
+            relative_symlinks,
+            on_conflict,
+            depends_on,
+            tags,
+            data,
             unsafe_shell_hooks,
+            hooks,
+            hook_timeout,
+            on_hook_failure,
+        })
+    }
+}
+
+/// Parse a comment style, given either as the prefix of line comments, like `"#"`,
+/// or as the start and end of block comments, like `["/*", "*/"]`.
+fn comment_style_from_dynamic(value: &Dynamic) -> Result<CommentStyle, RhaiScriptError> {
+    if let Ok(prefix) = value.as_immutable_string_ref() {
+        return Ok(CommentStyle::prefix(&prefix));
+    }
+    let error = || {
+        rhai_error!(
+            "comment style must be a string or a list of two strings, but got {}",
+            value
+        )
+    };
+    let parts = value.as_array_ref().map_err(|_| error())?;
+    match parts.as_slice() {
+        [left, right] => Ok(CommentStyle::circumfix(
+            &left.clone().into_string().map_err(|_| error())?,
+            &right.clone().into_string().map_err(|_| error())?,
+        )),
+        _ => Err(error()),
+    }
+}
+
+/// Files larger than this are never considered to be templates when scanning an egg for yolk tags.
+const MAX_TEMPLATE_SCAN_SIZE: u64 = 1024 * 1024;
+
+/// Whether the given entry of the `templates` list is a `!pattern` excluding files from the templates.
+pub fn is_template_exclusion(pattern: &Path) -> bool {
+    pattern.to_string_lossy().starts_with('!')
+}
+
+/// The `!pattern` entries of a `templates` list.
+struct TemplateExclusions<'a> {
+    in_dir: &'a Path,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl<'a> TemplateExclusions<'a> {
+    fn new(in_dir: &'a Path, templates: &HashSet<PathBuf>) -> miette::Result<Self> {
+        let patterns = templates
+            .iter()
+            .filter_map(|pattern| pattern.to_str()?.strip_prefix('!'))
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Invalid template exclusion `!{pattern}`"))
+            })
+            .collect::<miette::Result<_>>()?;
+        Ok(Self { in_dir, patterns })
+    }
+
+    /// Check whether the given path, or any directory containing it, matches one of the exclusions.
+    fn is_excluded(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(self.in_dir) else {
+            return false;
+        };
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        relative
+            .ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(path, options))
+            })
+    }
+}
+
+/// All regular files inside the given directory, recursively, leaving out ignored files and `.git` directories.
+fn files_in_dir(dir: &Path, ignore: &EggIgnore) -> miette::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let walker = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git" && !ignore.is_ignored(entry.path()));
+    for entry in walker {
+        let entry = entry.into_diagnostic()?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// Check whether the given file contains yolk tags, i.e. looks like it is meant to be a template.
+///
+/// Binary files, files that are too large and files that don't parse as a template are never considered to contain tags.
+fn contains_yolk_tags(path: &Path) -> bool {
+    if path
+        .metadata()
+        .map_or(true, |meta| meta.len() > MAX_TEMPLATE_SCAN_SIZE)
+    {
+        return false;
+    }
+    let Ok(content) = fs_err::read_to_string(path) else {
+        return false;
+    };
+    let first_line = content.lines().next().unwrap_or_default();
+    (first_line.contains("yolk:") || ["{<", "{%", "{#"].iter().any(|tag| content.contains(tag)))
+        && Document::parse_string_named(&path.to_string_lossy(), &content, None)
+            .is_ok_and(|document| document.contains_tags())
+}
+
+/// Selects a subset of eggs by name or by tag, i.e. for `yolk sync --egg nvim --tag desktop`.
+///
+/// An egg is selected if it matches any of the given names or tags. An empty filter selects every egg.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EggFilter {
+    pub names: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl EggFilter {
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.tags.is_empty()
+    }
+
+    pub fn matches(&self, name: &str, config: &EggConfig) -> bool {
+        self.is_empty()
+            || self.names.iter().any(|x| x == name)
+            || config.tags.iter().any(|tag| self.tags.contains(tag))
+    }
+
+    /// Ensure that every egg name in this filter refers to one of the given eggs.
+    pub fn validate(&self, configs: &HashMap<String, EggConfig>) -> miette::Result<()> {
+        if let Some(name) = self.names.iter().find(|name| !configs.contains_key(*name)) {
+            miette::bail!("No egg with name {name}");
+        }
+        Ok(())
+    }
+}
+
+/// Find the `depends_on` entries that don't name any of the given eggs, as `(egg, dependency)` pairs sorted by egg name.
+pub fn unknown_dependencies(configs: &HashMap<String, EggConfig>) -> Vec<(&str, &str)> {
+    let mut unknown = configs
+        .iter()
+        .flat_map(|(name, config)| {
+            config
+                .depends_on
+                .iter()
+                .filter(|dependency| !configs.contains_key(*dependency))
+                .map(|dependency| (name.as_str(), dependency.as_str()))
+        })
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown
+}
+
+/// Report the [`unknown_dependencies`] of the given eggs as errors.
+pub fn unknown_dependency_errors(configs: &HashMap<String, EggConfig>) -> Vec<miette::Report> {
+    unknown_dependencies(configs)
+        .into_iter()
+        .map(|(name, dependency)| {
+            miette::miette!(
+                help = "`depends_on` must only contain names of eggs declared in the `eggs` map",
+                "Egg `{name}` depends on unknown egg `{dependency}`"
+            )
+        })
+        .collect()
+}
+
+/// Determine the order in which the given eggs should be synced, so that every egg comes after the eggs it depends on.
+///
+/// Eggs that don't depend on each other are ordered by name, so the order is the same on every run.
+/// Eggs with [`unknown_dependencies`] are ordered as late as possible, as their prerequisites can't be met.
+pub fn sync_order(configs: &HashMap<String, EggConfig>) -> miette::Result<Vec<String>> {
+    let with_unknown_dependencies = unknown_dependencies(configs)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<HashSet<_>>();
+    let mut remaining: HashMap<&str, BTreeSet<&str>> = configs
+        .iter()
+        .map(|(name, config)| {
+            let dependencies = config
+                .depends_on
+                .iter()
+                .map(String::as_str)
+                .filter(|dependency| configs.contains_key(*dependency))
+                .collect();
+            (name.as_str(), dependencies)
         })
+        .collect();
+    let mut order = Vec::with_capacity(configs.len());
+    loop {
+        let ready: BTreeSet<&str> = remaining
+            .iter()
+            .filter(|(_, dependencies)| dependencies.is_empty())
+            .map(|(name, _)| *name)
+            .collect();
+        let Some(next) = ready
+            .iter()
+            .min_by_key(|name| (with_unknown_dependencies.contains(*name), **name))
+            .copied()
+        else {
+            break;
+        };
+        remaining.remove(next);
+        for dependencies in remaining.values_mut() {
+            dependencies.remove(next);
+        }
+        order.push(next.to_string());
+    }
+    if let Some(start) = remaining.keys().min().copied() {
+        // Every remaining egg still waits for another remaining egg, so following the dependencies must eventually loop.
+        let mut cycle = vec![start];
+        let mut current = start;
+        loop {
+            current = remaining[current]
+                .first()
+                .copied()
+                .expect("remaining eggs always have a remaining dependency");
+            if let Some(position) = cycle.iter().position(|name| *name == current) {
+                cycle.drain(..position);
+                cycle.push(current);
+                break;
+            }
+            cycle.push(current);
+        }
+        miette::bail!(
+            help = "Remove one of these dependencies to break the cycle",
+            "Eggs depend on each other in a cycle: {}",
+            cycle.join(" -> ")
+        );
     }
+    Ok(order)
+}
+
+/// Parse a target given as a map like `#{ path: "~/.ssh/config", strategy: "put", enabled: true, mode: "600", owner: "root" }`.
+/// See [`TargetOptions`] for all supported keys.
+fn parse_target_map(value: Dynamic) -> Result<(PathBuf, TargetOptions), RhaiScriptError> {
+    let map = value.cast::<rhai::Map>();
+    for (k, _v) in map.iter() {
+        let k: &str = k;
+        if !matches!(
+            k,
+            "path" | "strategy" | "no_fold" | "enabled" | "mode" | "owner"
+        ) {
+            tracing::warn!("unknown target key: {}", k);
+        }
+    }
+    let path = map
+        .get("path")
+        .ok_or_else(|| rhai_error!("target map must contain a `path`"))?
+        .as_immutable_string_ref()
+        .map_err(|e| rhai_error!("target `path` must be a path, but got {e}"))?
+        .to_string();
+    let mode = match map.get("mode") {
+        Some(mode) if mode.is_int() => {
+            let mode = mode.as_int().unwrap_or_default();
+            Some(
+                u32::try_from(mode)
+                    .ok()
+                    .filter(|mode| *mode <= 0o7777)
+                    .ok_or_else(|| rhai_error!("Invalid file mode {mode:o}"))?,
+            )
+        }
+        Some(mode) => {
+            Some(permissions::parse_mode(&mode.to_string()).map_err(RhaiScriptError::from_report)?)
+        }
+        None => None,
+    };
+    let owner = match map.get("owner") {
+        Some(owner) => {
+            Some(Owner::from_str(&owner.to_string()).map_err(RhaiScriptError::from_report)?)
+        }
+        None => None,
+    };
+    let strategy = match map.get("strategy") {
+        Some(strategy) => Some(
+            DeploymentStrategy::from_str(&strategy.to_string())
+                .map_err(RhaiScriptError::from_report)?,
+        ),
+        None => None,
+    };
+    let no_fold = match map.get("no_fold") {
+        Some(no_fold) => Some(
+            no_fold
+                .as_bool()
+                .map_err(|t| rhai_error!("target `no_fold` must be a boolean, but got {t}"))?,
+        ),
+        None => None,
+    };
+    let enabled = match map.get("enabled") {
+        Some(enabled) => enabled
+            .as_bool()
+            .map_err(|t| rhai_error!("target `enabled` must be a boolean, but got {t}"))?,
+        None => true,
+    };
+    Ok((
+        PathBuf::from(path),
+        TargetOptions {
+            strategy,
+            no_fold,
+            enabled,
+            permissions: FilePermissions { mode, owner },
+        },
+    ))
 }
 
 /// Replace the `~` at the start of a path with the given home directory path.
@@ -399,7 +1772,7 @@ fn expanduser_with_home(path: &Path, home: &Path) -> PathBuf {
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashSet;
+    use std::{collections::HashSet, path::Path};
 
     use assert_fs::{
         prelude::{FileWriteStr as _, PathChild as _},
@@ -410,8 +1783,14 @@ mod test {
     use pretty_assertions::assert_eq;
 
     use crate::{
-        eggs_config::{DeploymentStrategy, EggConfig, ShellHooks},
+        eggs_config::{
+            ConflictPolicy, DeploymentStrategy, EggConfig, HookCommand, HookFailurePolicy,
+            ShellHooks, TargetOptions,
+        },
+        permissions::FilePermissions,
+        templating::comment_style::CommentStyle,
         util::test_util::TestResult,
+        yolk_paths::EggIgnore,
     };
 
     use rstest::rstest;
@@ -422,28 +1801,122 @@ mod test {
                 enabled: false,
                 targets: #{ "foo": "~/bar" },
                 templates: ["foo"],
+                ignore: ["README.md", "*.swp"],
                 main_file: "foo",
                 strategy: "merge",
+                on_conflict: "backup",
                 unsafe_shell_hooks: #{
                     post_deploy: "run after deploy",
                     post_undeploy: "run after undeploy",
                     pre_deploy: "run before deploy",
                     pre_undeploy: "run before undeploy",
+                    post_template_change: "run after template change",
                 }
             }
         "#},
         EggConfig::new_merge("foo", "~/bar")
             .with_enabled(false)
             .with_template("foo")
+            .with_ignore("README.md")
+            .with_ignore("*.swp")
             .with_strategy(DeploymentStrategy::Merge)
             .with_main_file("foo")
+            .with_on_conflict(ConflictPolicy::Backup)
             .with_unsafe_hooks(ShellHooks {
-                post_deploy: Some("run after deploy".to_string()),
-                post_undeploy: Some("run after undeploy".to_string()),
-                pre_deploy: Some("run before deploy".to_string()),
-                pre_undeploy: Some("run before undeploy".to_string()),
+                post_deploy: Some("run after deploy".into()),
+                post_undeploy: Some("run after undeploy".into()),
+                pre_deploy: Some("run before deploy".into()),
+                pre_undeploy: Some("run before undeploy".into()),
+                post_template_change: Some("run after template change".into()),
             })
     )]
+    #[case(
+        r#"#{ targets: #{ "config": #{ path: "~/.ssh/config", mode: "600", owner: "root:wheel" } }, strategy: "copy" }"#,
+        EggConfig::new("config", "~/.ssh/config")
+            .with_strategy(DeploymentStrategy::Copy)
+            .with_permissions(
+                "config",
+                FilePermissions::default()
+                    .with_mode(0o600)
+                    .with_owner("root:wheel".parse().unwrap())
+            )
+    )]
+    #[case(
+        r#"#{ targets: #{ "config": #{ path: "~/.ssh/config", mode: 0o644, strategy: "copy" } } }"#,
+        EggConfig::new("config", "~/.ssh/config").with_target_options(
+            "config",
+            TargetOptions::default()
+                .with_strategy(DeploymentStrategy::Copy)
+                .with_permissions(FilePermissions::default().with_mode(0o644))
+        )
+    )]
+    #[case(
+        indoc::indoc! {r#"
+            #{
+                targets: #{
+                    "foo": #{ path: "~/foo", strategy: "merge" },
+                    "bar": #{ path: "~/bar", enabled: false, no_fold: true },
+                },
+                strategy: "copy",
+                no_fold: true,
+            }
+        "#},
+        EggConfig::default()
+            .with_strategy(DeploymentStrategy::Copy)
+            .with_no_fold(true)
+            .with_target("foo", "~/foo")
+            .with_target("bar", "~/bar")
+            .with_target_options("foo", TargetOptions::default().with_strategy(DeploymentStrategy::Merge))
+            .with_target_options("bar", TargetOptions::default().with_enabled(false).with_no_fold(true))
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", hook_timeout: 10, on_hook_failure: "warn" }"#,
+        EggConfig::new(".", "~/bar")
+            .with_hook_timeout(std::time::Duration::from_secs(10))
+            .with_on_hook_failure(HookFailurePolicy::Warn)
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", data: #{ port: 8080, hosts: ["a", "b"] } }"#,
+        EggConfig::new(".", "~/bar").with_data(serde_json::json!({ "port": 8080, "hosts": ["a", "b"] }))
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", comment_styles: #{ "*.Xresources": "!", "*.css": ["/*", "*/"] } }"#,
+        EggConfig::new(".", "~/bar")
+            .with_comment_style("*.Xresources", CommentStyle::prefix("!"))
+            .with_comment_style("*.css", CommentStyle::circumfix("/*", "*/"))
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", templates: "auto" }"#,
+        EggConfig::new(".", "~/bar").with_auto_templates()
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", templates: ["**/*.toml", "!vendor"] }"#,
+        EggConfig::new(".", "~/bar").with_template("**/*.toml").with_template("!vendor")
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", relative_symlinks: true }"#,
+        EggConfig::new(".", "~/bar").with_relative_symlinks(true)
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", depends_on: ["fonts", "zsh"] }"#,
+        EggConfig::new(".", "~/bar").with_dependency("fonts").with_dependency("zsh")
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", tags: ["desktop", "work"] }"#,
+        EggConfig::new(".", "~/bar").with_tag("desktop").with_tag("work")
+    )]
+    #[case(
+        r#"#{ targets: "~/bar", unsafe_shell_hooks: #{ post_deploy: ["systemctl", "--user", "restart", "bar"] } }"#,
+        EggConfig::new(".", "~/bar").with_unsafe_hooks(ShellHooks {
+            post_deploy: Some(HookCommand::Argv(vec![
+                "systemctl".to_string(),
+                "--user".to_string(),
+                "restart".to_string(),
+                "bar".to_string(),
+            ])),
+            ..ShellHooks::default()
+        })
+    )]
     #[case(r#"#{ targets: "~/bar" }"#, EggConfig::new(".", "~/bar"))]
     #[case(r#""~/bar""#, EggConfig::new(".", "~/bar"))]
     fn test_read_eggs_config(#[case] input: &str, #[case] expected: EggConfig) -> TestResult {
@@ -475,6 +1948,94 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_template_exclusions() -> TestResult {
+        let home = TempDir::new().into_diagnostic()?;
+        let config = EggConfig::new_merge(home.to_str().unwrap(), ".")
+            .with_template("**/*.foo")
+            .with_template("!vendor")
+            .with_template("!*.skip.foo");
+        home.child("a.foo").write_str("a")?;
+        home.child("a.skip.foo").write_str("a")?;
+        home.child("bar/a.skip.foo").write_str("a")?;
+        home.child("vendor/a.foo").write_str("a")?;
+        home.child("vendor/baz/a.foo").write_str("a")?;
+        let result = config.templates_globexpanded(&home)?;
+
+        assert_eq!(
+            result.into_iter().collect::<HashSet<_>>(),
+            hashset![
+                home.child("a.foo").path().to_path_buf(),
+                home.child("bar/a.skip.foo").path().to_path_buf(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_templates() -> TestResult {
+        let home = TempDir::new().into_diagnostic()?;
+        home.child("plain").write_str("no tags {< in here")?;
+        home.child("inline").write_str("foo # {< 1 >}")?;
+        home.child("dir/conditional")
+            .write_str("{% if true %}\nfoo\n{% end %}")?;
+        home.child("vendor/inline").write_str("foo # {< 1 >}")?;
+        home.child(".git/inline").write_str("foo # {< 1 >}")?;
+        home.child("ignored/inline").write_str("foo # {< 1 >}")?;
+        fs_err::write(home.child("binary"), b"\xff\xfe{<1>}").into_diagnostic()?;
+
+        let config = EggConfig::new_merge(home.to_str().unwrap(), ".")
+            .with_auto_templates()
+            .with_template("!vendor")
+            .with_ignore("ignored");
+        assert_eq!(
+            config.templates_globexpanded(&home)?,
+            vec![
+                home.child("dir/conditional").path().to_path_buf(),
+                home.child("inline").path().to_path_buf(),
+            ]
+        );
+        assert_eq!(
+            config.untemplated_files_with_tags(
+                &home,
+                &config.templates_globexpanded(&home)?,
+                &EggIgnore::new(&home, &config.ignore)?
+            )?,
+            Vec::<std::path::PathBuf>::new()
+        );
+
+        let config = EggConfig::new_merge(home.to_str().unwrap(), ".")
+            .with_template("inline")
+            .with_ignore("ignored");
+        assert_eq!(
+            config.untemplated_files_with_tags(
+                &home,
+                &config.templates_globexpanded(&home)?,
+                &EggIgnore::new(&home, &config.ignore)?
+            )?,
+            vec![
+                home.child("dir/conditional").path().to_path_buf(),
+                home.child("vendor/inline").path().to_path_buf(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_comment_style_for() {
+        let config = EggConfig::new(".", "~/foo")
+            .with_comment_style("*.ini", CommentStyle::prefix(";"))
+            .with_comment_style("conf/*", CommentStyle::prefix("#"))
+            .with_comment_style("conf/*.ini", CommentStyle::prefix("REM"));
+        let style = |path: &str| config.comment_style_for("/egg", Path::new("/egg").join(path));
+        assert_eq!(style("a.ini"), Some(&CommentStyle::prefix(";")));
+        assert_eq!(style("nested/dir/a.ini"), Some(&CommentStyle::prefix(";")));
+        assert_eq!(style("conf/a"), Some(&CommentStyle::prefix("#")));
+        assert_eq!(style("conf/a.ini"), Some(&CommentStyle::prefix("REM")));
+        assert_eq!(style("conf/nested/a"), None);
+        assert_eq!(style("a.toml"), None);
+    }
+
     #[test]
     fn test_invalid_key_warns_and_parses() {
         let input = r#"#{ unknown_key: "value" }"#;
@@ -506,4 +2067,61 @@ mod test {
         let cfg = parsed.unwrap();
         assert_eq!(cfg.unsafe_shell_hooks, ShellHooks::default());
     }
+
+    #[rstest]
+    #[case(r#"#{ targets: #{ "config": #{ path: "~/.ssh/config", mode: "600" } } }"#)]
+    #[case(
+        r#"#{ targets: #{ "config": #{ path: "~/.ssh/config", owner: "root", strategy: "merge" } }, strategy: "copy" }"#
+    )]
+    fn test_permissions_require_copy_strategy(#[case] input: &str) {
+        let result = rhai::Engine::new().eval(input).unwrap();
+        assert!(EggConfig::from_dynamic(result).is_err());
+    }
+
+    #[test]
+    fn test_rhai_hooks_must_be_functions() {
+        let input = r#"#{ hooks: #{ post_deploy: "echo hi" } }"#;
+        let result = rhai::Engine::new().eval(input).unwrap();
+        assert!(EggConfig::from_dynamic(result).is_err());
+    }
+
+    #[test]
+    fn test_sync_order() -> TestResult {
+        let configs = maplit::hashmap! {
+            "zsh".to_string() => EggConfig::default(),
+            "fonts".to_string() => EggConfig::default(),
+            "nvim".to_string() => EggConfig::default().with_dependency("fonts").with_dependency("zsh"),
+            "kitty".to_string() => EggConfig::default().with_dependency("fonts"),
+        };
+        assert_eq!(
+            super::sync_order(&configs)?,
+            vec!["fonts", "kitty", "zsh", "nvim"]
+        );
+
+        let configs = maplit::hashmap! {
+            "a".to_string() => EggConfig::default().with_dependency("missing"),
+            "b".to_string() => EggConfig::default().with_dependency("a"),
+            "c".to_string() => EggConfig::default(),
+        };
+        assert_eq!(super::sync_order(&configs)?, vec!["c", "a", "b"]);
+        assert_eq!(
+            super::unknown_dependencies(&configs),
+            vec![("a", "missing")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_order_rejects_cycles() {
+        let configs = maplit::hashmap! {
+            "a".to_string() => EggConfig::default().with_dependency("b"),
+            "b".to_string() => EggConfig::default().with_dependency("c"),
+            "c".to_string() => EggConfig::default().with_dependency("b"),
+        };
+        let err = super::sync_order(&configs).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Eggs depend on each other in a cycle: b -> c -> b"
+        );
+    }
 }
@@ -0,0 +1,133 @@
+//! The trust store for shell hooks.
+//!
+//! Shell hooks can run arbitrary commands, so yolk only runs hooks the user explicitly approved.
+//! The hashes of approved hook commands are stored as JSON in yolk's data directory, separately for every yolk directory.
+//! As the store is not part of the yolk directory, cloning someone else's dotfiles can't carry over their approvals.
+use std::{collections::BTreeSet, path::Path};
+
+use miette::{Context as _, IntoDiagnostic as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    eggs_config::{HookCommand, HookKey},
+    util::{self, PathExt as _},
+};
+
+/// The current version of the trust store format.
+pub const TRUST_STORE_VERSION: u32 = 1;
+
+/// Callback that decides whether an untrusted shell hook may run.
+/// When it returns `true`, the hook is added to the trust store.
+pub type TrustHandler = dyn Fn(&ShellHook) -> Result<bool> + Send + Sync;
+
+/// A shell hook configured for an egg, or at the top level of the yolk.rhai file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellHook {
+    /// The egg the hook belongs to, or `None` for hooks declared at the top level of the yolk.rhai file.
+    pub egg_name: Option<String>,
+    /// The hook this command runs for, i.e. `post_deploy`.
+    pub hook: HookKey,
+    pub command: HookCommand,
+}
+
+impl ShellHook {
+    /// Describe what declared this hook, i.e. `egg foo` or `yolk.rhai`.
+    pub fn owner(&self) -> String {
+        match &self.egg_name {
+            Some(egg_name) => format!("egg {egg_name}"),
+            None => "yolk.rhai".to_string(),
+        }
+    }
+
+    /// The hex-encoded sha256 hash identifying this hook.
+    ///
+    /// Besides the command, this covers the egg and the name of the hook,
+    /// so trusting a command for one hook doesn't trust it anywhere else.
+    pub fn hash(&self) -> String {
+        use sha2::Digest as _;
+        let command = match &self.command {
+            HookCommand::Shell(script) => format!("shell\0{script}"),
+            HookCommand::Argv(args) => format!("argv\0{}", args.join("\0")),
+        };
+        let content = format!("{}\0{}\0{command}", self.owner(), self.hook);
+        hex::encode(sha2::Sha256::digest(content))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookTrustStore {
+    pub version: u32,
+    #[serde(default)]
+    pub trusted: BTreeSet<String>,
+}
+
+impl Default for HookTrustStore {
+    fn default() -> Self {
+        Self {
+            version: TRUST_STORE_VERSION,
+            trusted: BTreeSet::new(),
+        }
+    }
+}
+
+impl HookTrustStore {
+    /// Load the trust store from the given path, or return an empty store if it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs_err::read_to_string(path).into_diagnostic()?;
+        let store: Self = serde_json::from_str(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse hook trust store {}", path.abbr()))?;
+        if store.version > TRUST_STORE_VERSION {
+            miette::bail!(
+                help = "Please update yolk.",
+                "Hook trust store {} was written by a newer version of yolk (format version {})",
+                path.abbr(),
+                store.version
+            );
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self).into_diagnostic()?;
+        if let Some(parent) = path.parent() {
+            fs_err::create_dir_all(parent).into_diagnostic()?;
+        }
+        util::write_atomic(path, content)
+            .wrap_err_with(|| format!("Failed to update hook trust store {}", path.abbr()))
+    }
+
+    pub fn is_trusted(&self, hook: &ShellHook) -> bool {
+        self.trusted.contains(&hook.hash())
+    }
+
+    /// Trust the command of the given hook. Returns whether it wasn't trusted before.
+    pub fn trust(&mut self, hook: &ShellHook) -> bool {
+        self.trusted.insert(hook.hash())
+    }
+}
+
+/// Make sure the user trusts the given hook, asking the `handler` if it isn't in the trust store at `store_path` yet.
+pub fn ensure_trusted(store_path: &Path, handler: &TrustHandler, hook: &ShellHook) -> Result<()> {
+    let mut store = HookTrustStore::load(store_path)?;
+    if store.is_trusted(hook) {
+        return Ok(());
+    }
+    if !handler(hook)? {
+        miette::bail!(
+            help =
+                "Run `yolk hooks trust` to trust the hooks of your eggs, or pass `--trust-hooks`.",
+            "Refusing to run untrusted {} hook of {}: {}",
+            hook.hook,
+            hook.owner(),
+            hook.command
+        );
+    }
+    store.trust(hook);
+    store.save(store_path)
+}
@@ -1,10 +1,15 @@
 #[cfg(feature = "docgen")]
 pub mod doc_generator;
 
+pub mod backup;
 pub mod deploy;
+pub mod doctor;
 pub mod eggs_config;
 pub mod git_utils;
+pub mod hook_trust;
+pub mod manifest;
 pub mod multi_error;
+pub mod permissions;
 pub mod script;
 pub mod templating;
 #[cfg(test)]
@@ -1,9 +1,4 @@
-use std::{
-    collections::HashSet,
-    io::Read as _,
-    path::{Path, PathBuf},
-    str::FromStr,
-};
+use std::{collections::HashSet, io::Read as _, path::PathBuf, str::FromStr};
 
 use clap::{builder::StyledStr, CommandFactory, Parser, Subcommand, ValueHint};
 use clap_complete::{env::CompleteEnv, ArgValueCompleter, CompletionCandidate};
@@ -18,14 +13,17 @@ use tracing_subscriber::{
 
 use tracing_tree::HierarchicalLayer;
 use yolk::{
-    deploy::Deployer,
-    eggs_config::DeploymentStrategy,
+    deploy::{Deployer, DriftResolution},
+    doctor,
+    eggs_config::{ConflictPolicy, DeploymentStrategy, EggFilter, GlobalHookKey},
+    permissions::{self, FilePermissions},
     util::PathExt as _,
     yolk::{EvalMode, Yolk},
     yolk_paths,
 };
 
 mod adopt;
+mod prompts;
 
 #[derive(clap::Parser, Debug)]
 #[command(version, about, name = "yolk", author = "ElKowar")]
@@ -41,6 +39,10 @@ struct Args {
     #[arg(long, env = "YOLK_HOME_DIR", global = true)]
     home_dir: Option<PathBuf>,
 
+    /// Provide a custom directory for local state that is not part of the yolk directory, such as trusted hooks
+    #[arg(long, env = "YOLK_DATA_DIR", global = true)]
+    data_dir: Option<PathBuf>,
+
     /// Enable debug logging
     #[arg(long, short = 'v', global = true, action = clap::ArgAction::Count)]
     debug: u8,
@@ -48,6 +50,10 @@ struct Args {
     /// Enable displaying logs as a tree
     #[arg(long, global = true)]
     tracing_tree: bool,
+
+    /// Run shell hooks that were never trusted before without asking, and add them to the trust store.
+    #[arg(long, global = true)]
+    trust_hooks: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -59,6 +65,18 @@ enum Command {
     /// Show the current state of your yolk eggs.
     Status,
 
+    /// Check your yolk directory and deployments for problems, and suggest how to fix them.
+    Doctor {
+        /// Automatically apply all fixes that are safe to apply.
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Check that every template survives being converted to its canonical state and back unchanged.
+    ///
+    /// This check also runs before any command that uses the canonical state, such as `yolk git`.
+    Verify,
+
     /// Make sure you don't accidentally commit your local egg states.
     ///
     /// This renames `.git` to `.yolk_git` to ensure that git interaction happens through the yolk CLI
@@ -71,7 +89,7 @@ enum Command {
         egg_name: String,
         /// Path that should be adopted into yolk.
         path: PathBuf,
-        /// Whether this should be deployed via `merge` or `put` strategy. Defaults to `put`.
+        /// Whether this should be deployed via `merge`, `put` or `copy` strategy. Defaults to `put`.
         #[arg(long)]
         strategy: Option<DeploymentStrategy>,
         /// Append the generated config to yolk.rhai and sync the adopted egg without prompting.
@@ -107,6 +125,26 @@ enum Command {
         /// Sync to canonical state. This should only be necessary for debugging purposes.
         #[arg(long)]
         canonical: bool,
+        /// Print everything sync would do, without touching the filesystem or running any hooks.
+        #[arg(long)]
+        dry_run: bool,
+        /// What to do with copied files that were modified since they were deployed: `pull`, `overwrite` or `skip`.
+        /// If not given, yolk asks for every modified file when running interactively, and skips them otherwise.
+        #[arg(long)]
+        on_drift: Option<DriftResolution>,
+        /// What to do with existing files that are in the way of a deployment: `fail` or `backup`.
+        /// Overrides the `on_conflict` setting of every egg.
+        #[arg(long)]
+        on_conflict: Option<ConflictPolicy>,
+        /// Restore backed up files when undeploying an egg, without asking.
+        #[arg(long)]
+        restore_backups: bool,
+        /// Only sync the given egg. Can be given multiple times.
+        #[arg(long = "egg", value_name = "EGG", add = ArgValueCompleter::new(egg_completer))]
+        eggs: Vec<String>,
+        /// Only sync eggs with the given tag. Can be given multiple times.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
     },
 
     /// Evaluate a given templated file, or read a templated string from stdin.
@@ -121,7 +159,17 @@ enum Command {
     },
 
     /// List all the eggs in your yolk directory
-    List,
+    List {
+        /// Only list eggs with the given tag. Can be given multiple times.
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+    },
+
+    /// Manage which shell hooks of your eggs yolk trusts to run.
+    Hooks {
+        #[command(subcommand)]
+        command: HooksCommand,
+    },
 
     /// Open your `yolk.rhai` or the given egg in your `$EDITOR` of choice.
     Edit {
@@ -156,6 +204,8 @@ enum Command {
         create_symlink: Vec<(PathBuf, PathBuf)>,
         #[arg(long, value_names = ["SYMLINK_PATH"], required = false, value_hint=ValueHint::AnyPath)]
         delete_symlink: Vec<PathBuf>,
+        #[arg(long, value_names = ["PATH::::MODE::::OWNER"], required = false, value_parser=parse_permissions_arg)]
+        set_permissions: Vec<(PathBuf, FilePermissions)>,
     },
 
     /// Generate markdown documentation for the CLI
@@ -169,6 +219,17 @@ enum Command {
     },
 }
 
+#[derive(Debug, Subcommand)]
+enum HooksCommand {
+    /// List the shell hooks of all eggs, and whether they are trusted.
+    List,
+    /// Trust the current shell hooks of all eggs, or of the given egg.
+    Trust {
+        #[arg(add = ArgValueCompleter::new(egg_completer))]
+        egg: Option<String>,
+    },
+}
+
 fn egg_completer(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let Some(current) = current.to_str() else {
         return vec![];
@@ -215,6 +276,22 @@ fn parse_symlink_pair(s: &str) -> Result<(PathBuf, PathBuf), String> {
     }
 }
 
+fn parse_permissions_arg(s: &str) -> Result<(PathBuf, FilePermissions), String> {
+    let parts: Vec<&str> = s.splitn(3, "::::").collect();
+    let [path, mode, owner] = parts[..] else {
+        return Err(format!("Invalid permissions argument: {}", s));
+    };
+    let mode = (!mode.is_empty())
+        .then(|| permissions::parse_mode(mode))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let owner = (!owner.is_empty())
+        .then(|| owner.parse::<permissions::Owner>())
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    Ok((PathBuf::from(path), FilePermissions { mode, owner }))
+}
+
 pub(crate) fn main() -> Result<()> {
     miette::set_hook(Box::new(|_| {
         Box::new(
@@ -297,9 +374,17 @@ fn run_command(args: Args) -> Result<()> {
         .wrap_err("No home dir could be found")?;
     tracing::trace!("Setting yolk dir to {}", yolk_dir.display());
     tracing::trace!("Setting home dir to {}", home_dir.display());
-    let yolk_paths = yolk::yolk_paths::YolkPaths::new(yolk_dir, home_dir)?;
+    let mut yolk_paths = yolk::yolk_paths::YolkPaths::new(yolk_dir, home_dir)?;
+    if let Some(data_dir) = args.data_dir {
+        yolk_paths.set_data_dir(data_dir);
+    }
 
-    let yolk = Yolk::new(yolk_paths);
+    let mut yolk = Yolk::new(yolk_paths);
+    let trust_hooks = args.trust_hooks;
+    yolk.set_trust_handler(move |hook| match trust_hooks {
+        true => Ok(true),
+        false => prompts::prompt_trust_hook(hook),
+    });
     match &args.command {
         Command::Init => yolk.init_yolk(None)?,
         // TODO: we should likely also do this as part of init, maybe
@@ -311,6 +396,28 @@ fn run_command(args: Args) -> Result<()> {
             if yolk.paths().active_yolk_git_dir()? == yolk.paths().yolk_default_git_path() {
                 println!("Yolk git is not safeguarded. It is recommended to run `yolk safeguard`.");
             }
+            for (egg_name, entry) in yolk.find_missing_deployments()? {
+                println!(
+                    "{} was deployed from egg {} but has since been removed or replaced. Run `yolk sync` to deploy it again.",
+                    entry.path.abbr(),
+                    egg_name
+                );
+            }
+            for drifted in yolk.find_drifted_copies()? {
+                println!(
+                    "{} was modified since it was copied from {}. Run `yolk sync` to pull or overwrite the changes.",
+                    drifted.deployed.abbr(),
+                    drifted.in_egg.abbr()
+                );
+            }
+            for drift in yolk.find_permission_drift()? {
+                println!(
+                    "{} of egg {}: {}. Run `yolk sync` to fix its permissions.",
+                    drift.path.abbr(),
+                    drift.egg_name,
+                    drift.differences.join(", ")
+                );
+            }
             yolk.with_canonical_state(|| {
                 yolk.paths()
                     .start_git()?
@@ -320,8 +427,49 @@ fn run_command(args: Args) -> Result<()> {
                     .into_diagnostic()
             })?;
         }
-        Command::List => {
-            let mut eggs = yolk.list_eggs()?;
+        Command::Verify => {
+            yolk.verify_templates()?;
+            println!("All templates survive the round trip through their canonical state");
+        }
+        Command::Doctor { fix } => {
+            let findings = doctor::diagnose(&yolk)?;
+            let mut unresolved = 0;
+            for finding in &findings {
+                println!("✗ {}", finding.problem);
+                match &finding.fix {
+                    Some(to_apply)
+                        if *fix
+                            && (!to_apply.requires_confirmation()
+                                || prompts::prompt_apply_fix(&finding.suggestion)?) =>
+                    {
+                        doctor::apply_fix(&yolk, to_apply)?;
+                        println!("  fixed: {}", finding.suggestion);
+                    }
+                    Some(_) => {
+                        unresolved += 1;
+                        println!(
+                            "  fix: {} (run `yolk doctor --fix` to apply)",
+                            finding.suggestion
+                        );
+                    }
+                    None => {
+                        unresolved += 1;
+                        println!("  fix: {}", finding.suggestion);
+                    }
+                }
+            }
+            if findings.is_empty() {
+                println!("No problems found");
+            } else if unresolved > 0 {
+                miette::bail!("Found {unresolved} unresolved problem(s)");
+            }
+        }
+        Command::List { tags } => {
+            let filter = EggFilter {
+                names: Vec::new(),
+                tags: tags.clone(),
+            };
+            let mut eggs = yolk.list_filtered_eggs(&filter)?;
             eggs.sort_by_key(|egg| egg.name().to_string());
             for egg in eggs {
                 let deployed = egg.is_deployed()?;
@@ -336,6 +484,41 @@ fn run_command(args: Args) -> Result<()> {
             }
         }
 
+        Command::Hooks { command } => match command {
+            HooksCommand::List => {
+                for (hook, trusted) in yolk.list_shell_hooks()? {
+                    let text = format!(
+                        "{} {} {}: {}",
+                        if trusted { "✓" } else { "✗" },
+                        hook.egg_name.as_deref().unwrap_or("yolk.rhai"),
+                        hook.hook,
+                        hook.command
+                    );
+                    let text = text.if_supports_color(owo_colors::Stream::Stdout, |text| {
+                        text.color(match trusted {
+                            true => owo_colors::AnsiColors::Green,
+                            false => owo_colors::AnsiColors::Yellow,
+                        })
+                    });
+                    println!("{}", text);
+                }
+            }
+            HooksCommand::Trust { egg } => {
+                let trusted = yolk.trust_hooks(egg.as_deref())?;
+                if trusted.is_empty() {
+                    println!("All hooks are already trusted");
+                }
+                for hook in trusted {
+                    println!(
+                        "Trusted {} hook of {}: {}",
+                        hook.hook,
+                        hook.owner(),
+                        hook.command
+                    );
+                }
+            }
+        },
+
         Command::Adopt {
             egg_name,
             path,
@@ -390,17 +573,47 @@ fn run_command(args: Args) -> Result<()> {
             }
         }
 
-        Command::Sync { canonical } => {
-            // Lets always ensure that the yolk dir is in a properly set up state.
-            // This should later be replaced with some sort of version-aware compatibility check.
-            yolk.init_git_config(None)?;
+        Command::Sync {
+            canonical,
+            dry_run,
+            on_drift,
+            on_conflict,
+            restore_backups,
+            eggs,
+            tags,
+        } => {
+            let on_drift = *on_drift;
+            yolk.set_drift_handler(move |drifted| match on_drift {
+                Some(resolution) => Ok(resolution),
+                None => prompts::prompt_drift_resolution(drifted),
+            });
+            if let Some(on_conflict) = on_conflict {
+                yolk.set_conflict_policy(*on_conflict);
+            }
+            let restore_backups = *restore_backups;
+            yolk.set_restore_handler(move |egg_name, paths| match restore_backups {
+                true => Ok(true),
+                false => prompts::prompt_restore_backups(egg_name, paths),
+            });
+            if *dry_run {
+                yolk.set_dry_run(true);
+            } else {
+                // Lets always ensure that the yolk dir is in a properly set up state.
+                // This should later be replaced with some sort of version-aware compatibility check.
+                yolk.init_git_config(None)?;
+            }
 
-            yolk.sync_to_mode(
+            let filter = EggFilter {
+                names: eggs.clone(),
+                tags: tags.clone(),
+            };
+            yolk.sync_eggs_to_mode(
                 match *canonical {
                     true => EvalMode::Canonical,
                     false => EvalMode::Local,
                 },
                 true,
+                &filter,
             )?
         }
         Command::Eval { expr, canonical } => {
@@ -434,13 +647,16 @@ fn run_command(args: Args) -> Result<()> {
             // before executing it
 
             let first_cmd = command.first().map(|x| x.as_ref());
+            yolk.run_git_hook(GlobalHookKey::PreGit)?;
             if !force_canonical
                 && (first_cmd == Some("push")
                     || first_cmd == Some("init")
                     || first_cmd == Some("fetch")
                     || first_cmd.is_none())
             {
-                cmd.status().into_diagnostic()?;
+                if cmd.status().into_diagnostic()?.success() {
+                    yolk.run_git_hook(GlobalHookKey::PostGit)?;
+                }
             } else {
                 // TODO: Ensure that, if something goes wrong during the sync, the git command is _not_ run.
                 // Even if, normally, the sync call would only emit warnings, we must _never_ commit a failed sync.
@@ -451,6 +667,7 @@ fn run_command(args: Args) -> Result<()> {
                 if !status.success() {
                     miette::bail!("Git command failed with status {}", status);
                 }
+                yolk.run_git_hook(GlobalHookKey::PostGit)?;
             }
         }
 
@@ -525,22 +742,6 @@ fn run_command(args: Args) -> Result<()> {
                         }
                     };
 
-                    let mut on_file_updated = |path: &Path| {
-                        let result = if no_sync {
-                            let Ok(content) = fs_err::read_to_string(path) else {
-                                return;
-                            };
-                            let path = path.to_string_lossy();
-                            yolk.eval_template(&mut eval_ctx, &path, &content)
-                                .map(|_| ())
-                        } else {
-                            yolk.sync_template_file(&mut eval_ctx, path)
-                        };
-                        if let Err(e) = result {
-                            eprintln!("Error: {e:?}");
-                        }
-                    };
-
                     match debounce_event_res {
                         Ok(events) => {
                             let changed = events
@@ -555,15 +756,25 @@ fn run_command(args: Args) -> Result<()> {
                                 .flat_map(|x| x.paths.clone().into_iter())
                                 .collect::<HashSet<_>>();
                             // If yolk.rhai changed, we need to re-evaluate all files.
-                            // This means either just sync_to_mode, or, if no_sync is set, manually calling on_file_updated for each file.
+                            // This means either just sync_to_mode, or, if no_sync is set, evaluating every watched template.
                             if changed.contains(&yolk.paths().yolk_rhai_path()) {
                                 if no_sync {
-                                    files_to_watch.iter().for_each(|file| on_file_updated(file));
+                                    if let Err(e) =
+                                        yolk.eval_template_files(&mut eval_ctx, &files_to_watch)
+                                    {
+                                        eprintln!("Error: {e:?}");
+                                    }
                                 } else if let Err(e) = yolk.sync_to_mode(mode, true) {
                                     eprintln!("Error: {e:?}");
                                 }
-                            } else {
-                                changed.iter().for_each(|path| on_file_updated(path));
+                            } else if no_sync {
+                                if let Err(e) = yolk.eval_template_files(&mut eval_ctx, &changed) {
+                                    eprintln!("Error: {e:?}");
+                                }
+                            } else if let Err(e) =
+                                yolk.sync_template_files(&mut eval_ctx, mode, &changed)
+                            {
+                                eprintln!("Error: {e:?}");
                             }
                         }
                         Err(error) => tracing::error!("Error: {error:?}"),
@@ -590,17 +801,22 @@ fn run_command(args: Args) -> Result<()> {
         Command::RootManageSymlinks {
             create_symlink,
             delete_symlink,
+            set_permissions,
         } => {
             let mut tracker = Deployer::new();
+            // Delete first, so symlinks that are being replaced can be recreated in the same run.
+            for symlink_path in delete_symlink {
+                tracker.delete_symlink(symlink_path)?;
+            }
             for (original_path, symlink_path) in create_symlink {
                 tracker.create_symlink(original_path, symlink_path)?;
             }
-            for symlink_path in delete_symlink {
-                tracker.delete_symlink(symlink_path)?;
+            for (path, permissions) in set_permissions {
+                tracker.set_permissions(path, permissions)?;
             }
             if tracker.has_pending_elevated_operations() {
                 miette::bail!(
-                    help = "The elevated helper still did not have permission to complete these symlink operations.",
+                    help = "The elevated helper still did not have permission to complete these operations.",
                     "Failed to manage symlinks: {}",
                     tracker.pending_elevated_operations_summary()
                 );
@@ -0,0 +1,236 @@
+//! The deployment manifest, which records everything yolk deployed.
+//!
+//! The manifest is stored as JSON in `.deployment_manifest.json` inside the yolk directory.
+//! It is used to find stale symlinks and copies that need to be cleaned up,
+//! to detect modified copies, and to report the deployment state.
+//!
+//! Older versions of yolk kept a plain list of symlinks per egg in `.deployed_cache/`.
+//! Those caches are migrated into the manifest automatically when it is loaded.
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use miette::{Context as _, IntoDiagnostic as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    eggs_config::DeploymentStrategy,
+    util::{self, PathExt as _},
+};
+
+/// The current version of the manifest format.
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentManifest {
+    pub version: u32,
+    #[serde(default)]
+    pub eggs: BTreeMap<String, Vec<ManifestEntry>>,
+}
+
+/// A single file or directory that was deployed by yolk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The deployed path, i.e. the symlink or the copied file.
+    pub path: PathBuf,
+    /// The path inside the egg that was deployed.
+    pub source: PathBuf,
+    /// The strategy the entry was deployed with.
+    /// This is `None` for entries migrated from the old deployment cache, which did not record it.
+    pub strategy: Option<DeploymentStrategy>,
+    /// Whether deploying this entry required elevated privileges.
+    #[serde(default)]
+    pub elevated: bool,
+    /// For copies, the hash of the content at the time it was copied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Whether this is a directory that yolk created to deploy the files of a directory inside the egg into,
+    /// rather than symlinking the whole directory.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub directory: bool,
+    /// Unix timestamp of when this entry was first deployed.
+    pub deployed_at: u64,
+}
+
+impl ManifestEntry {
+    pub fn symlink(path: PathBuf, source: PathBuf, strategy: DeploymentStrategy) -> Self {
+        Self {
+            path,
+            source,
+            strategy: Some(strategy),
+            elevated: false,
+            hash: None,
+            directory: false,
+            deployed_at: now(),
+        }
+    }
+
+    /// A directory that was created by yolk to deploy the contents of the `source` directory into.
+    pub fn directory(path: PathBuf, source: PathBuf, strategy: DeploymentStrategy) -> Self {
+        Self {
+            directory: true,
+            ..Self::symlink(path, source, strategy)
+        }
+    }
+
+    pub fn copy(path: PathBuf, source: PathBuf, hash: String) -> Self {
+        Self {
+            path,
+            source,
+            strategy: Some(DeploymentStrategy::Copy),
+            elevated: false,
+            hash: Some(hash),
+            directory: false,
+            deployed_at: now(),
+        }
+    }
+
+    pub fn with_elevated(mut self, elevated: bool) -> Self {
+        self.elevated = elevated;
+        self
+    }
+}
+
+impl Default for DeploymentManifest {
+    fn default() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            eggs: BTreeMap::new(),
+        }
+    }
+}
+
+impl DeploymentManifest {
+    /// Load the manifest from the given path, migrating the old deployment cache directory if there is no manifest yet.
+    pub fn load(path: impl AsRef<Path>, legacy_cache_dir: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::migrate_legacy_cache(legacy_cache_dir.as_ref());
+        }
+        let content = fs_err::read_to_string(path).into_diagnostic()?;
+        let manifest: Self = serde_json::from_str(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse deployment manifest {}", path.abbr()))?;
+        if manifest.version > MANIFEST_VERSION {
+            miette::bail!(
+                help = "Please update yolk.",
+                "Deployment manifest {} was written by a newer version of yolk (format version {})",
+                path.abbr(),
+                manifest.version
+            );
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest to the given path, removing the old deployment cache directory if it still exists.
+    pub fn save(&self, path: impl AsRef<Path>, legacy_cache_dir: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(self).into_diagnostic()?;
+        util::write_atomic(path, content)
+            .wrap_err_with(|| format!("Failed to update deployment manifest {}", path.abbr()))?;
+        let legacy_cache_dir = legacy_cache_dir.as_ref();
+        if legacy_cache_dir.is_dir() {
+            tracing::info!("Migrated {} to {}", legacy_cache_dir.abbr(), path.abbr());
+            fs_err::remove_dir_all(legacy_cache_dir).into_diagnostic()?;
+        }
+        Ok(())
+    }
+
+    /// All entries recorded for the given egg.
+    pub fn entries(&self, egg_name: &str) -> &[ManifestEntry] {
+        self.eggs
+            .get(egg_name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The files deployed as copies for the given egg, together with the hash of their content at the time of copying.
+    pub fn copies(&self, egg_name: &str) -> HashMap<PathBuf, String> {
+        self.entries(egg_name)
+            .iter()
+            .filter_map(|entry| Some((entry.path.clone(), entry.hash.clone()?)))
+            .collect()
+    }
+
+    /// Replace the entries of the given egg.
+    ///
+    /// Entries that were already recorded for the same path and source keep their original timestamp.
+    pub fn set_entries(&mut self, egg_name: &str, mut entries: Vec<ManifestEntry>) {
+        let previous = self.entries(egg_name);
+        for entry in &mut entries {
+            if let Some(old) = previous
+                .iter()
+                .find(|old| old.path == entry.path && old.source == entry.source)
+            {
+                entry.deployed_at = old.deployed_at;
+            }
+        }
+        if entries.is_empty() {
+            self.eggs.remove(egg_name);
+        } else {
+            self.eggs.insert(egg_name.to_string(), entries);
+        }
+    }
+
+    /// Remove the entry for `path` from the entries of the given egg.
+    pub fn remove_entry(&mut self, egg_name: &str, path: &Path) {
+        if let Some(entries) = self.eggs.get_mut(egg_name) {
+            entries.retain(|entry| entry.path != path);
+            if entries.is_empty() {
+                self.eggs.remove(egg_name);
+            }
+        }
+    }
+
+    /// Remove all entries for `path`, regardless of which egg they belong to.
+    /// Returns whether any entry was removed.
+    pub fn remove_path(&mut self, path: &Path) -> bool {
+        let before = self.eggs.values().map(Vec::len).sum::<usize>();
+        for entries in self.eggs.values_mut() {
+            entries.retain(|entry| entry.path != path);
+        }
+        self.eggs.retain(|_, entries| !entries.is_empty());
+        before != self.eggs.values().map(Vec::len).sum::<usize>()
+    }
+
+    /// Build a manifest from the old `.deployed_cache` directory, which contained a `<egg>` file listing the symlinks of each egg.
+    fn migrate_legacy_cache(legacy_cache_dir: &Path) -> Result<Self> {
+        let mut manifest = Self::default();
+        if !legacy_cache_dir.is_dir() {
+            return Ok(manifest);
+        }
+        cov_mark::hit!(migrate_legacy_deployment_cache);
+        let timestamp = now();
+        for entry in fs_err::read_dir(legacy_cache_dir).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            let egg_name = entry.file_name().to_string_lossy().to_string();
+            let content = fs_err::read_to_string(entry.path()).into_diagnostic()?;
+            for line in content.lines().filter(|line| !line.is_empty()) {
+                let path = PathBuf::from(line);
+                let source = fs_err::read_link(&path).unwrap_or_default();
+                manifest
+                    .eggs
+                    .entry(egg_name.clone())
+                    .or_default()
+                    .push(ManifestEntry {
+                        path,
+                        source,
+                        strategy: None,
+                        elevated: false,
+                        hash: None,
+                        directory: false,
+                        deployed_at: timestamp,
+                    });
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
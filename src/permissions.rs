@@ -0,0 +1,257 @@
+//! File modes and ownership that should be applied to deployed files.
+//!
+//! Permissions are only supported on unix. On other platforms, they are ignored.
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use miette::{IntoDiagnostic as _, Result};
+
+/// The owner of a file, given as user and optionally group, either by name or by numeric id.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Owner {
+    pub user: String,
+    pub group: Option<String>,
+}
+
+impl FromStr for Owner {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        if user.is_empty() || group.is_some_and(str::is_empty) {
+            miette::bail!(
+                help = "owner must be given as `user` or `user:group`",
+                "Invalid owner {}",
+                s
+            );
+        }
+        Ok(Owner {
+            user: user.to_string(),
+            group: group.map(str::to_string),
+        })
+    }
+}
+
+impl Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.group {
+            Some(group) => write!(f, "{}:{}", self.user, group),
+            None => write!(f, "{}", self.user),
+        }
+    }
+}
+
+/// A deployed file whose mode or owner no longer match the permissions configured for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionDrift {
+    pub egg_name: String,
+    pub path: PathBuf,
+    /// Human readable descriptions of every difference, i.e. `mode is 644 instead of 600`.
+    pub differences: Vec<String>,
+}
+
+/// The mode and owner a deployed file should have.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct FilePermissions {
+    /// The permission bits, i.e. `0o600`.
+    pub mode: Option<u32>,
+    pub owner: Option<Owner>,
+}
+
+impl FilePermissions {
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Owner) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.owner.is_none()
+    }
+
+    /// Read the current permissions of the given file, following symlinks.
+    /// The owner is given by numeric ids, so applying the result restores the file exactly.
+    #[cfg(unix)]
+    pub fn current(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+        let metadata = fs_err::metadata(path).into_diagnostic()?;
+        Ok(FilePermissions {
+            mode: Some(metadata.permissions().mode() & 0o7777),
+            owner: Some(Owner {
+                user: metadata.uid().to_string(),
+                group: Some(metadata.gid().to_string()),
+            }),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn current(_path: &Path) -> Result<Self> {
+        Ok(FilePermissions::default())
+    }
+
+    /// Describe every way in which the given file does not match these permissions.
+    /// Returns an empty list if the file matches.
+    #[cfg(unix)]
+    pub fn differences(&self, path: &Path) -> Result<Vec<String>> {
+        use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+        let metadata = fs_err::metadata(path).into_diagnostic()?;
+        let mut differences = Vec::new();
+        if let Some(mode) = self.mode {
+            let actual = metadata.permissions().mode() & 0o7777;
+            if actual != mode {
+                differences.push(format!("mode is {actual:o} instead of {mode:o}"));
+            }
+        }
+        if let Some(owner) = &self.owner {
+            let (uid, gid) = resolve_owner(owner).into_diagnostic()?;
+            if metadata.uid() != uid || gid.is_some_and(|gid| metadata.gid() != gid) {
+                differences.push(format!(
+                    "owner is {}:{} instead of {owner}",
+                    user_name(metadata.uid()),
+                    group_name(metadata.gid())
+                ));
+            }
+        }
+        Ok(differences)
+    }
+
+    #[cfg(not(unix))]
+    pub fn differences(&self, _path: &Path) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Apply these permissions to the given file, following symlinks.
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path) -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt as _;
+        if let Some(owner) = &self.owner {
+            let (uid, gid) = resolve_owner(owner)?;
+            std::os::unix::fs::chown(path, Some(uid), gid)?;
+        }
+        // Changing the owner may reset setuid bits, so the mode is applied afterwards.
+        if let Some(mode) = self.mode {
+            fs_err::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, path: &Path) -> std::io::Result<()> {
+        use crate::util::PathExt as _;
+        tracing::warn!(
+            "Setting file permissions is not supported on this platform, ignoring them for {}",
+            path.abbr()
+        );
+        Ok(())
+    }
+}
+
+impl Display for FilePermissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(mode) = self.mode {
+            parts.push(format!("mode {mode:o}"));
+        }
+        if let Some(owner) = &self.owner {
+            parts.push(format!("owner {owner}"));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Parse a file mode given in octal notation, such as `600`, `0600` or `0o600`.
+pub fn parse_mode(s: &str) -> Result<u32> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => miette::bail!(
+            help = "mode must be given in octal notation, i.e. \"600\"",
+            "Invalid file mode {}",
+            s
+        ),
+    }
+}
+
+#[cfg(unix)]
+fn resolve_owner(owner: &Owner) -> std::io::Result<(u32, Option<u32>)> {
+    let uid = match owner.user.parse::<u32>() {
+        Ok(uid) => uid,
+        Err(_) => uzers::get_user_by_name(&owner.user)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Unknown user {}", owner.user),
+                )
+            })?
+            .uid(),
+    };
+    let gid = match &owner.group {
+        None => None,
+        Some(group) => Some(match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => uzers::get_group_by_name(group)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("Unknown group {group}"),
+                    )
+                })?
+                .gid(),
+        }),
+    };
+    Ok((uid, gid))
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> String {
+    uzers::get_user_by_uid(uid)
+        .map(|user| user.name().to_string_lossy().to_string())
+        .unwrap_or_else(|| uid.to_string())
+}
+
+#[cfg(unix)]
+fn group_name(gid: u32) -> String {
+    uzers::get_group_by_gid(gid)
+        .map(|group| group.name().to_string_lossy().to_string())
+        .unwrap_or_else(|| gid.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::{parse_mode, Owner};
+
+    #[rstest]
+    #[case("600", Some(0o600))]
+    #[case("0644", Some(0o644))]
+    #[case("0o755", Some(0o755))]
+    #[case("4755", Some(0o4755))]
+    #[case("800", None)]
+    #[case("17777", None)]
+    #[case("rw", None)]
+    fn test_parse_mode(#[case] input: &str, #[case] expected: Option<u32>) {
+        assert_eq!(parse_mode(input).ok(), expected);
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        let owner: Owner = "root:wheel".parse().unwrap();
+        assert_eq!(owner.user, "root");
+        assert_eq!(owner.group.as_deref(), Some("wheel"));
+        assert_eq!(owner.to_string(), "root:wheel");
+        assert_eq!("1000".parse::<Owner>().unwrap().group, None);
+        assert!("root:".parse::<Owner>().is_err());
+        assert!(":wheel".parse::<Owner>().is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    io::{IsTerminal as _, Write as _},
+    path::PathBuf,
+};
+
+use miette::{IntoDiagnostic as _, Result};
+use yolk::{
+    deploy::{DriftResolution, DriftedCopy},
+    hook_trust::ShellHook,
+    util::PathExt as _,
+};
+
+/// Whether yolk can ask the user anything, i.e. both stdin and stdout are terminals.
+fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Ask the user a yes/no question until they answer it, returning `default` for an empty answer.
+///
+/// When not running interactively, `default` is returned without asking.
+fn prompt_yes_no(question: &str, default: bool) -> Result<bool> {
+    if !is_interactive() {
+        return Ok(default);
+    }
+    let default_label = if default { "y" } else { "n" };
+    loop {
+        print!("{question} [y/n] (default: {default_label}): ");
+        std::io::stdout().flush().into_diagnostic()?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).into_diagnostic()?;
+        match input.trim() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please enter y or n."),
+        }
+    }
+}
+
+/// Ask the user what to do with a copied file that was modified since yolk deployed it.
+///
+/// When not running interactively, the file is skipped.
+pub fn prompt_drift_resolution(drifted: &DriftedCopy) -> Result<DriftResolution> {
+    if !is_interactive() {
+        return Ok(DriftResolution::Skip);
+    }
+
+    println!(
+        "{} was modified since it was copied from {}.",
+        drifted.deployed.abbr(),
+        drifted.in_egg.abbr()
+    );
+    loop {
+        print!("Pull the changes into the egg, overwrite them, or skip? [p/o/s] (default: s): ");
+        std::io::stdout().flush().into_diagnostic()?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).into_diagnostic()?;
+        match input.trim() {
+            "p" | "pull" => return Ok(DriftResolution::Pull),
+            "o" | "overwrite" => return Ok(DriftResolution::Overwrite),
+            "" | "s" | "skip" => return Ok(DriftResolution::Skip),
+            _ => println!("Please enter p, o or s."),
+        }
+    }
+}
+
+/// Ask the user whether the files that were backed up when deploying an egg should be restored.
+///
+/// When not running interactively, the backups are kept and not restored.
+pub fn prompt_restore_backups(egg_name: &str, paths: &[PathBuf]) -> Result<bool> {
+    if !is_interactive() {
+        return Ok(false);
+    }
+    println!("Deploying egg {egg_name} backed up the following files:");
+    for path in paths {
+        println!("  {}", path.abbr());
+    }
+    prompt_yes_no("Restore them?", false)
+}
+
+/// Ask the user whether a fix suggested by `yolk doctor` that needs confirmation should be applied.
+///
+/// When not running interactively, the fix is not applied.
+pub fn prompt_apply_fix(suggestion: &str) -> Result<bool> {
+    prompt_yes_no(&format!("  {suggestion} now?"), false)
+}
+
+/// Ask the user whether a shell hook that was never trusted before may run.
+///
+/// When not running interactively, the hook is refused.
+pub fn prompt_trust_hook(hook: &ShellHook) -> Result<bool> {
+    if !is_interactive() {
+        return Ok(false);
+    }
+    println!(
+        "The {} hook of {} is new or has changed since you last trusted it:",
+        hook.hook,
+        hook.owner()
+    );
+    println!("  {}", hook.command);
+    prompt_yes_no("Trust and run it?", false)
+}
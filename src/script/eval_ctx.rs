@@ -1,9 +1,12 @@
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use miette::Result;
 use rhai::module_resolvers::FileModuleResolver;
+use rhai::Dynamic;
 use rhai::Engine;
+use rhai::FnPtr;
 use rhai::Module;
 use rhai::Scope;
 use rhai::Variant;
@@ -11,7 +14,7 @@ use rhai::Variant;
 use crate::yolk::EvalMode;
 
 use super::rhai_error::RhaiScriptError;
-use super::stdlib;
+use super::stdlib::{self, CommandRunner};
 
 pub const YOLK_TEXT_NAME: &str = "YOLK_TEXT";
 
@@ -19,13 +22,17 @@ pub const YOLK_TEXT_NAME: &str = "YOLK_TEXT";
 pub struct EvalCtx {
     engine: Engine,
     scope: Scope<'static>,
-    /// Module holding variables registered via [`Self::set_global`].
-    /// Registered on the engine as a global module so its variables are
-    /// accessible inside script-defined functions (rhai's optimizer doesn't
-    /// propagate scope constants through `lhs.rhs` expressions, but module
-    /// variables resolve at runtime).
-    globals_module: Module,
+    /// Module holding variables registered via [`Self::set_global`] and [`Self::with_scoped_global`].
+    /// Variables in it are resolved at runtime by the engine's variable resolver,
+    /// so they are accessible inside script-defined functions (rhai's optimizer doesn't
+    /// propagate scope constants through `lhs.rhs` expressions).
+    /// It is shared with the resolver, such that scoped globals can be added and removed again later on.
+    globals_module: Arc<RwLock<Module>>,
     yolk_file_module: Option<(rhai::AST, Arc<Module>)>,
+    /// The mode the standard library modules were set up for, if any.
+    mode: Option<EvalMode>,
+    /// The directory `import` statements are resolved relative to, if set.
+    module_path: Option<PathBuf>,
 }
 
 impl Default for EvalCtx {
@@ -36,16 +43,16 @@ impl Default for EvalCtx {
 
 impl EvalCtx {
     pub fn new_empty() -> Self {
-        let mut engine = Engine::new();
-        engine.set_optimization_level(rhai::OptimizationLevel::Simple);
-
-        engine.build_type::<super::sysinfo::SystemInfo>();
-        engine.build_type::<super::sysinfo::SystemInfoPaths>();
+        let globals_module = Arc::new(RwLock::new(Module::new()));
+        let mut engine = new_engine();
+        register_globals_resolver(&mut engine, globals_module.clone());
         Self {
             engine,
             scope: Scope::new(),
-            globals_module: Module::new(),
+            globals_module,
             yolk_file_module: None,
+            mode: None,
+            module_path: None,
         }
     }
 
@@ -55,16 +62,8 @@ impl EvalCtx {
     /// to determine whether to actually perform any IO or to just simulate it.
     pub fn new_in_mode(mode: EvalMode) -> Result<Self> {
         let mut ctx = Self::new_empty();
-        ctx.engine
-            .register_global_module(Arc::new(stdlib::global_stuff()));
-        ctx.engine
-            .register_static_module("utils", Arc::new(stdlib::utils_module()));
-        ctx.engine
-            .register_static_module("io", Arc::new(stdlib::io_module(mode)));
-        let template_module = Arc::new(stdlib::tag_module());
-        ctx.engine
-            .register_static_module("template", template_module);
-
+        ctx.mode = Some(mode);
+        register_stdlib(&mut ctx.engine, stdlib::io_module(mode));
         Ok(ctx)
     }
 
@@ -75,12 +74,11 @@ impl EvalCtx {
     pub fn set_module_path(&mut self, path: &Path) {
         self.engine
             .set_module_resolver(FileModuleResolver::new_with_path(path));
+        self.module_path = Some(path.to_path_buf());
     }
 
     /// Load a given rhai string as a global module, and store it as the `yolk_file_module`.
     pub fn load_rhai_file_to_module(&mut self, content: &str) -> Result<(), RhaiScriptError> {
-        self.engine
-            .register_global_module(Arc::new(self.globals_module.clone()));
         let ast = self.compile(content)?;
         let module = match Module::eval_ast_as_new(self.scope.clone(), &ast, &self.engine) {
             Ok(module) => module,
@@ -133,14 +131,93 @@ impl EvalCtx {
     /// Set a global, immutable variable that is accessible both at the top level
     /// of yolk.rhai and from inside script-defined functions.
     pub fn set_global<T: Variant + Clone>(&mut self, name: &str, value: T) {
-        self.globals_module.set_var(name, value.clone());
+        self.globals_module
+            .write()
+            .unwrap()
+            .set_var(name, value.clone());
         self.scope.push_constant(name, value);
     }
 
+    /// Run the given closure with an additional global, immutable variable.
+    ///
+    /// Like with [`Self::set_global`], the variable is accessible from inside script-defined functions.
+    /// Once the closure returns, the variable is removed again,
+    /// or restored to its previous value if a global with the same name already existed.
+    pub fn with_scoped_global<T: Variant + Clone, R>(
+        &mut self,
+        name: &str,
+        value: T,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let previous = self.globals_module.read().unwrap().get_var(name);
+        self.globals_module
+            .write()
+            .unwrap()
+            .set_var(name, value.clone());
+        let scope_before = self.scope.len();
+        self.scope.push_constant(name.to_string(), value);
+        let result = f(self);
+        self.scope.rewind(scope_before);
+        let mut globals = self.globals_module.write().unwrap();
+        if let Some(previous) = previous {
+            globals.set_var(name, previous);
+        } else {
+            // rhai modules don't support removing variables, so rebuild the module without it
+            let mut without_var = Module::new();
+            for (var_name, value) in globals.iter_var().filter(|(n, _)| *n != name) {
+                without_var.set_var(var_name, value.clone());
+            }
+            *globals = without_var;
+        }
+        result
+    }
+
     pub fn engine_mut(&mut self) -> &mut Engine {
         &mut self.engine
     }
 
+    /// Call a hook, i.e. a function pointer or closure that was defined in the yolk.rhai file.
+    ///
+    /// `arg` is only passed if the function takes an argument, so both `|| ...` and `|x| ...` closures can be called.
+    ///
+    /// Hooks are called with a separate engine, which additionally provides `io::run`.
+    /// This way, neither the top level of the yolk.rhai file nor template tags can run programs.
+    /// Every program a hook runs is passed to `run_command`.
+    pub fn call_hook(
+        &self,
+        f: &FnPtr,
+        arg: Dynamic,
+        run_command: Arc<CommandRunner>,
+        deadline: Instant,
+    ) -> Result<Dynamic, RhaiScriptError> {
+        let Some((ast, yolk_file_module)) = self.yolk_file_module.as_ref() else {
+            return Err(RhaiScriptError::msg(
+                "Tried to call a function before loading the yolk file",
+            ));
+        };
+        let mut engine = new_engine();
+        if let Some(mode) = self.mode {
+            let mut io_module = stdlib::io_module(mode);
+            stdlib::register_io_run(&mut io_module, mode, run_command);
+            register_stdlib(&mut engine, io_module);
+        }
+        if let Some(path) = &self.module_path {
+            engine.set_module_resolver(FileModuleResolver::new_with_path(path));
+        }
+        register_globals_resolver(&mut engine, self.globals_module.clone());
+        engine.register_global_module(yolk_file_module.clone());
+        engine.on_progress(move |_| (Instant::now() >= deadline).then(|| "timed out".into()));
+        let arity = ast
+            .iter_functions()
+            .find(|def| def.name == f.fn_name())
+            .map(|def| def.params.len().saturating_sub(f.curry().len()));
+        let result = match arity {
+            Some(0) => f.call::<Dynamic>(&engine, ast, ()),
+            _ => f.call::<Dynamic>(&engine, ast, (arg,)),
+        };
+        result.map_err(|err| RhaiScriptError::from_rhai_with_engine("", *err, &engine))
+    }
+
     pub fn yolk_file_module(&self) -> Option<&(rhai::AST, Arc<Module>)> {
         self.yolk_file_module.as_ref()
     }
@@ -151,3 +228,32 @@ impl EvalCtx {
             .map_err(|e| RhaiScriptError::from_rhai_compile(text, e))
     }
 }
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_optimization_level(rhai::OptimizationLevel::Simple);
+    engine.build_type::<super::sysinfo::SystemInfo>();
+    engine.build_type::<super::sysinfo::SystemInfoPaths>();
+    engine
+}
+
+/// Resolve variables that aren't defined in the current scope from the given globals module.
+///
+/// Unlike a registered global module, the module can still be changed after registering it.
+fn register_globals_resolver(engine: &mut Engine, globals: Arc<RwLock<Module>>) {
+    #[allow(deprecated)]
+    engine.on_var(move |name, _, ctx| {
+        if ctx.scope().contains(name) {
+            return Ok(None);
+        }
+        Ok(globals.read().unwrap().get_var(name))
+    });
+}
+
+/// Register yolk's standard library on the given engine, using the given `io` module.
+fn register_stdlib(engine: &mut Engine, io_module: Module) {
+    engine.register_global_module(Arc::new(stdlib::global_stuff()));
+    engine.register_static_module("utils", Arc::new(stdlib::utils_module()));
+    engine.register_static_module("io", Arc::new(io_module));
+    engine.register_static_module("template", Arc::new(stdlib::tag_module()));
+}
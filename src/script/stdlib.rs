@@ -1,7 +1,7 @@
 use miette::{IntoDiagnostic, Result};
 use rhai::{Dynamic, EvalAltResult, ImmutableString, Map, NativeCallContext};
 use rhai::{FuncRegistration, Module};
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use regex::Regex;
 
@@ -253,6 +253,37 @@ pub fn io_module(eval_mode: EvalMode) -> Module {
     module
 }
 
+/// Callback that runs a program with the given arguments on behalf of a hook, and returns its exit code.
+pub type CommandRunner = dyn Fn(Vec<String>) -> Result<i64, String> + Send + Sync;
+
+/// Add the `run` function to the given `io` module, which runs programs through the given `run_command` callback.
+///
+/// This is only available to hooks, see [`super::eval_ctx::EvalCtx::call_hook`].
+pub fn register_io_run(module: &mut Module, eval_mode: EvalMode, run_command: Arc<CommandRunner>) {
+    let run = move |args: rhai::Array| -> RhaiFnResult<i64> {
+        if_canonical_return!(eval_mode, 0);
+        let args = args
+            .into_iter()
+            .map(|x| {
+                x.into_string()
+                    .map_err(|t| format!("command argument must be a string, but got {t}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if args.is_empty() {
+            return Err("command must not be empty".into());
+        }
+        Ok(run_command(args)?)
+    };
+    FuncRegistration::new("run")
+        .with_comments([
+            "/// Run a program with the given list of arguments, without a shell, and return its exit code.",
+            "/// Only available in hooks. Like shell hooks, every command has to be trusted before it runs.",
+            "/// In canonical mode, nothing is run and 0 is returned.",
+        ])
+        .with_params_info(["args: Array", "Result<i64>"])
+        .set_into_module(module, run);
+}
+
 pub fn tag_module() -> Module {
     use indoc::indoc;
     let mut module = rhai::Module::new();
@@ -27,6 +27,28 @@ impl Default for CommentStyle {
     }
 }
 
+/// The comment style used for the tags of a document.
+#[derive(Debug, Clone, Eq, PartialEq, arbitrary::Arbitrary)]
+pub enum DocumentCommentStyle {
+    /// A comment style that was set explicitly, used for every tag in the document.
+    Fixed(CommentStyle),
+    /// The comment style is inferred for each tag from its own line,
+    /// falling back to the given style for tags where that isn't possible.
+    PerElement { fallback: CommentStyle },
+}
+
+impl DocumentCommentStyle {
+    /// Get the comment style to use when rendering the given element.
+    pub fn for_element(&self, element: &Element<'_>) -> Cow<'_, CommentStyle> {
+        match self {
+            DocumentCommentStyle::Fixed(style) => Cow::Borrowed(style),
+            DocumentCommentStyle::PerElement { fallback } => {
+                CommentStyle::try_infer(element).map_or(Cow::Borrowed(fallback), Cow::Owned)
+            }
+        }
+    }
+}
+
 // TODO: Technically, a lot of this could already be done in the parser
 // We could parse the indent, and yolk-comment-start and end stuff during the main parsing phase already
 // That would allow us to avoid having to regex here, which would potentially be noticably more performant,
@@ -83,11 +105,9 @@ impl CommentStyle {
         None
     }
 
-    #[allow(unused)]
     pub fn prefix(left: &str) -> Self {
         CommentStyle::Prefix(left.to_string())
     }
-    #[allow(unused)]
     pub fn circumfix(left: &str, right: &str) -> Self {
         CommentStyle::Circumfix(left.to_string(), right.to_string())
     }
@@ -171,7 +191,7 @@ mod test {
 
     use crate::templating::element::Element;
 
-    use super::CommentStyle;
+    use super::{CommentStyle, DocumentCommentStyle};
 
     use pretty_assertions::assert_eq;
     use rstest::rstest;
@@ -212,6 +232,27 @@ mod test {
         assert_eq!(disabled, disabled_again);
     }
 
+    #[test]
+    fn test_per_element_comment_style() -> TestResult {
+        let style = DocumentCommentStyle::PerElement {
+            fallback: CommentStyle::prefix("#"),
+        };
+        assert_eq!(
+            CommentStyle::circumfix("/*", "*/"),
+            *style.for_element(&Element::try_from_str("/* {< foo >} */")?)
+        );
+        assert_eq!(
+            CommentStyle::prefix("#"),
+            *style.for_element(&Element::try_from_str("{< foo >}")?)
+        );
+        let style = DocumentCommentStyle::Fixed(CommentStyle::prefix("!"));
+        assert_eq!(
+            CommentStyle::prefix("!"),
+            *style.for_element(&Element::try_from_str("/* {< foo >} */")?)
+        );
+        Ok(())
+    }
+
     #[rstest]
     #[case("# {< foo >}", Some(CommentStyle::prefix("#")))]
     #[case("/* {< foo >} */", Some(CommentStyle::circumfix("/*", "*/")))]
@@ -1,7 +1,9 @@
+use std::ops::Range;
+
 use crate::script::eval_ctx::EvalCtx;
 
 use super::{
-    comment_style::CommentStyle,
+    comment_style::{CommentStyle, DocumentCommentStyle},
     element::{self, render_elements},
     parser,
 };
@@ -10,7 +12,7 @@ use miette::Result;
 
 #[derive(Debug, arbitrary::Arbitrary)]
 pub struct Document<'a> {
-    comment_style: CommentStyle,
+    comment_style: DocumentCommentStyle,
     elements: Vec<element::Element<'a>>,
     source: &'a str,
     source_name: String,
@@ -25,12 +27,51 @@ impl<'a> Document<'a> {
 
     #[cfg(test)]
     pub fn parse_string(s: &'a str) -> Result<Self> {
-        Self::parse_string_named("unnamed", s)
+        Self::parse_string_named("unnamed", s, None)
+    }
+
+    /// Get the span of the tag line of the innermost tagged element that contains the given byte offset.
+    pub fn tag_line_at(&self, offset: usize) -> Option<Range<usize>> {
+        let contains = |element: &&element::Element<'_>| {
+            let range = element.full_span().range();
+            range.start <= offset && offset < range.end
+        };
+        let mut found = None;
+        let mut elements = self.elements.iter().collect::<Vec<_>>();
+        while let Some(element) = elements.into_iter().find(contains) {
+            if let Some(line) = element.tagged_line() {
+                found = Some(line.full_line.range());
+            }
+            elements = element.children();
+        }
+        found
+    }
+
+    /// Check whether the document contains any yolk tags, rather than only plain text.
+    pub fn contains_tags(&self) -> bool {
+        self.elements
+            .iter()
+            .any(|element| !matches!(element, element::Element::Plain(_)))
     }
 
-    pub fn parse_string_named(name: &str, s: &'a str) -> Result<Self> {
-        let elements = parser::parse_document_named(name, s).map_err(miette::Report::from)?;
-        let comment_style = CommentStyle::try_infer_from_elements(&elements).unwrap_or_default();
+    /// Parse a document, honoring the `yolk:` pragma in its first line.
+    ///
+    /// The comment style is taken from the pragma if set, otherwise from the given comment style.
+    /// If neither is set, it is inferred for each tag from its own line, falling back to the first style
+    /// that can be inferred from any tag in the document.
+    pub fn parse_string_named(
+        name: &str,
+        s: &'a str,
+        comment_style: Option<CommentStyle>,
+    ) -> Result<Self> {
+        let (elements, pragma) =
+            parser::parse_document_with_pragma(name, s).map_err(miette::Report::from)?;
+        let comment_style = match pragma.comment_style.or(comment_style) {
+            Some(style) => DocumentCommentStyle::Fixed(style),
+            None => DocumentCommentStyle::PerElement {
+                fallback: CommentStyle::try_infer_from_elements(&elements).unwrap_or_default(),
+            },
+        };
         Ok(Self {
             elements,
             comment_style,
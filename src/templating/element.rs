@@ -1,7 +1,7 @@
 use crate::script::eval_ctx::EvalCtx;
 use miette::Result;
 
-use super::{comment_style::CommentStyle, error::TemplateError, parser::Sp};
+use super::{comment_style::DocumentCommentStyle, error::TemplateError, parser::Sp};
 
 /// A single, full line with a tag in it. Contains the span of the entire line.
 #[derive(Debug, Eq, PartialEq, arbitrary::Arbitrary)]
@@ -100,6 +100,35 @@ impl<'a> Element<'a> {
         parser::parse_element(s).into_diagnostic()
     }
 
+    /// Get the line of the tag of this element, or of its first tag if it consists of multiple tags.
+    pub fn tagged_line(&self) -> Option<&TaggedLine<'a>> {
+        match self {
+            Element::Plain(_) => None,
+            Element::Inline { line, .. } => Some(line),
+            Element::NextLine { tagged_line, .. } | Element::IgnoreNextLine { tagged_line, .. } => {
+                Some(tagged_line)
+            }
+            Element::MultiLine { block, .. } => Some(&block.tagged_line),
+            Element::Conditional { blocks, .. } => blocks.first().map(|block| &block.tagged_line),
+            Element::IgnoreMultiLine { start, .. } => Some(start),
+        }
+    }
+
+    /// Get the elements nested within the body of this element.
+    pub fn children(&self) -> Vec<&Element<'a>> {
+        match self {
+            Element::MultiLine { block, .. } => block.body.iter().collect(),
+            Element::Conditional {
+                blocks, else_block, ..
+            } => blocks
+                .iter()
+                .flat_map(|block| &block.body)
+                .chain(else_block.iter().flat_map(|block| &block.body))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn full_span(&self) -> &Sp<&str> {
         match self {
             Element::Plain(sp) => sp,
@@ -114,7 +143,7 @@ impl<'a> Element<'a> {
 
     pub fn render(
         &self,
-        comment_style: &CommentStyle,
+        document_comment_style: &DocumentCommentStyle,
         eval_ctx: &mut EvalCtx,
     ) -> Result<String, TemplateError> {
         match self {
@@ -124,6 +153,7 @@ impl<'a> Element<'a> {
                     let eval_result = eval_ctx
                         .eval_rhai::<bool>(expr.as_str())
                         .map_err(|e| TemplateError::from_rhai(e, expr.range()))?;
+                    let comment_style = document_comment_style.for_element(self);
                     Ok(comment_style.toggle_string(line.full_line.as_str(), eval_result))
                 }
                 TagExpr::Transform(expr) => Ok(format!(
@@ -142,7 +172,7 @@ impl<'a> Element<'a> {
                 TagExpr::If(expr) => Ok(format!(
                     "{}{}",
                     line.full_line.as_str(),
-                    &comment_style.toggle_string(
+                    &document_comment_style.for_element(self).toggle_string(
                         next_line.as_str(),
                         eval_ctx
                             .eval_rhai::<bool>(expr.as_str())
@@ -156,7 +186,7 @@ impl<'a> Element<'a> {
                 )),
             },
             Element::MultiLine { block, end, .. } => {
-                let rendered_body = render_elements(comment_style, eval_ctx, &block.body)?;
+                let rendered_body = render_elements(document_comment_style, eval_ctx, &block.body)?;
                 Ok(format!(
                     "{}{}{}",
                     block.tagged_line.full_line.as_str(),
@@ -170,6 +200,7 @@ impl<'a> Element<'a> {
                 end,
                 ..
             } => {
+                let comment_style = document_comment_style.for_element(self);
                 let mut output = String::new();
                 let mut had_true = false;
                 for block in blocks {
@@ -183,7 +214,7 @@ impl<'a> Element<'a> {
                     had_true = had_true || expr_true;
 
                     let rendered_body = if expr_true {
-                        render_elements(comment_style, eval_ctx, &block.body)?
+                        render_elements(document_comment_style, eval_ctx, &block.body)?
                     } else {
                         render_no_eval(&block.body)
                     };
@@ -192,7 +223,8 @@ impl<'a> Element<'a> {
                 }
                 if let Some(block) = else_block {
                     let expr_true = !had_true;
-                    let rendered_body = render_elements(comment_style, eval_ctx, &block.body)?;
+                    let rendered_body =
+                        render_elements(document_comment_style, eval_ctx, &block.body)?;
                     output.push_str(block.tagged_line.full_line.as_str());
                     output.push_str(&comment_style.toggle_string(&rendered_body, expr_true));
                 }
@@ -207,7 +239,7 @@ impl<'a> Element<'a> {
 }
 
 pub fn render_elements(
-    comment_style: &CommentStyle,
+    comment_style: &DocumentCommentStyle,
     eval_ctx: &mut EvalCtx,
     elements: &[Element<'_>],
 ) -> Result<String, TemplateError> {
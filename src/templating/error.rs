@@ -34,6 +34,21 @@ impl TemplateError {
     }
 }
 
+/// A template that does not survive being rendered to its canonical state and back unchanged.
+#[derive(Debug, Diagnostic, thiserror::Error)]
+#[error("{message}")]
+#[diagnostic(help(
+    "Make sure every transformation in the template can restore its local value from the canonical one"
+))]
+pub struct RoundTripError {
+    pub message: String,
+    #[source_code]
+    pub input: NamedSource<String>,
+    #[label("{label}")]
+    pub span: SourceSpan,
+    pub label: &'static str,
+}
+
 #[derive(Debug, Diagnostic, Clone, Eq, PartialEq, thiserror::Error)]
 #[error("Failed to parse yolk template file")]
 pub struct YolkParseFailure {
@@ -10,18 +10,19 @@ use winnow::{
         alt, cut_err, delimited, eof, fail, not, opt, peek, preceded, repeat, repeat_till,
         terminated, trace,
     },
-    stream::{Location, Recoverable, Stream},
+    stream::{Location, Recoverable, Stateful, Stream},
     token::{any, literal},
     LocatingSlice, Parser, RecoverableParser,
 };
 
 use super::{
+    comment_style::CommentStyle,
     element::{Block, Element, TagExpr, TaggedLine},
     error::{cx, YolkParseError, YolkParseFailure},
 };
 
 // type Input<'a> = winnow::LocatingSlice<&'a str>;
-type Input<'a> = Recoverable<LocatingSlice<&'a str>, YolkParseError>;
+type Input<'a> = Recoverable<Stateful<LocatingSlice<&'a str>, Delimiters<'a>>, YolkParseError>;
 type PResult<T> = winnow::ModalResult<T, YolkParseError>;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -66,6 +67,52 @@ impl<T> Sp<T> {
     }
 }
 
+/// The strings that surround the tags of a template.
+///
+/// Every tag starts with `open` and ends with `close`, with the character that determines the kind of tag in between,
+/// so the default delimiters result in `{< ... >}`, `{# ... #}` and `{% ... %}` tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delimiters<'a> {
+    pub open: &'a str,
+    pub close: &'a str,
+}
+
+impl Default for Delimiters<'_> {
+    fn default() -> Self {
+        Self {
+            open: "{",
+            close: "}",
+        }
+    }
+}
+
+/// The settings of a `yolk:` pragma in the first line of a template, i.e. `# yolk: comment="//" delimiters="[[ ]]"`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pragma<'a> {
+    /// The comment style set via `comment="//"` or `comment="/* */"`.
+    pub comment_style: Option<CommentStyle>,
+    /// The delimiters set via `delimiters="[[ ]]"`.
+    pub delimiters: Delimiters<'a>,
+}
+
+/// The kind of a tag, determining which characters follow and precede its delimiters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind {
+    Inline,
+    NextLine,
+    MultiLine,
+}
+
+impl TagKind {
+    fn markers(self) -> (&'static str, &'static str) {
+        match self {
+            TagKind::Inline => ("<", ">"),
+            TagKind::NextLine => ("#", "#"),
+            TagKind::MultiLine => ("%", "%"),
+        }
+    }
+}
+
 #[allow(unused)]
 pub fn parse_document(s: &str) -> Result<Vec<Element<'_>>, YolkParseFailure> {
     parse_document_named("file", s)
@@ -77,22 +124,122 @@ pub fn parse_document_named<'a>(
     name: &str,
     s: &'a str,
 ) -> Result<Vec<Element<'a>>, YolkParseFailure> {
+    Ok(parse_document_with_pragma(name, s)?.0)
+}
+
+/// Parse a document like [`parse_document_named`], honoring and returning the `yolk:` pragma in its first line, if any.
+pub fn parse_document_with_pragma<'a>(
+    name: &str,
+    s: &'a str,
+) -> Result<(Vec<Element<'a>>, Pragma<'a>), YolkParseFailure> {
+    let pragma = parse_pragma(s).map_err(|err| YolkParseFailure::from_errs(vec![err], name, s))?;
     let p = repeat(0.., p_element);
-    try_parse(p, name, s)
+    let elements = try_parse(p, name, s, pragma.delimiters)?;
+    Ok((elements, pragma))
 }
 
 #[allow(unused)]
 pub fn parse_element(s: &str) -> Result<Element<'_>, YolkParseFailure> {
     let p = terminated(p_element, repeat(0.., line_ending).map(|_: ()| ()));
-    try_parse(p, "file", s)
+    try_parse(p, "file", s, Delimiters::default())
+}
+
+/// Parse the `yolk:` pragma in the first line of the given template.
+///
+/// The pragma starts the line, after optional whitespace and a comment prefix of up to four symbols, i.e. `#`, `//` or `<!--`.
+/// It consists of `key="value"` pairs following `yolk:`, anything after them is ignored.
+/// A first line that doesn't start like that, or without such a pair, is not a pragma, and results in the default settings.
+fn parse_pragma(s: &str) -> Result<Pragma<'_>, YolkParseError> {
+    let first_line = s.split('\n').next().unwrap_or_default();
+    let first_line = first_line.strip_suffix('\r').unwrap_or(first_line);
+    let after_indent = first_line.trim_start();
+    let comment_prefix_len = after_indent
+        .find(|c: char| c.is_whitespace() || c.is_alphanumeric() || c == '_')
+        .unwrap_or(after_indent.len());
+    let after_prefix = after_indent[comment_prefix_len..].trim_start();
+    let comment_prefix = &after_indent[..comment_prefix_len];
+    if !(1..=4).contains(&comment_prefix.chars().count()) || !after_prefix.starts_with("yolk:") {
+        return Ok(Pragma::default());
+    }
+    let start = first_line.len() - after_prefix.len();
+    let error = |span: Range<usize>, message: &'static str, help: &'static str| YolkParseError {
+        message: Some(message),
+        span: Some(span.into()),
+        label: Some("here"),
+        help: Some(help),
+    };
+
+    let mut pragma = Pragma::default();
+    let mut offset = start + "yolk:".len();
+    loop {
+        let rest = &first_line[offset..];
+        let key_start = offset + (rest.len() - rest.trim_start().len());
+        let rest = &first_line[key_start..];
+        let key_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if key_len == 0 || key_start == offset || !rest[key_len..].starts_with("=\"") {
+            break;
+        }
+        let key = &rest[..key_len];
+        let value_start = key_start + key_len + 2;
+        let Some(value_len) = first_line[value_start..].find('"') else {
+            return Err(error(
+                key_start..first_line.len(),
+                "Unterminated value in yolk pragma",
+                "Values in the yolk pragma must be quoted, e.g. `comment=\"//\"`",
+            ));
+        };
+        let value = &first_line[value_start..value_start + value_len];
+        let value_span = value_start..value_start + value_len;
+        let parts = value.split_whitespace().collect::<Vec<_>>();
+        match key {
+            "comment" => {
+                pragma.comment_style = Some(match parts.as_slice() {
+                    [prefix] => CommentStyle::prefix(prefix),
+                    [left, right] => CommentStyle::circumfix(left, right),
+                    _ => {
+                        return Err(error(
+                            value_span,
+                            "Invalid comment style in yolk pragma",
+                            "Specify the start of a comment, optionally followed by its end, e.g. `comment=\"//\"` or `comment=\"/* */\"`",
+                        ))
+                    }
+                });
+            }
+            "delimiters" => {
+                let [open, close] = parts.as_slice() else {
+                    return Err(error(
+                        value_span,
+                        "Invalid delimiters in yolk pragma",
+                        "Specify the opening and closing delimiter separated by a space, e.g. `delimiters=\"[[ ]]\"`",
+                    ));
+                };
+                pragma.delimiters = Delimiters { open, close };
+            }
+            _ => {
+                return Err(error(
+                    key_start..key_start + key_len,
+                    "Unknown key in yolk pragma",
+                    "The yolk pragma supports the keys `comment` and `delimiters`",
+                ))
+            }
+        }
+        offset = value_start + value_len + 1;
+    }
+    Ok(pragma)
 }
 
 pub fn try_parse<'a, P: Parser<Input<'a>, T, YolkParseError>, T>(
     mut parser: P,
     name: &str,
     input: &'a str,
+    delimiters: Delimiters<'a>,
 ) -> Result<T, YolkParseFailure> {
-    let (_, maybe_val, errs) = parser.recoverable_parse(LocatingSlice::new(input));
+    let (_, maybe_val, errs) = parser.recoverable_parse(Stateful {
+        input: LocatingSlice::new(input),
+        state: delimiters,
+    });
     if let (Some(v), true) = (maybe_val, errs.is_empty()) {
         Ok(v)
     } else {
@@ -138,8 +285,11 @@ fn p_plain_line_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     .parse_next(input)
 }
 
-fn p_regular_tag_inner(end: &str) -> impl winnow::Parser<Input<'_>, &'_ str, YolkParseError> {
-    let p = repeat_till(1.., (not(end), any), peek(end)).map(|(_, _): ((), _)| ());
+fn p_regular_tag_inner<'a>(
+    end: TagKind,
+) -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> {
+    let p = repeat_till(1.., (not(p_tag_end(end)), any), peek(p_tag_end(end)))
+        .map(|(_, _): ((), _)| ());
     cut_err(
         p.context(
             cx().msg("Expected an expression in this tag")
@@ -151,7 +301,7 @@ fn p_regular_tag_inner(end: &str) -> impl winnow::Parser<Input<'_>, &'_ str, Yol
 
 fn p_condition_tag_inner<'a>(
     keyword: &'static str,
-    end: &'static str,
+    end: TagKind,
     message: &'static str,
 ) -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> {
     preceded(
@@ -169,7 +319,7 @@ fn p_condition_tag_inner<'a>(
 fn p_if_tag_inner<'a>() -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> {
     p_condition_tag_inner(
         "if",
-        "%}",
+        TagKind::MultiLine,
         "An if tag requires a condition, e.g. `{% if foo %}`",
     )
 }
@@ -177,13 +327,13 @@ fn p_if_tag_inner<'a>() -> impl winnow::Parser<Input<'a>, &'a str, YolkParseErro
 fn p_elif_tag_inner<'a>() -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> {
     p_condition_tag_inner(
         "elif",
-        "%}",
+        TagKind::MultiLine,
         "An elif tag requires a condition, e.g. `{% elif foo %}`",
     )
 }
 
 fn p_optional_if_tag_inner<'a>(
-    end: &'static str,
+    end: TagKind,
     message: &'static str,
 ) -> impl winnow::Parser<Input<'a>, (bool, &'a str), YolkParseError> {
     alt((
@@ -193,7 +343,7 @@ fn p_optional_if_tag_inner<'a>(
         )
         .map(|expr| (true, expr)),
         preceded(
-            (literal("if"), peek((wsp0_or_newline, literal(end)))),
+            (literal("if"), peek((wsp0_or_newline, p_tag_end(end)))),
             cut_err(fail.context(cx().msg(message).lbl("condition"))),
         ),
         p_regular_tag_inner(end).map(|expr| (false, expr)),
@@ -225,21 +375,45 @@ fn p_tag<'a, T>(
     .map(|(s, span)| Sp::new(span, s))
 }
 
+/// Matches the start of a tag of the given kind, i.e. `{%`, using the delimiters of the input.
+fn p_tag_start<'a>(
+    kind: TagKind,
+) -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> + Copy {
+    move |input: &mut Input<'a>| {
+        let open = input.state.open;
+        (literal(open), literal(kind.markers().0))
+            .take()
+            .parse_next(input)
+    }
+}
+
+/// Matches the end of a tag of the given kind, i.e. `%}`, using the delimiters of the input.
+fn p_tag_end<'a>(kind: TagKind) -> impl winnow::Parser<Input<'a>, &'a str, YolkParseError> + Copy {
+    move |input: &mut Input<'a>| {
+        let close = input.state.close;
+        (literal(kind.markers().1), literal(close))
+            .take()
+            .parse_next(input)
+    }
+}
+
 fn p_any_tag_start<'a>(input: &mut Input<'a>) -> PResult<&'a str> {
-    alt((literal("{%"), literal("{#"), literal("{<"))).parse_next(input)
+    alt((
+        p_tag_start(TagKind::MultiLine),
+        p_tag_start(TagKind::NextLine),
+        p_tag_start(TagKind::Inline),
+    ))
+    .parse_next(input)
 }
 
 /// Peeks a token boundary after a keyword: whitespace, a line ending, or the
-/// given closing delimiter. `end` is the closing delimiter of the surrounding
-/// tag (`#}` or `%}`), so a delimiter-adjacent keyword like `{#ignore#}` still
-/// counts as a whole token.
-fn peek_keyword_boundary<'a>(
-    end: &'static str,
-) -> impl winnow::Parser<Input<'a>, (), YolkParseError> {
+/// closing delimiter of the surrounding tag of the given kind, so a
+/// delimiter-adjacent keyword like `{#ignore#}` still counts as a whole token.
+fn peek_keyword_boundary<'a>(end: TagKind) -> impl winnow::Parser<Input<'a>, (), YolkParseError> {
     peek(alt((
         winnow::ascii::space1.void(),
         line_ending.void(),
-        literal(end).void(),
+        p_tag_end(end).void(),
     )))
     .void()
 }
@@ -250,7 +424,7 @@ fn peek_keyword_boundary<'a>(
 /// identifier like `ignore_color()`. Because [`p_tag`] wraps its end matcher in
 /// `cut_err`, matching a prefix here would turn into a hard failure on the
 /// closing delimiter instead of backtracking to the regular tag parsers.
-fn p_ignore_keyword<'a>(end: &'static str) -> impl winnow::Parser<Input<'a>, (), YolkParseError> {
+fn p_ignore_keyword<'a>(end: TagKind) -> impl winnow::Parser<Input<'a>, (), YolkParseError> {
     terminated(literal("ignore"), peek_keyword_boundary(end)).void()
 }
 
@@ -260,7 +434,7 @@ fn p_ignore_keyword<'a>(end: &'static str) -> impl winnow::Parser<Input<'a>, (),
 /// still allowing regular transform expressions like `{% ignore_case() %}` that
 /// merely start with one of these keywords.
 fn p_reserved_multiline_keyword<'a>(
-    end: &'static str,
+    end: TagKind,
 ) -> impl winnow::Parser<Input<'a>, (), YolkParseError> {
     terminated(
         alt(("if", "elif", "else", "end", "ignore")),
@@ -272,19 +446,18 @@ fn p_reserved_multiline_keyword<'a>(
 /// p_tag_line := <start> <p_inner> <right> (\n)?
 /// returns the parsed syntax for the full tag line and its spanned inner content.
 fn p_tag_line<'a, T>(
-    start: impl winnow::Parser<Input<'a>, &'a str, YolkParseError> + Copy,
+    kind: TagKind,
     p_inner: impl winnow::Parser<Input<'a>, T, YolkParseError>,
-    end: impl winnow::Parser<Input<'a>, &'a str, YolkParseError>,
     require_newline: bool,
 ) -> impl winnow::Parser<Input<'a>, ParsedTagLine<'a, T>, YolkParseError> {
     let left_p = repeat_till(
         0..,
         (not(line_ending), not(p_any_tag_start), any),
-        peek(start),
+        peek(p_tag_start(kind)),
     )
     .map(|((), _)| ())
     .take();
-    let tag_p = p_tag(start, p_inner, end).with_taken();
+    let tag_p = p_tag(p_tag_start(kind), p_inner, p_tag_end(kind)).with_taken();
     let right_p = move |input: &mut Input<'a>| -> PResult<&'a str> {
         if require_newline {
             (till_line_ending, line_ending.map(Some))
@@ -313,11 +486,13 @@ fn p_tag_line<'a, T>(
 
 fn p_nextline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
-    let p_inner =
-        p_optional_if_tag_inner("#}", "An if tag requires a condition, e.g. `{# if foo #}`");
+    let p_inner = p_optional_if_tag_inner(
+        TagKind::NextLine,
+        "An if tag requires a condition, e.g. `{# if foo #}`",
+    );
 
     let ((tag, next_line), full_span) = (
-        p_tag_line("{#", p_inner, "#}", true),
+        p_tag_line(TagKind::NextLine, p_inner, true),
         till_line_ending.spanned(),
     )
         .with_spanned()
@@ -334,7 +509,7 @@ fn p_nextline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
 fn p_ignore_nextline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
     let ((tag, next_line), full_span) = (
-        p_tag_line("{#", p_ignore_keyword("#}"), "#}", true),
+        p_tag_line(TagKind::NextLine, p_ignore_keyword(TagKind::NextLine), true),
         till_line_ending.spanned(),
     )
         .with_spanned()
@@ -349,9 +524,11 @@ fn p_ignore_nextline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>>
 /// Parses an inline element, including the surrounding line
 fn p_inline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
-    let p_inner =
-        p_optional_if_tag_inner(">}", "An if tag requires a condition, e.g. `{< if foo >}`");
-    let tag = p_tag_line("{<", cut_err(p_inner), ">}", false).parse_next(input)?;
+    let p_inner = p_optional_if_tag_inner(
+        TagKind::Inline,
+        "An if tag requires a condition, e.g. `{< if foo >}`",
+    );
+    let tag = p_tag_line(TagKind::Inline, cut_err(p_inner), false).parse_next(input)?;
     Ok(Element::Inline {
         line: tag.line,
         expr: spanned_tag_expr(tag.inner),
@@ -365,7 +542,7 @@ fn p_inline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
 fn p_multiline_body<'a>(
     p_end_tag_inner: impl winnow::Parser<Input<'a>, &'a str, YolkParseError>,
 ) -> impl Parser<Input<'a>, Vec<Element<'a>>, YolkParseError> {
-    let end_tag_line = peek(p_tag_line("{%", p_end_tag_inner, "%}", false));
+    let end_tag_line = peek(p_tag_line(TagKind::MultiLine, p_end_tag_inner, false));
     repeat_till(0.., p_element, end_tag_line)
         .context(
             cx().msg("Expected block to end here")
@@ -379,17 +556,16 @@ fn p_multiline_body<'a>(
 fn p_multiline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
     let p_start_tag_line = p_tag_line(
-        "{%",
+        TagKind::MultiLine,
         preceded(
-            not(p_reserved_multiline_keyword("%}")),
-            p_regular_tag_inner("%}"),
+            not(p_reserved_multiline_keyword(TagKind::MultiLine)),
+            p_regular_tag_inner(TagKind::MultiLine),
         ),
-        "%}",
         true,
     );
     let p_body = cut_err(p_multiline_body("end"));
     let p_end = cut_err(
-        p_tag_line("{%", "end", "%}", false).context(
+        p_tag_line(TagKind::MultiLine, "end", false).context(
             cx().msg("Expected block to end here")
                 .lbl("block end")
                 .hlp("Did you forget an `{% end %}` tag?"),
@@ -419,17 +595,21 @@ fn p_multiline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
 /// `{% end %}` cannot appear inside the body.
 fn p_ignore_multiline_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
-    let p_start_tag_line = p_tag_line("{%", p_ignore_keyword("%}"), "%}", true);
+    let p_start_tag_line = p_tag_line(
+        TagKind::MultiLine,
+        p_ignore_keyword(TagKind::MultiLine),
+        true,
+    );
     // Consume raw characters until we reach an `{% end %}` line.
     let p_body = repeat_till(
         0..,
-        (not(p_tag_line("{%", "end", "%}", false)), any),
-        peek(p_tag_line("{%", "end", "%}", false)),
+        (not(p_tag_line(TagKind::MultiLine, "end", false)), any),
+        peek(p_tag_line(TagKind::MultiLine, "end", false)),
     )
     .map(|((), _)| ())
     .take();
     let p_end = cut_err(
-        p_tag_line("{%", "end", "%}", false).context(
+        p_tag_line(TagKind::MultiLine, "end", false).context(
             cx().msg("Expected block to end here")
                 .lbl("block end")
                 .hlp("Did you forget an `{% end %}` tag?"),
@@ -469,19 +649,19 @@ fn p_conditional_element<'a>(input: &mut Input<'a>) -> PResult<Element<'a>> {
     peek(any).parse_next(input)?;
 
     let p_if = p_block::<&'a str>(
-        p_tag_line("{%", p_if_tag_inner(), "%}", true),
+        p_tag_line(TagKind::MultiLine, p_if_tag_inner(), true),
         p_conditional_body(),
     );
     let p_elif = p_block::<&'a str>(
-        p_tag_line("{%", p_elif_tag_inner(), "%}", true),
+        p_tag_line(TagKind::MultiLine, p_elif_tag_inner(), true),
         p_conditional_body(),
     );
     let p_else = p_block(
-        p_tag_line("{%", "else".void(), "%}", true)
+        p_tag_line(TagKind::MultiLine, "else".void(), true)
             .context(cx().msg("Failed to parse else tag").lbl("else tag")),
         p_multiline_body("end"),
     );
-    let p_end = p_tag_line("{%", "end", "%}", false);
+    let p_end = p_tag_line(TagKind::MultiLine, "end", false);
     let ((if_body, elif_bodies, else_block, end_line), full_span): (
         (_, Vec<_>, Option<Block<'a, _>>, _),
         _,
@@ -522,7 +702,10 @@ fn wsp1(input: &mut Input<'_>) -> PResult<()> {
 #[cfg(test)]
 fn new_input(s: &str) -> Input<'_> {
     use winnow::{stream::Recoverable, LocatingSlice};
-    Recoverable::new(LocatingSlice::new(s))
+    Recoverable::new(Stateful {
+        input: LocatingSlice::new(s),
+        state: Delimiters::default(),
+    })
 }
 
 #[allow(unused)]
@@ -553,7 +736,11 @@ mod test {
         parse_document_named,
     };
 
-    use super::{new_input, p_inline_element, p_tag_line};
+    use super::{
+        new_input, p_inline_element, p_tag_line, parse_document_with_pragma, parse_pragma,
+        Delimiters, Pragma, TagKind,
+    };
+    use crate::templating::comment_style::CommentStyle;
 
     #[test]
     fn test_inline_tag() -> TestResult {
@@ -615,7 +802,9 @@ mod test {
 
     #[test]
     fn test_parse_end() -> TestResult {
-        assert_debug_snapshot!(p_tag_line("{%", "end", "%}", false).parse(new_input("a{% end %}b")));
+        assert_debug_snapshot!(
+            p_tag_line(TagKind::MultiLine, "end", false).parse(new_input("a{% end %}b"))
+        );
         Ok(())
     }
 
@@ -730,6 +919,66 @@ mod test {
         insta::assert_snapshot!(render_error(parse_document("{%if%}").unwrap_err()));
     }
 
+    #[test]
+    fn test_pragma() {
+        assert_eq!(None, parse_pragma("foo yolk: bar\n").unwrap().comment_style);
+        assert_eq!(
+            Delimiters::default(),
+            parse_pragma("myyolk: delimiters=\"[ ]\"")
+                .unwrap()
+                .delimiters
+        );
+        // Only a comment at the start of the line can be a pragma
+        for line in [
+            "yolk: foo=\"x\"",
+            "# see https://example.com/yolk: foo=\"x\"",
+            "# about yolk: foo=\"x\"",
+            "##### yolk: foo=\"x\"",
+        ] {
+            assert_eq!(Pragma::default(), parse_pragma(line).unwrap());
+        }
+        let pragma = parse_pragma("  // yolk: comment=\"//\"").unwrap();
+        assert_eq!(Some(CommentStyle::prefix("//")), pragma.comment_style);
+        let pragma =
+            parse_pragma("-- yolk: comment=\"--\" delimiters=\"<< >>\" rest\r\nfoo").unwrap();
+        assert_eq!(Some(CommentStyle::prefix("--")), pragma.comment_style);
+        assert_eq!(
+            Delimiters {
+                open: "<<",
+                close: ">>"
+            },
+            pragma.delimiters
+        );
+        let pragma = parse_pragma("<!-- yolk: comment=\"<!-- -->\" -->").unwrap();
+        assert_eq!(
+            Some(CommentStyle::circumfix("<!--", "-->")),
+            pragma.comment_style
+        );
+    }
+
+    #[test]
+    fn test_pragma_delimiters() -> TestResult {
+        let (elements, _) = parse_document_with_pragma(
+            "file",
+            "# yolk: delimiters=\"[[ ]]\"\n{% foo %}\n[[% if a %]]\nb\n[[% end %]]\n",
+        )?;
+        assert_debug_snapshot!(elements);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_invalid_pragma() {
+        insta::assert_snapshot!(render_error(
+            parse_document("# yolk: delimiters=\"[[\"").unwrap_err()
+        ));
+        insta::assert_snapshot!(render_error(
+            parse_document("# yolk: colour=\"red\"").unwrap_err()
+        ));
+        insta::assert_snapshot!(render_error(
+            parse_document("# yolk: comment=\"//").unwrap_err()
+        ));
+    }
+
     #[test]
     fn test_error_uses_source_name() {
         let rendered = render_error(parse_document_named("real/path.yolk", "{%%}").unwrap_err());
@@ -123,6 +123,76 @@ pub fn eval_ctx() -> EvalCtx {
     "{# get_yolk_text().to_upper() #}\nfoo\n",
     "{# get_yolk_text().to_upper() #}\nFOO\n"
 )]
+#[case::per_element_comment_style(
+    indoc!{r#"
+        <!-- {% if false %} -->
+        <p>desktop</p>
+        <!-- {% end %} -->
+        <style>
+          /* {% if false %} */
+          body { color: red; }
+          /* {% end %} */
+        </style>
+        <script>
+          // {# if false #}
+          alert("hi");
+        </script>
+    "#},
+    indoc!{r#"
+        <!-- {% if false %} -->
+        <!--<yolk> <p>desktop</p>-->
+        <!-- {% end %} -->
+        <style>
+          /* {% if false %} */
+          /*<yolk> body { color: red; }*/
+          /* {% end %} */
+        </style>
+        <script>
+          // {# if false #}
+          //<yolk> alert("hi");
+        </script>
+    "#},
+)]
+#[case::per_element_comment_style_fallback(
+    indoc!{r#"
+        // {# if false #}
+        foo
+        {% if false %}
+        bar
+        {% end %}
+    "#},
+    indoc!{r#"
+        // {# if false #}
+        //<yolk> foo
+        {% if false %}
+        //<yolk> bar
+        {% end %}
+    "#},
+)]
+#[case::pragma_delimiters(
+    indoc!{r#"
+        {# yolk: delimiters="[[ ]]" #}
+        {% if x %}{{ x }}{% endif %} [[< if false >]]
+        {# [[# get_yolk_text().to_upper() #]] #}
+        foo {< not a tag >}
+    "#},
+    indoc!{r#"
+        {# yolk: delimiters="[[ ]]" #}
+        #<yolk> {% if x %}{{ x }}{% endif %} [[< if false >]]
+        {# [[# get_yolk_text().to_upper() #]] #}
+        FOO {< NOT A TAG >}
+    "#},
+)]
+#[case::pragma_comment_style(
+    indoc!{r#"
+        // yolk: comment="!"
+        foo // {< if false >}
+    "#},
+    indoc!{r#"
+        // yolk: comment="!"
+        !<yolk> foo // {< if false >}
+    "#},
+)]
 pub fn test_render(
     mut eval_ctx: EvalCtx,
     #[case] input: &str,
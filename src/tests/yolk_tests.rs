@@ -1,7 +1,9 @@
-use std::path::PathBuf;
+use std::{collections::HashSet, path::PathBuf};
 
 use crate::{
-    eggs_config::{DeploymentStrategy, ShellHooks},
+    eggs_config::{
+        DeploymentStrategy, EggFilter, GlobalHookKey, ShellHookKey, ShellHooks, TargetOptions,
+    },
     util::test_util::{TestEnv, TestResult},
     yolk::EvalMode,
 };
@@ -20,6 +22,7 @@ use pretty_assertions::assert_str_eq;
 use test_log::test;
 
 use crate::eggs_config::EggConfig;
+use miette::IntoDiagnostic as _;
 
 // fn is_direct_file(
 // ) -> AndPredicate<FileTypePredicate, NotPredicate<FileTypePredicate, Path>, Path> {
@@ -53,25 +56,18 @@ fn test_egg_post_deploy_hooks() -> TestResult {
         .with_strategy(DeploymentStrategy::Put)
         .with_target("foo.toml", env.home_file("foo.toml"))
         .with_unsafe_hooks(ShellHooks {
-            post_deploy: Some(format!("touch {}/post_deploy_ran", env.home.display())),
-            post_undeploy: Some(format!("touch {}/post_undeploy_ran", env.home.display())),
-            pre_deploy: Some(format!("touch {}/pre_deploy_ran", env.home.display())),
-            pre_undeploy: Some(format!("touch {}/pre_undeploy_ran", env.home.display())),
+            post_deploy: Some(format!("touch {}/post_deploy_ran", env.home.display()).into()),
+            post_undeploy: Some(format!("touch {}/post_undeploy_ran", env.home.display()).into()),
+            pre_deploy: Some(format!("touch {}/pre_deploy_ran", env.home.display()).into()),
+            pre_undeploy: Some(format!("touch {}/pre_undeploy_ran", env.home.display()).into()),
+            post_template_change: None,
         });
     let egg_again = egg.clone().with_unsafe_hooks(ShellHooks {
-        post_deploy: Some(format!(
-            "touch {}/post_deploy_ran_again",
-            env.home.display()
-        )),
-        post_undeploy: Some(format!(
-            "touch {}/post_undeploy_ran_again",
-            env.home.display()
-        )),
-        pre_deploy: Some(format!("touch {}/pre_deploy_ran_again", env.home.display())),
-        pre_undeploy: Some(format!(
-            "touch {}/pre_undeploy_ran_again",
-            env.home.display()
-        )),
+        post_deploy: Some(format!("touch {}/post_deploy_ran_again", env.home.display()).into()),
+        post_undeploy: Some(format!("touch {}/post_undeploy_ran_again", env.home.display()).into()),
+        pre_deploy: Some(format!("touch {}/pre_deploy_ran_again", env.home.display()).into()),
+        pre_undeploy: Some(format!("touch {}/pre_undeploy_ran_again", env.home.display()).into()),
+        post_template_change: None,
     });
     env.yolk()
         .sync_egg_deployment(&env.open_egg("foo", egg.clone())?)?;
@@ -205,6 +201,30 @@ fn test_moving_put_deploy_cleans_up_old_symlinks() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn test_resync_only_cleans_up_changed_deployments() -> TestResult {
+    cov_mark::check_count!(skip_cleanup_of_unchanged_deployment, 1);
+    cov_mark::check_count!(delete_stale_symlink, 1);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("foo/bar.toml").write_str("")?;
+    let config = EggConfig::default()
+        .with_target("foo.toml", env.home_file("foo.toml"))
+        .with_target("bar.toml", env.home_file("bar.toml"));
+    let mut egg = env.open_egg("foo", config.clone())?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.yolk().sync_egg_deployment(&egg)?;
+
+    *egg.config_mut() =
+        config.with_target_options("bar.toml", TargetOptions::default().with_enabled(false));
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("foo.toml").assert(is_symlink());
+    env.home_file("bar.toml").assert(exists().not());
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert_eq!(manifest.entries("foo").len(), 1);
+    Ok(())
+}
+
 #[test]
 #[cfg(not(windows))]
 fn test_failed_deploy_does_not_cleanup_previous_successful_deploy() -> TestResult {
@@ -219,11 +239,83 @@ fn test_failed_deploy_does_not_cleanup_previous_successful_deploy() -> TestResul
     assert!(env.yolk().sync_egg_deployment(&egg).is_err());
 
     env.home_file("old.toml").assert(is_symlink());
-    let cached_deployments = fs_err::read_to_string(env.yolk_file(".deployed_cache/foo"))?;
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    let deployed_paths = manifest
+        .entries("foo")
+        .iter()
+        .map(|entry| entry.path.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        deployed_paths,
+        vec![env.home_file("old.toml").to_path_buf()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_deployment_manifest_records_deployments() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("bar/bar.toml").write_str("bar")?;
+    env.yolk().sync_egg_deployment(
+        &env.open_egg("foo", EggConfig::new("foo.toml", env.home_file("foo.toml")))?,
+    )?;
+    env.yolk().sync_egg_deployment(
+        &env.open_egg(
+            "bar",
+            EggConfig::new("bar.toml", env.home_file("bar.toml"))
+                .with_strategy(DeploymentStrategy::Copy),
+        )?,
+    )?;
+
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert_eq!(manifest.version, crate::manifest::MANIFEST_VERSION);
+    let [foo] = manifest.entries("foo") else {
+        panic!("Expected a single entry for foo: {manifest:?}");
+    };
+    assert_eq!(foo.path, env.home_file("foo.toml").to_path_buf());
+    assert_eq!(foo.source, fs_err::read_link(env.home_file("foo.toml"))?);
+    assert_eq!(foo.strategy, Some(DeploymentStrategy::Put));
+    assert!(!foo.elevated && foo.hash.is_none());
+    let [bar] = manifest.entries("bar") else {
+        panic!("Expected a single entry for bar: {manifest:?}");
+    };
+    assert_eq!(bar.strategy, Some(DeploymentStrategy::Copy));
     assert_eq!(
-        cached_deployments,
-        env.home_file("old.toml").display().to_string()
+        bar.hash,
+        Some(crate::util::file_content_hash(env.home_file("bar.toml"))?)
     );
+    assert!(env.yolk().find_missing_deployments()?.is_empty());
+    fs_err::remove_file(env.home_file("bar.toml"))?;
+    let missing = env.yolk().find_missing_deployments()?;
+    assert_eq!(missing, vec![("bar".to_string(), bar.clone())]);
+
+    env.yolk().sync_egg_deployment(&env.open_egg(
+        "foo",
+        EggConfig::new("foo.toml", env.home_file("foo.toml")).with_enabled(false),
+    )?)?;
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert!(manifest.entries("foo").is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_legacy_deployment_cache_is_migrated() -> TestResult {
+    cov_mark::check!(migrate_legacy_deployment_cache);
+    cov_mark::check!(delete_stale_symlink);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    symlink::symlink_file(env.egg_file("foo/foo.toml"), env.home_file("old.toml"))?;
+    env.yolk_file(".deployed_cache/foo")
+        .write_str(&env.home_file("old.toml").display().to_string())?;
+
+    env.yolk().sync_egg_deployment(
+        &env.open_egg("foo", EggConfig::new("foo.toml", env.home_file("new.toml")))?,
+    )?;
+    env.home_file("old.toml").assert(exists().not());
+    env.home_file("new.toml").assert(is_symlink());
+    env.yolk_file(".deployed_cache").assert(exists().not());
+    env.yolk_file(".deployment_manifest.json").assert(exists());
     Ok(())
 }
 
@@ -737,3 +829,1572 @@ pub fn test_only_active_sections_get_evaluated() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn test_sync_dry_run_does_not_touch_filesystem() -> TestResult {
+    let mut env = TestEnv::init()?;
+    env.yolk_rhai().write_str(&format!(
+        indoc::indoc! {r#"
+            export let eggs = #{{
+                foo: #{{
+                    targets: `~/foo.toml`,
+                    templates: ["foo.toml"],
+                    unsafe_shell_hooks: #{{ post_deploy: "touch {}/hook_ran" }},
+                }},
+            }};
+        "#},
+        env.home.display()
+    ))?;
+    env.egg_file("foo/foo.toml")
+        .write_str("foo # {< if false >}")?;
+    env.yolk.set_dry_run(true);
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert(exists().not());
+    env.home_file("hook_ran").assert(exists().not());
+    env.yolk_file(".deployment_manifest.json")
+        .assert(exists().not());
+    env.egg_file("foo/foo.toml").assert("foo # {< if false >}");
+
+    env.yolk.set_dry_run(false);
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert(is_symlink());
+    env.home_file("hook_ran").assert(exists());
+    env.egg_file("foo/foo.toml")
+        .assert("#<yolk> foo # {< if false >}");
+    Ok(())
+}
+
+#[test]
+fn test_deploy_copy_mode() -> TestResult {
+    cov_mark::check!(copy_egg_changed);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("foo")?;
+    env.egg_file("foo/thing/thing.toml").write_str("thing")?;
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new(".", env.home_file("foo")).with_strategy(DeploymentStrategy::Copy),
+    )?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("foo").assert(is_symlink().not());
+    env.home_file("foo/foo.toml").assert("foo");
+    env.home_file("foo/thing/thing.toml").assert("thing");
+    assert!(egg.is_deployed()?);
+
+    env.egg_file("foo/foo.toml").write_str("changed in egg")?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("foo/foo.toml").assert("changed in egg");
+    assert!(env.yolk().find_drifted_copies()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_copy_mode_drift() -> TestResult {
+    use crate::deploy::DriftResolution;
+    cov_mark::check_count!(copy_drifted, 3);
+    let mut env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{ targets: #{ "foo.toml": "~/foo.toml" }, strategy: "copy" },
+        };
+    "#})?;
+    env.egg_file("foo/foo.toml").write_str("original")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert("original");
+
+    env.home_file("foo.toml").write_str("modified")?;
+    let drifted = env.yolk().find_drifted_copies()?;
+    assert_eq!(drifted.len(), 1);
+    assert_eq!(drifted[0].deployed, env.home_file("foo.toml").to_path_buf());
+
+    // By default, modified copies are left alone
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert("modified");
+    env.egg_file("foo/foo.toml").assert("original");
+
+    env.yolk
+        .set_drift_handler(|_| Ok(DriftResolution::Overwrite));
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert("original");
+
+    env.home_file("foo.toml").write_str("modified again")?;
+    env.yolk.set_drift_handler(|_| Ok(DriftResolution::Pull));
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.egg_file("foo/foo.toml").assert("modified again");
+    assert!(env.yolk().find_drifted_copies()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_undeploy_copy_mode_keeps_modified_copies() -> TestResult {
+    cov_mark::check_count!(delete_stale_copy, 1);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/a.toml").write_str("a")?;
+    env.egg_file("foo/b.toml").write_str("b")?;
+    let config = EggConfig::new(".", env.home_file("foo")).with_strategy(DeploymentStrategy::Copy);
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config.clone())?)?;
+    env.home_file("foo/b.toml").write_str("modified")?;
+
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config.with_enabled(false))?)?;
+    env.home_file("foo/a.toml").assert(exists().not());
+    env.home_file("foo/b.toml").assert("modified");
+    Ok(())
+}
+
+#[test]
+fn test_put_conflict_is_backed_up_and_restored() -> TestResult {
+    use crate::eggs_config::ConflictPolicy;
+    cov_mark::check!(backup_conflicting_file);
+    cov_mark::check!(restore_backup);
+    let mut env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("from egg")?;
+    env.home_file("foo.toml").write_str("existing")?;
+    let config = EggConfig::new("foo.toml", env.home_file("foo.toml"));
+
+    assert!(env
+        .yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config.clone())?)
+        .is_err());
+    env.home_file("foo.toml").assert("existing");
+
+    let config = config.with_on_conflict(ConflictPolicy::Backup);
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config.clone())?)?;
+    env.home_file("foo.toml").assert(is_symlink());
+    let backups = env.yolk().paths().egg_backups("foo").list()?;
+    assert_eq!(backups.len(), 1);
+    assert_eq!(
+        backups[0].paths,
+        vec![env.home_file("foo.toml").to_path_buf()]
+    );
+    assert_str_eq!(
+        fs_err::read_to_string(backups[0].backup_path_of(env.home_file("foo.toml")))?,
+        "existing"
+    );
+
+    env.yolk.set_restore_handler(|_, _| Ok(true));
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config.with_enabled(false))?)?;
+    env.home_file("foo.toml").assert("existing");
+    assert!(env.yolk().paths().egg_backups("foo").list()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_failed_deploy_is_rolled_back() -> TestResult {
+    cov_mark::check!(rollback_deployment);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/a.toml").write_str("a")?;
+    env.egg_file("foo/b.toml").write_str("b")?;
+    env.home_file("b.toml").write_str("existing")?;
+    let config = EggConfig::default()
+        .with_target("a.toml", env.home_file("new/dir/a.toml"))
+        .with_target("b.toml", env.home_file("b.toml"));
+
+    assert!(env
+        .yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config)?)
+        .is_err());
+    env.home_file("new").assert(exists().not());
+    env.home_file("b.toml").assert("existing");
+    Ok(())
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_rollback_restores_mode_of_removed_dir() -> TestResult {
+    use std::os::unix::fs::PermissionsExt as _;
+    let env = TestEnv::init()?;
+    let dir = env.home_file("private");
+    dir.create_dir_all()?;
+    fs_err::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    let mut deployer = crate::deploy::Deployer::new();
+    assert!(deployer.remove_dir_if_empty(&dir)?);
+    dir.assert(exists().not());
+    deployer.rollback()?;
+    dir.assert(is_dir());
+    assert_eq!(fs_err::metadata(&dir)?.permissions().mode() & 0o7777, 0o700);
+    Ok(())
+}
+
+#[test]
+fn test_rollback_restores_backed_up_files() -> TestResult {
+    use crate::eggs_config::ConflictPolicy;
+    cov_mark::check!(rollback_deployment);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/a.toml").write_str("a")?;
+    env.egg_file("foo/b.toml").write_str("b")?;
+    env.home_file("a.toml").write_str("existing")?;
+    // A file where a parent directory should be makes deploying b.toml fail.
+    env.home_file("blocker").write_str("")?;
+    let config = EggConfig::default()
+        .with_target("a.toml", env.home_file("a.toml"))
+        .with_target("b.toml", env.home_file("blocker/b.toml"))
+        .with_on_conflict(ConflictPolicy::Backup);
+
+    assert!(env
+        .yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config)?)
+        .is_err());
+    env.home_file("a.toml").assert(is_symlink().not());
+    env.home_file("a.toml").assert("existing");
+    assert!(env.yolk().paths().egg_backups("foo").list()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_merge_conflict_is_backed_up_with_global_policy() -> TestResult {
+    use crate::eggs_config::ConflictPolicy;
+    let mut env = TestEnv::init()?;
+    env.egg_file("bar/.config/thing.toml")
+        .write_str("from egg")?;
+    env.egg_file("bar/.config/other.toml").write_str("")?;
+    env.home_file(".config/thing.toml").write_str("existing")?;
+    env.yolk.set_conflict_policy(ConflictPolicy::Backup);
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("bar", EggConfig::new_merge(".", &env.home))?)?;
+    env.home_file(".config").assert(is_dir());
+    env.home_file(".config/thing.toml").assert(is_symlink());
+    env.home_file(".config/thing.toml").assert("from egg");
+    env.home_file(".config/other.toml").assert(is_symlink());
+
+    // Without a restore handler, backups are kept when undeploying
+    env.yolk().sync_egg_deployment(&env.open_egg(
+        "bar",
+        EggConfig::new_merge(".", &env.home).with_enabled(false),
+    )?)?;
+    env.home_file(".config/thing.toml").assert(exists().not());
+    assert_eq!(env.yolk().paths().egg_backups("bar").list()?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_backup_dirs_are_unique() -> TestResult {
+    let env = TestEnv::init()?;
+    let backups = env.yolk().paths().egg_backups("foo");
+    let first = backups.new_backup_dir();
+    fs_err::create_dir_all(&first)?;
+    let second = backups.new_backup_dir();
+    assert_ne!(first, second);
+    fs_err::create_dir_all(&second)?;
+    assert_ne!(second, backups.new_backup_dir());
+    Ok(())
+}
+
+#[test]
+fn test_doctor_finds_and_fixes_problems() -> TestResult {
+    use crate::doctor::{self, Fix};
+    cov_mark::check!(doctor_partial_deployment);
+    cov_mark::check!(doctor_dangling_symlink);
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{ targets: `~`, strategy: "merge", templates: ["missing*.toml", "broken.toml"] },
+        };
+    "#})?;
+    env.egg_file("foo/a.toml").write_str("")?;
+    env.egg_file("foo/b.toml").write_str("")?;
+    env.egg_file("foo/gone.toml").write_str("")?;
+    env.egg_file("foo/broken.toml").write_str("")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+
+    env.egg_file("foo/broken.toml").write_str("{%%}")?;
+    fs_err::remove_file(env.egg_file("foo/gone.toml"))?;
+    crate::deploy::remove_symlink(env.home_file("b.toml"))?;
+    fs_err::write(env.yolk_file(".gitignore"), "/.git\n/.yolk_git\n")?;
+
+    let findings = doctor::diagnose(env.yolk())?;
+    let problems = findings
+        .iter()
+        .map(|x| x.problem.as_str())
+        .collect::<Vec<_>>();
+    let has_problem = |needle: &str| problems.iter().any(|x| x.contains(needle));
+    assert!(has_problem(
+        "missing*.toml of egg foo does not match any files"
+    ));
+    assert!(has_problem("broken.toml of egg foo failed to parse"));
+    assert!(has_problem("Egg foo is only partially deployed"));
+    assert!(has_problem(
+        ".gitignore is missing /.deployed_cache, /.deployment_manifest.json, /.backups"
+    ));
+    assert!(findings.iter().any(|x| x.fix
+        == Some(Fix::RemoveDanglingSymlink(
+            env.home_file("gone.toml").to_path_buf()
+        ))));
+    assert!(findings.iter().any(|x| x.fix
+        == Some(Fix::RemoveManifestEntry {
+            egg_name: "foo".to_string(),
+            path: env.home_file("b.toml").to_path_buf()
+        })));
+
+    for fix in findings.iter().filter_map(|x| x.fix.as_ref()) {
+        doctor::apply_fix(env.yolk(), fix)?;
+    }
+    env.home_file("gone.toml").assert(exists().not());
+    assert!(doctor::diagnose(env.yolk())?
+        .iter()
+        .all(|finding| finding.fix.is_none()));
+    Ok(())
+}
+
+#[test]
+fn test_auto_templates_and_exclusions() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{ targets: "~/foo", templates: "auto" },
+            bar: #{ targets: "~/bar", templates: ["*.toml", "!vendor.toml"] },
+        };
+    "#})?;
+    env.egg_file("foo/templated.toml")
+        .write_str(r#"value=x # {< replace_value("y") >}"#)?;
+    env.egg_file("foo/plain.toml").write_str("value=x")?;
+    env.egg_file("bar/templated.toml")
+        .write_str(r#"value=x # {< replace_value("y") >}"#)?;
+    env.egg_file("bar/vendor.toml")
+        .write_str(r#"value=x # {< replace_value("y") >}"#)?;
+    env.egg_file("bar/forgotten.conf")
+        .write_str(r#"value=x # {< replace_value("y") >}"#)?;
+    {
+        cov_mark::check_count!(warn_untemplated_file, 1);
+        env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    }
+
+    env.egg_file("foo/templated.toml")
+        .assert(r#"value=y # {< replace_value("y") >}"#);
+    env.egg_file("foo/plain.toml").assert("value=x");
+    env.egg_file("bar/templated.toml")
+        .assert(r#"value=y # {< replace_value("y") >}"#);
+    env.egg_file("bar/vendor.toml")
+        .assert(r#"value=x # {< replace_value("y") >}"#);
+
+    let findings = crate::doctor::diagnose(env.yolk())?;
+    let untemplated = findings
+        .iter()
+        .filter(|x| x.problem.contains("contains yolk tags"))
+        .map(|x| x.problem.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(untemplated.len(), 1);
+    assert!(untemplated[0].contains("forgotten.conf"));
+
+    // Temporarily switching to the canonical state doesn't warn again
+    {
+        cov_mark::check_count!(warn_untemplated_file, 0);
+        env.yolk().with_canonical_state(|| Ok(()))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_egg_data_is_scoped_to_its_templates() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: "~/foo",
+                templates: ["foo.toml"],
+                data: #{ port: if LOCAL { 8080 } else { 0 } },
+            },
+            bar: #{ targets: "~/bar", templates: ["bar.toml"] },
+        };
+    "#})?;
+    env.egg_file("foo/foo.toml").write_str(indoc::indoc! {r#"
+        name="" # {< replace_quoted(EGG.name) >}
+        port=0 # {< replace_value(`${EGG.data.port}`) >}
+    "#})?;
+    env.egg_file("bar/bar.toml").write_str(indoc::indoc! {r#"
+        data=x # {< replace_value(if EGG.data == () { "none" } else { "some" }) >}
+        defined=x # {< replace_value(is_def_var("EGG").to_string()) >}
+    "#})?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.egg_file("foo/foo.toml").assert(indoc::indoc! {r#"
+        name="foo" # {< replace_quoted(EGG.name) >}
+        port=8080 # {< replace_value(`${EGG.data.port}`) >}
+    "#});
+    env.egg_file("bar/bar.toml").assert(indoc::indoc! {r#"
+        data=none # {< replace_value(if EGG.data == () { "none" } else { "some" }) >}
+        defined=true # {< replace_value(is_def_var("EGG").to_string()) >}
+    "#});
+
+    let mut eval_ctx = env.yolk().prepare_eval_ctx_for_templates(EvalMode::Local)?;
+    env.yolk().load_egg_configs(&mut eval_ctx)?;
+    assert!(!eval_ctx.eval_rhai::<bool>(r#"is_def_var("EGG")"#)?);
+    Ok(())
+}
+
+#[test]
+fn test_egg_is_available_in_yolk_rhai_functions() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{ targets: "~/foo", templates: ["foo.toml"], data: #{ color: "red" } },
+            bar: #{ targets: "~/bar", templates: ["bar.toml"], data: #{ color: "blue" } },
+        };
+        fn egg_color() { EGG.data.color }
+    "#})?;
+    env.egg_file("foo/foo.toml")
+        .write_str("color=x # {< replace_value(egg_color()) >}")?;
+    env.egg_file("bar/bar.toml")
+        .write_str("color=x # {< replace_value(egg_color()) >}")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.egg_file("foo/foo.toml")
+        .assert("color=red # {< replace_value(egg_color()) >}");
+    env.egg_file("bar/bar.toml")
+        .assert("color=blue # {< replace_value(egg_color()) >}");
+
+    // Outside of the templates of an egg, `EGG` is gone again, also for functions
+    let mut eval_ctx = env.yolk().prepare_eval_ctx_for_templates(EvalMode::Local)?;
+    env.yolk().load_egg_configs(&mut eval_ctx)?;
+    assert!(eval_ctx.eval_rhai::<String>("egg_color()").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_eval_template_files_renders_like_sync() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: "~/foo",
+                templates: ["foo.toml", "Xresources"],
+                comment_styles: #{ "Xresources": "!" },
+                data: #{ port: 8080 },
+            },
+        };
+    "#})?;
+    env.egg_file("foo/foo.toml")
+        .write_str("port=0 # {< replace_value(`${EGG.data.port}`) >}")?;
+    env.egg_file("foo/Xresources").write_str(indoc::indoc! {r#"
+        ! {% if !LOCAL %}
+        *.font: canonical
+        ! {% end %}
+    "#})?;
+    let foo_path = env.egg_file("foo/foo.toml").to_path_buf();
+    let xresources_path = env.egg_file("foo/Xresources").to_path_buf();
+    let mut eval_ctx = env.yolk().prepare_eval_ctx_for_templates(EvalMode::Local)?;
+    let rendered = env.yolk().eval_template_files(
+        &mut eval_ctx,
+        &HashSet::from([foo_path.clone(), xresources_path.clone()]),
+    )?;
+    assert_eq!(
+        rendered[&foo_path],
+        "port=8080 # {< replace_value(`${EGG.data.port}`) >}"
+    );
+    assert_str_eq!(
+        rendered[&xresources_path],
+        indoc::indoc! {r#"
+            ! {% if !LOCAL %}
+            !<yolk> *.font: canonical
+            ! {% end %}
+        "#}
+    );
+    // Nothing is written
+    env.egg_file("foo/foo.toml")
+        .assert("port=0 # {< replace_value(`${EGG.data.port}`) >}");
+    Ok(())
+}
+
+#[test]
+fn test_configured_comment_styles() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: "~/foo",
+                templates: ["Xresources", "style.css"],
+                comment_styles: #{ "Xresources": "!", "*.css": ["/*", "*/"] },
+            },
+        };
+    "#})?;
+    env.egg_file("foo/Xresources").write_str(indoc::indoc! {r#"
+        ! {% if LOCAL %}
+        *.font: local
+        ! {% else %}
+        *.font: canonical
+        ! {% end %}
+    "#})?;
+    env.egg_file("foo/style.css").write_str(indoc::indoc! {r#"
+        a {
+            color: red; // {< if !LOCAL >}
+        }
+    "#})?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.egg_file("foo/Xresources").assert(indoc::indoc! {r#"
+        ! {% if LOCAL %}
+        *.font: local
+        ! {% else %}
+        !<yolk> *.font: canonical
+        ! {% end %}
+    "#});
+    env.egg_file("foo/style.css").assert(indoc::indoc! {r#"
+        a {
+            /*<yolk> color: red; // {< if !LOCAL >}*/
+        }
+    "#});
+    Ok(())
+}
+
+#[test]
+fn test_ignored_paths_are_collected_once_per_egg() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("bar/bar.toml").write_str("")?;
+    env.egg_file("bar/README.md").write_str("")?;
+    {
+        cov_mark::check_count!(collect_ignored_paths, 0);
+        let egg = env.open_egg("foo", EggConfig::new_merge(".", &env.home))?;
+        env.yolk().sync_egg_deployment(&egg)?;
+    }
+    {
+        cov_mark::check_count!(collect_ignored_paths, 1);
+        let egg = env.open_egg(
+            "bar",
+            EggConfig::new_merge(".", &env.home).with_ignore("README.md"),
+        )?;
+        env.yolk().sync_egg_deployment(&egg)?;
+        assert!(egg.is_deployed()?);
+    }
+    env.home_file("README.md").assert(exists().not());
+    Ok(())
+}
+
+#[test]
+fn test_merge_deploy_skips_ignored_files() -> TestResult {
+    cov_mark::check!(skip_ignored_file);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/.config/app/config.toml").write_str("")?;
+    env.egg_file("foo/.config/app/.config.toml.swp")
+        .write_str("")?;
+    env.egg_file("foo/README.md").write_str("")?;
+    env.egg_file("foo/scripts/install.sh").write_str("")?;
+    env.egg_file("foo/.yolkignore").write_str("scripts/\n")?;
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new_merge(".", &env.home)
+            .with_ignore("README.md")
+            .with_ignore("*.swp"),
+    )?;
+    env.yolk().sync_egg_deployment(&egg)?;
+
+    env.home_file(".config")
+        .assert(is_dir().and(is_symlink().not()));
+    env.home_file(".config/app")
+        .assert(is_dir().and(is_symlink().not()));
+    env.home_file(".config/app/config.toml")
+        .assert(is_symlink());
+    env.home_file(".config/app/.config.toml.swp")
+        .assert(exists().not());
+    env.home_file("README.md").assert(exists().not());
+    env.home_file("scripts").assert(exists().not());
+    env.home_file(".yolkignore").assert(exists().not());
+    assert!(egg.is_deployed()?);
+
+    let mut egg = egg;
+    *egg.config_mut() = egg.config().clone().with_enabled(false);
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file(".config/app/config.toml")
+        .assert(exists().not());
+    assert!(!egg.is_deployed()?);
+    Ok(())
+}
+
+#[test]
+fn test_put_deploy_skips_ignored_files() -> TestResult {
+    cov_mark::check!(deploy_put_unfold_ignored);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/nvim/init.lua").write_str("")?;
+    env.egg_file("foo/nvim/README.md").write_str("")?;
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new("nvim", env.home_file(".config/nvim")).with_ignore("README.md"),
+    )?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file(".config/nvim")
+        .assert(is_dir().and(is_symlink().not()));
+    env.home_file(".config/nvim/init.lua").assert(is_symlink());
+    env.home_file(".config/nvim/README.md")
+        .assert(exists().not());
+    assert!(egg.is_deployed()?);
+    Ok(())
+}
+
+#[test]
+fn test_folded_dir_is_unfolded_when_it_gains_ignored_files() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/app/config.toml").write_str("")?;
+    env.egg_file("foo/app/notes.md").write_str("")?;
+    let egg = env.open_egg("foo", EggConfig::new_merge(".", &env.home))?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("app").assert(is_symlink());
+
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new_merge(".", &env.home).with_ignore("*.md"),
+    )?;
+    assert!(!egg.is_deployed()?);
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("app")
+        .assert(is_dir().and(is_symlink().not()));
+    env.home_file("app/config.toml").assert(is_symlink());
+    env.home_file("app/notes.md").assert(exists().not());
+    assert!(egg.is_deployed()?);
+    Ok(())
+}
+
+#[test]
+fn test_copy_deploy_skips_ignored_files() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/config.toml").write_str("")?;
+    env.egg_file("foo/config.toml.swp").write_str("")?;
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new(".", env.home_file("app"))
+            .with_strategy(DeploymentStrategy::Copy)
+            .with_ignore("*.swp"),
+    )?;
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file("app/config.toml")
+        .assert(is_symlink().not().and(exists()));
+    env.home_file("app/config.toml.swp").assert(exists().not());
+    assert!(egg.is_deployed()?);
+    Ok(())
+}
+
+#[test]
+#[cfg(not(windows))]
+fn test_target_permissions_are_applied_and_checked() -> TestResult {
+    use std::os::unix::fs::PermissionsExt as _;
+    let mode_of = |path: &std::path::Path| -> miette::Result<u32> {
+        Ok(fs_err::metadata(path)
+            .into_diagnostic()?
+            .permissions()
+            .mode()
+            & 0o7777)
+    };
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            ssh: #{ targets: #{ "config": #{ path: "~/.ssh/config", mode: "600", strategy: "copy" } } },
+            gpg: #{
+                targets: #{ "gpg-agent.conf": #{ path: "~/.gnupg/gpg-agent.conf", mode: 0o640 } },
+                strategy: "copy",
+            },
+        };
+    "#})?;
+    env.egg_file("ssh/config").write_str("Host *")?;
+    env.egg_file("gpg/gpg-agent.conf").write_str("")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+
+    env.home_file(".ssh/config").assert(is_symlink().not());
+    assert_eq!(mode_of(env.home_file(".ssh/config").path())?, 0o600);
+    assert_ne!(mode_of(env.egg_file("ssh/config").path())?, 0o600);
+    env.home_file(".gnupg/gpg-agent.conf")
+        .assert(is_symlink().not());
+    assert_eq!(
+        mode_of(env.home_file(".gnupg/gpg-agent.conf").path())?,
+        0o640
+    );
+    assert!(env.yolk().find_permission_drift()?.is_empty());
+
+    fs_err::set_permissions(
+        env.home_file(".gnupg/gpg-agent.conf"),
+        std::fs::Permissions::from_mode(0o644),
+    )
+    .into_diagnostic()?;
+    let drift = env.yolk().find_permission_drift()?;
+    assert_eq!(drift.len(), 1);
+    assert_eq!(drift[0].egg_name, "gpg");
+    assert_eq!(drift[0].differences, vec!["mode is 644 instead of 640"]);
+
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    assert_eq!(
+        mode_of(env.home_file(".gnupg/gpg-agent.conf").path())?,
+        0o640
+    );
+    assert!(env.yolk().find_permission_drift()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_per_target_strategy_and_enabled() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/app.toml").write_str("")?;
+    env.egg_file("foo/data/x.txt").write_str("")?;
+    env.egg_file("foo/copy.txt").write_str("copy")?;
+    env.home_file("data/other.txt").write_str("")?;
+    let config = EggConfig::default()
+        .with_target("app.toml", env.home_file("app.toml"))
+        .with_target("data", env.home_file("data"))
+        .with_target("copy.txt", env.home_file("copy.txt"))
+        .with_target_options(
+            "data",
+            TargetOptions::default().with_strategy(DeploymentStrategy::Merge),
+        )
+        .with_target_options(
+            "copy.txt",
+            TargetOptions::default().with_strategy(DeploymentStrategy::Copy),
+        );
+    let egg = env.open_egg("foo", config.clone())?;
+    env.yolk().sync_egg_deployment(&egg)?;
+
+    env.home_file("app.toml").assert(is_symlink());
+    env.home_file("data").assert(is_symlink().not());
+    env.home_file("data/x.txt").assert(is_symlink());
+    env.home_file("copy.txt")
+        .assert(is_symlink().not().and(exists()));
+    assert!(egg.is_deployed()?);
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    let strategy_of = |path: &str| {
+        manifest
+            .entries("foo")
+            .iter()
+            .find(|entry| entry.path == env.home_file(path).path())
+            .and_then(|entry| entry.strategy)
+    };
+    assert_eq!(strategy_of("app.toml"), Some(DeploymentStrategy::Put));
+    assert_eq!(strategy_of("data/x.txt"), Some(DeploymentStrategy::Merge));
+    assert_eq!(strategy_of("copy.txt"), Some(DeploymentStrategy::Copy));
+
+    // Disabling a single target removes it, but leaves the rest of the egg deployed
+    let egg = env.open_egg(
+        "foo",
+        config.with_target_options("app.toml", TargetOptions::default().with_enabled(false)),
+    )?;
+    assert!(egg.is_deployed()?);
+    assert!(env.yolk().sync_egg_deployment(&egg)?);
+    env.home_file("app.toml").assert(exists().not());
+    env.home_file("data/x.txt").assert(is_symlink());
+    env.home_file("copy.txt").assert("copy");
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert_eq!(manifest.entries("foo").len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_no_fold_merge_deployment() -> TestResult {
+    cov_mark::check!(delete_empty_created_dir);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/.config/app/config.toml").write_str("")?;
+    env.egg_file("foo/.config/app/themes/dark.toml")
+        .write_str("")?;
+    let mut egg = env.open_egg(
+        "foo",
+        EggConfig::new_merge(".", &env.home).with_no_fold(true),
+    )?;
+    env.yolk().sync_egg_deployment(&egg)?;
+
+    for dir in [".config", ".config/app", ".config/app/themes"] {
+        env.home_file(dir).assert(is_dir().and(is_symlink().not()));
+    }
+    env.home_file(".config/app/config.toml")
+        .assert(is_symlink());
+    env.home_file(".config/app/themes/dark.toml")
+        .assert(is_symlink());
+    assert!(egg.is_deployed()?);
+
+    // Runtime state written by the application stays out of the egg
+    env.home_file(".config/app/state.json").write_str("")?;
+    env.egg_file("foo/.config/app/state.json")
+        .assert(exists().not());
+
+    *egg.config_mut() = egg.config().clone().with_enabled(false);
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file(".config/app/config.toml")
+        .assert(exists().not());
+    env.home_file(".config/app/themes").assert(exists().not());
+    env.home_file(".config/app/state.json").assert(exists());
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert!(manifest.entries("foo").is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_no_fold_unfolds_existing_deployment() -> TestResult {
+    cov_mark::check!(traverse_folded_dir_without_folding);
+    cov_mark::check!(unfold_symlinked_dir);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/.config/app/config.toml").write_str("")?;
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", EggConfig::new_merge(".", &env.home))?)?;
+    env.home_file(".config").assert(is_symlink());
+
+    let egg = env.open_egg(
+        "foo",
+        EggConfig::new_merge(".", &env.home).with_no_fold(true),
+    )?;
+    assert!(!egg.is_deployed()?);
+    env.yolk().sync_egg_deployment(&egg)?;
+    env.home_file(".config")
+        .assert(is_dir().and(is_symlink().not()));
+    env.home_file(".config/app/config.toml")
+        .assert(is_symlink());
+    assert!(egg.is_deployed()?);
+    Ok(())
+}
+
+#[test]
+fn test_relative_symlinks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("bar/.config/bar/bar.toml").write_str("")?;
+    let foo = env.open_egg(
+        "foo",
+        EggConfig::new("foo.toml", env.home_file("foo.toml")).with_relative_symlinks(true),
+    )?;
+    let bar = env.open_egg(
+        "bar",
+        EggConfig::new_merge(".", &env.home).with_relative_symlinks(true),
+    )?;
+    env.yolk().sync_egg_deployment(&foo)?;
+    env.yolk().sync_egg_deployment(&bar)?;
+
+    let link = fs_err::read_link(env.home_file("foo.toml")).into_diagnostic()?;
+    assert!(link.is_relative(), "{} is not relative", link.display());
+    let link = fs_err::read_link(env.home_file(".config")).into_diagnostic()?;
+    assert!(link.is_relative(), "{} is not relative", link.display());
+    env.home_file(".config/bar/bar.toml").assert(exists());
+    assert!(foo.is_deployed()?);
+    assert!(bar.is_deployed()?);
+    assert!(env.yolk().find_missing_deployments()?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_relative_symlinks_are_cleaned_up() -> TestResult {
+    cov_mark::check!(delete_stale_symlink);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    let config = EggConfig::new("foo.toml", env.home_file("foo.toml")).with_relative_symlinks(true);
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config)?)?;
+    env.home_file("foo.toml").assert(is_symlink());
+
+    let config =
+        EggConfig::new("foo.toml", env.home_file("moved.toml")).with_relative_symlinks(true);
+    env.yolk()
+        .sync_egg_deployment(&env.open_egg("foo", config)?)?;
+    env.home_file("foo.toml").assert(exists().not());
+    env.home_file("moved.toml").assert(is_symlink());
+    Ok(())
+}
+
+#[test]
+fn test_switching_to_relative_symlinks_updates_deployment() -> TestResult {
+    cov_mark::check!(update_symlink_style);
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{foo: #{ targets: `~`, strategy: "merge"}};
+    "#})?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    let link = fs_err::read_link(env.home_file("foo.toml")).into_diagnostic()?;
+    assert!(link.is_absolute());
+
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let relative_symlinks = true;
+        export let eggs = #{foo: #{ targets: `~`, strategy: "merge"}};
+    "#})?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    let link = fs_err::read_link(env.home_file("foo.toml")).into_diagnostic()?;
+    assert!(link.is_relative(), "{} is not relative", link.display());
+    env.home_file("foo.toml").assert(exists());
+    let manifest = env.yolk().paths().load_deployment_manifest()?;
+    assert_eq!(manifest.entries("foo").len(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_sync_skips_eggs_with_failed_or_disabled_dependencies() -> TestResult {
+    cov_mark::check_count!(skip_egg_with_failed_dependency, 3);
+    let env = TestEnv::init()?;
+    env.egg_file("base/base.toml").write_str("")?;
+    env.egg_file("app/app.toml").write_str("")?;
+    env.egg_file("other/other.toml").write_str("")?;
+    // A regular file in the way makes deploying `base` fail
+    env.home_file("base.toml").write_str("existing")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            base: #{ targets: #{ "base.toml": "~/base.toml" } },
+            app: #{ targets: #{ "app.toml": "~/app.toml" }, depends_on: ["base"] },
+            disabled: #{ targets: "~/disabled", enabled: false },
+            other: #{ targets: #{ "other.toml": "~/other.toml" }, depends_on: ["disabled"] },
+        };
+    "#})?;
+    env.egg_file("disabled").create_dir_all()?;
+    assert!(env.yolk().sync_to_mode(EvalMode::Local, true).is_err());
+    env.home_file("base.toml").assert("existing");
+    env.home_file("app.toml").assert(exists().not());
+    env.home_file("other.toml").assert(exists().not());
+
+    // Once the dependency deploys, the egg is deployed as well
+    fs_err::remove_file(env.home_file("base.toml")).into_diagnostic()?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("base.toml").assert(is_symlink());
+    env.home_file("app.toml").assert(is_symlink());
+    Ok(())
+}
+
+#[test]
+fn test_unknown_dependency_is_reported_without_blocking_other_eggs() -> TestResult {
+    cov_mark::check!(skip_egg_with_failed_dependency);
+    let env = TestEnv::init()?;
+    env.egg_file("app/app.toml").write_str("")?;
+    env.egg_file("other/other.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            app: #{ targets: #{ "app.toml": "~/app.toml" }, depends_on: ["missing"] },
+            other: #{ targets: #{ "other.toml": "~/other.toml" } },
+        };
+    "#})?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    let rendered = crate::util::test_util::render_error(err);
+    assert_eq!(
+        rendered
+            .matches("Egg `app` depends on unknown egg `missing`")
+            .count(),
+        1
+    );
+    env.home_file("app.toml").assert(exists().not());
+    env.home_file("other.toml").assert(is_symlink());
+
+    // Commands that only flip templates temporarily are not affected.
+    env.yolk().verify_templates()?;
+    let findings = crate::doctor::diagnose(env.yolk())?;
+    assert!(findings
+        .iter()
+        .any(|x| x.problem == "Egg `app` depends on unknown egg `missing`"));
+    Ok(())
+}
+
+#[test]
+fn test_sync_rejects_dependency_cycles() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("a/a.toml").write_str("")?;
+    env.egg_file("b/b.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            a: #{ targets: "~/a", depends_on: ["b"] },
+            b: #{ targets: "~/b", depends_on: ["a"] },
+        };
+    "#})?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(crate::util::test_util::render_error(err)
+        .contains("Eggs depend on each other in a cycle: a -> b -> a"));
+    env.home_file("a").assert(exists().not());
+    Ok(())
+}
+
+#[test]
+fn test_sync_selected_eggs() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("bar/bar.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{ targets: `~`, strategy: "merge", tags: ["desktop"] },
+            bar: #{ targets: `~`, strategy: "merge" },
+        };
+    "#})?;
+    env.yolk().sync_eggs_to_mode(
+        EvalMode::Local,
+        true,
+        &EggFilter::default().with_tag("desktop"),
+    )?;
+    env.home_file("foo.toml").assert(is_symlink());
+    env.home_file("bar.toml").assert(exists().not());
+
+    env.yolk().sync_eggs_to_mode(
+        EvalMode::Local,
+        true,
+        &EggFilter::default().with_name("bar"),
+    )?;
+    env.home_file("bar.toml").assert(is_symlink());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_hooks_get_egg_context() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(&format!(
+        indoc::indoc! {r#"
+            export let data = #{{ theme: "dark" }};
+            export let eggs = #{{
+                foo: #{{
+                    targets: #{{ "foo.toml": "~/foo.toml" }},
+                    unsafe_shell_hooks: #{{
+                        pre_deploy: ["touch", "created by argv hook"],
+                        post_deploy: `echo "$YOLK_EGG_NAME|$PWD|$YOLK_EGG_PATH|$YOLK_EGG_TARGETS|$YOLK_EVAL_MODE|$YOLK_DIR|$YOLK_DATA" > {}/context`,
+                    }},
+                }},
+            }};
+        "#},
+        env.home.display()
+    ))?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.egg_file("foo/created by argv hook").assert(exists());
+    let egg_path = env
+        .yolk()
+        .paths()
+        .egg_path("foo")
+        .canonicalize()
+        .into_diagnostic()?;
+    env.home_file("context").assert(format!(
+        "foo|{egg}|{egg}|{target}|local|{yolk_dir}|{{\"theme\":\"dark\"}}\n",
+        egg = egg_path.display(),
+        target = env.home_file("foo.toml").display(),
+        yolk_dir = env.yolk().paths().root_path().display(),
+    ));
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_rhai_hooks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(&format!(
+        indoc::indoc! {r#"
+            export let eggs = #{{
+                foo: #{{
+                    targets: #{{ "foo.toml": "~/foo.toml" }},
+                    hooks: #{{
+                        pre_deploy: || io::run(["touch", "{}/pre_deploy"]),
+                        post_deploy: |egg| io::run(["touch", egg.path + "/" + egg.name + "-" + egg.targets.len()]),
+                    }},
+                }},
+            }};
+        "#},
+        env.home.display()
+    ))?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("pre_deploy").assert(exists());
+    env.egg_file("foo/foo-1").assert(exists());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_rhai_hooks_do_not_run_commands_in_canonical_mode() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: #{ "foo.toml": "~/foo.toml" },
+                hooks: #{ post_deploy: || io::run(["touch", "post_deploy"]) },
+            },
+        };
+    "#})?;
+    env.yolk().sync_to_mode(EvalMode::Canonical, true)?;
+    env.egg_file("foo/post_deploy")
+        .assert(predicates::path::missing());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_commands_of_rhai_hooks_must_be_trusted() -> TestResult {
+    let mut env = TestEnv::init()?;
+    env.yolk.set_trust_handler(|_| Ok(false));
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(&format!(
+        indoc::indoc! {r#"
+            export let eggs = #{{
+                foo: #{{
+                    targets: #{{ "foo.toml": "~/foo.toml" }},
+                    hooks: #{{ pre_deploy: || io::run(["touch", "{}/hook_ran"]) }},
+                }},
+            }};
+        "#},
+        env.home.display()
+    ))?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(crate::util::test_util::render_error(err)
+        .contains("Refusing to run untrusted pre_deploy hook of egg foo"));
+    env.home_file("hook_ran").assert(exists().not());
+
+    env.yolk.set_trust_handler(|_| Ok(true));
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("hook_ran").assert(exists());
+    Ok(())
+}
+
+#[test]
+fn test_io_run_is_only_available_in_hooks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai()
+        .write_str(r#"export let eggs = #{}; io::run(["true"]);"#)?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(crate::util::test_util::render_error(err).contains("io::run"));
+
+    env.yolk_rhai().write_str("export let eggs = #{};")?;
+    let mut eval_ctx = env.yolk().prepare_eval_ctx_for_templates(EvalMode::Local)?;
+    let err = env
+        .yolk()
+        .eval_template(&mut eval_ctx, "", r#"{< io::run(["true"]) >}"#)
+        .unwrap_err();
+    assert!(format!("{err:?}").contains("io::run"));
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_post_template_change_hook() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml")
+        .write_str(r#"value=x # {< replace_value(if LOCAL { data.value } else { "x" }) >}"#)?;
+    env.egg_file("foo/other.toml").write_str("unchanged")?;
+    let write_yolk_rhai = |value: &str| {
+        env.yolk_rhai().write_str(&format!(
+            indoc::indoc! {r#"
+                export let data = #{{ value: "{}" }};
+                export let eggs = #{{
+                    foo: #{{
+                        targets: #{{ "foo.toml": "~/foo.toml" }},
+                        templates: ["foo.toml", "other.toml"],
+                        unsafe_shell_hooks: #{{
+                            post_template_change: `echo "$YOLK_CHANGED_FILES" >> {}/changed`,
+                        }},
+                        hooks: #{{
+                            post_template_change: |egg| io::run(["touch", egg.changed_files[0] + ".rhai"]),
+                        }},
+                    }},
+                }};
+            "#},
+            value,
+            env.home.display()
+        ))
+    };
+    write_yolk_rhai("a")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    let foo_path = env.yolk().paths().egg_path("foo").join("foo.toml");
+    env.home_file("changed")
+        .assert(format!("{}\n", foo_path.display()));
+    env.egg_file("foo/foo.toml.rhai").assert(exists());
+
+    // Nothing changed, and switching to canonical state and back is not a real change either
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.yolk().with_canonical_state(|| Ok(()))?;
+    env.home_file("changed")
+        .assert(format!("{}\n", foo_path.display()));
+
+    // Changes in canonical mode don't run the hook
+    env.yolk().sync_to_mode(EvalMode::Canonical, false)?;
+    env.yolk().sync_to_mode(EvalMode::Local, false)?;
+    env.home_file("changed")
+        .assert(format!("{}\n{}\n", foo_path.display(), foo_path.display()));
+
+    write_yolk_rhai("b")?;
+    let mut eval_ctx = env.yolk().prepare_eval_ctx_for_templates(EvalMode::Local)?;
+    env.yolk().sync_template_files(
+        &mut eval_ctx,
+        EvalMode::Local,
+        &HashSet::from([foo_path.clone()]),
+    )?;
+    env.home_file("changed").assert(format!(
+        "{}\n{}\n{}\n",
+        foo_path.display(),
+        foo_path.display(),
+        foo_path.display()
+    ));
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_global_sync_hooks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo").write_str("")?;
+    env.egg_file("bar/bar.toml")
+        .write_str(r#"value=x # {< replace_value(if LOCAL { data.value } else { "x" }) >}"#)?;
+    let write_yolk_rhai = |foo_enabled: bool, value: &str| {
+        env.yolk_rhai().write_str(&format!(
+            indoc::indoc! {r#"
+                export let data = #{{ value: "{value}" }};
+                export let eggs = #{{
+                    foo: #{{ targets: "~/foo", enabled: {foo_enabled} }},
+                    bar: #{{ targets: "~/bar", templates: ["bar.toml"] }},
+                }};
+                export let unsafe_shell_hooks = #{{
+                    pre_sync: `echo "pre $YOLK_EVAL_MODE" >> {home}/log`,
+                    post_sync: `echo "post $YOLK_DEPLOYED_EGGS|$YOLK_UNDEPLOYED_EGGS|$YOLK_TEMPLATES_CHANGED_EGGS" >> {home}/log`,
+                }};
+                export let hooks = #{{
+                    post_sync: |summary| io::run(["touch", "{home}/changed_" + summary.changed.len()]),
+                }};
+            "#},
+            value = value,
+            foo_enabled = foo_enabled,
+            home = env.home.display(),
+        ))
+    };
+    write_yolk_rhai(true, "a")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("log")
+        .assert("pre local\npost bar\nfoo||bar\n");
+    env.home_file("changed_2").assert(exists());
+
+    // Switching to canonical state and back doesn't run the hooks
+    env.yolk().with_canonical_state(|| Ok(()))?;
+    env.home_file("log")
+        .assert("pre local\npost bar\nfoo||bar\n");
+
+    write_yolk_rhai(false, "b")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("log")
+        .assert("pre local\npost bar\nfoo||bar\npre local\npost |foo|bar\n");
+    Ok(())
+}
+
+#[test]
+fn test_failing_pre_sync_hook_aborts_sync() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{ foo: "~/foo" };
+        export let hooks = #{ pre_sync: || throw "nope" };
+    "#})?;
+    let result = env.yolk().sync_to_mode(EvalMode::Local, true);
+    assert!(format!("{:?}", miette::Report::from(result.unwrap_err())).contains("nope"));
+    env.home_file("foo").assert(predicates::path::missing());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_untrusted_shell_hooks_are_refused() -> TestResult {
+    let mut env = TestEnv::init()?;
+    env.yolk.set_trust_handler(|_| Ok(false));
+    env.egg_file("foo/foo.toml").write_str("")?;
+    let write_yolk_rhai = |command: &str| {
+        env.yolk_rhai().write_str(&format!(
+            indoc::indoc! {r#"
+                export let eggs = #{{
+                    foo: #{{
+                        targets: #{{ "foo.toml": "~/foo.toml" }},
+                        unsafe_shell_hooks: #{{ pre_deploy: "{} {}/hook_ran" }},
+                    }},
+                }};
+            "#},
+            command,
+            env.home.display()
+        ))
+    };
+    write_yolk_rhai("touch")?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(crate::util::test_util::render_error(err)
+        .contains("Refusing to run untrusted pre_deploy hook of egg foo"));
+    env.home_file("hook_ran").assert(exists().not());
+    env.home_file("foo.toml").assert(exists().not());
+    assert!(!env.yolk().list_shell_hooks()?[0].1);
+
+    assert_eq!(env.yolk().trust_hooks(None)?.len(), 1);
+    assert!(env.yolk().trust_hooks(None)?.is_empty());
+    assert!(env.yolk().list_shell_hooks()?[0].1);
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("hook_ran").assert(exists());
+    let store_path = env.yolk().paths().trusted_hooks_path();
+    assert!(store_path.exists());
+    assert!(!store_path.starts_with(env.yolk().paths().root_path()));
+
+    // Changing the command requires trusting it again
+    write_yolk_rhai("rm")?;
+    assert!(!env.yolk().list_shell_hooks()?[0].1);
+    Ok(())
+}
+
+#[test]
+fn test_hook_trust_is_bound_to_egg_and_hook() {
+    use crate::hook_trust::ShellHook;
+    let hook = ShellHook {
+        egg_name: Some("foo".to_string()),
+        hook: ShellHookKey::PreDeploy.into(),
+        command: crate::eggs_config::HookCommand::Shell("true".to_string()),
+    };
+    let mut store = crate::hook_trust::HookTrustStore::default();
+    store.trust(&hook);
+    assert!(store.is_trusted(&hook));
+    assert!(!store.is_trusted(&ShellHook {
+        egg_name: Some("bar".to_string()),
+        ..hook.clone()
+    }));
+    assert!(!store.is_trusted(&ShellHook {
+        egg_name: None,
+        hook: GlobalHookKey::PreSync.into(),
+        ..hook.clone()
+    }));
+    assert!(!store.is_trusted(&ShellHook {
+        egg_name: None,
+        ..hook.clone()
+    }));
+    assert!(!store.is_trusted(&ShellHook {
+        hook: ShellHookKey::PostDeploy.into(),
+        ..hook.clone()
+    }));
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_approved_shell_hooks_are_remembered() -> TestResult {
+    let mut env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    let asked = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let asked_in_handler = asked.clone();
+    env.yolk.set_trust_handler(move |hook| {
+        assert_eq!(hook.hook, ShellHookKey::PreDeploy.into());
+        asked_in_handler.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(true)
+    });
+    let write_yolk_rhai = |enabled: bool| {
+        env.yolk_rhai().write_str(&format!(
+            indoc::indoc! {r#"
+                export let eggs = #{{
+                    foo: #{{
+                        enabled: {},
+                        targets: #{{ "foo.toml": "~/foo.toml" }},
+                        unsafe_shell_hooks: #{{ pre_deploy: "true" }},
+                    }},
+                }};
+            "#},
+            enabled
+        ))
+    };
+    for enabled in [true, false, true] {
+        write_yolk_rhai(enabled)?;
+        env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    }
+    env.home_file("foo.toml").assert(is_symlink());
+    assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 1);
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_failing_hook_reports_output() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: #{ "foo.toml": "~/foo.toml" },
+                unsafe_shell_hooks: #{ pre_deploy: "echo some output; echo some error >&2; exit 3" },
+            },
+        };
+    "#})?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    let rendered = crate::util::test_util::render_error(err);
+    assert!(rendered.contains("pre_deploy hook of egg foo failed with exit code 3"));
+    assert!(rendered.contains("stdout:"));
+    assert!(rendered.contains("some output"));
+    assert!(rendered.contains("some error"));
+    env.home_file("foo.toml").assert(exists().not());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_hook_timeout() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.egg_file("bar/bar.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: #{ "foo.toml": "~/foo.toml" },
+                hook_timeout: 1,
+                unsafe_shell_hooks: #{ pre_deploy: "sleep 10" },
+            },
+            bar: #{
+                targets: #{ "bar.toml": "~/bar.toml" },
+                hook_timeout: 1,
+                unsafe_shell_hooks: #{ pre_deploy: "sleep 10 &" },
+            },
+        };
+    "#})?;
+    let start = std::time::Instant::now();
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert!(crate::util::test_util::render_error(err)
+        .contains("pre_deploy hook of egg foo timed out after 1s"));
+    env.home_file("foo.toml").assert(exists().not());
+    // Processes left running in the background by a hook don't count towards its timeout
+    env.home_file("bar.toml").assert(is_symlink());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_hook_timeout_kills_processes_started_by_the_hook() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(&format!(
+        indoc::indoc! {r#"
+            export let eggs = #{{
+                foo: #{{
+                    targets: #{{ "foo.toml": "~/foo.toml" }},
+                    hook_timeout: 1,
+                    unsafe_shell_hooks: #{{ pre_deploy: "(sleep 2; touch {}/hook_ran) & sleep 10" }},
+                }},
+            }};
+        "#},
+        env.home.display()
+    ))?;
+    assert!(env.yolk().sync_to_mode(EvalMode::Local, true).is_err());
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    env.home_file("hook_ran").assert(exists().not());
+    Ok(())
+}
+
+#[test]
+fn test_rhai_hook_timeout() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: #{ "foo.toml": "~/foo.toml" },
+                hook_timeout: 1,
+                hooks: #{ pre_deploy: || { loop {} } },
+            },
+        };
+    "#})?;
+    let start = std::time::Instant::now();
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert!(crate::util::test_util::render_error(err)
+        .contains("pre_deploy rhai hook of egg foo failed"));
+    env.home_file("foo.toml").assert(exists().not());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_failing_post_hooks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.egg_file("foo/foo.toml").write_str("")?;
+    let write_yolk_rhai = |enabled: bool, on_hook_failure: &str| {
+        env.yolk_rhai().write_str(&format!(
+            indoc::indoc! {r#"
+                export let eggs = #{{
+                    foo: #{{
+                        enabled: {},
+                        targets: #{{ "foo.toml": "~/foo.toml" }},
+                        on_hook_failure: "{}",
+                        hooks: #{{ post_deploy: || throw "rhai hook broke" }},
+                        unsafe_shell_hooks: #{{ post_deploy: "exit 1" }},
+                    }},
+                }};
+            "#},
+            enabled, on_hook_failure
+        ))
+    };
+    write_yolk_rhai(true, "fail")?;
+    let err = env.yolk().sync_to_mode(EvalMode::Local, true).unwrap_err();
+    let rendered = crate::util::test_util::render_error(err);
+    assert!(rendered.contains("Failed to run post_deploy hooks of egg foo"));
+    assert!(rendered.contains("post_deploy rhai hook of egg foo failed"));
+    assert!(rendered.contains("post_deploy hook of egg foo failed with exit code 1"));
+    // The deployment itself succeeded, and is recorded as such
+    env.home_file("foo.toml").assert(is_symlink());
+    assert_eq!(
+        env.yolk()
+            .paths()
+            .load_deployment_manifest()?
+            .entries("foo")
+            .len(),
+        1
+    );
+
+    write_yolk_rhai(false, "warn")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    write_yolk_rhai(true, "warn")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    env.home_file("foo.toml").assert(is_symlink());
+    Ok(())
+}
+
+#[test]
+fn test_verify_templates_round_trip() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{ foo: #{ targets: "~", strategy: "merge", templates: ["foo.toml"] } };
+    "#})?;
+    let lossy = indoc::indoc! {r#"
+        name = "foo"
+        # {# (if LOCAL { get_yolk_text() } else { "token = hidden" }) #}
+        token = secret
+    "#};
+    env.egg_file("foo/foo.toml").write_str(lossy)?;
+    {
+        cov_mark::check!(template_round_trip_mismatch);
+        let rendered = test_util::render_error(env.yolk().verify_templates().unwrap_err());
+        assert!(rendered.contains("changes when rendered to its canonical state and back"));
+        assert!(rendered.contains("the local state differs after this tag"));
+    }
+
+    // The canonical operation doesn't run, and the template stays untouched
+    let mut ran = false;
+    let result = env.yolk().with_canonical_state(|| {
+        ran = true;
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert!(!ran);
+    env.egg_file("foo/foo.toml").assert(lossy);
+
+    env.egg_file("foo/foo.toml").write_str(indoc::indoc! {r#"
+        name = "foo"
+        # {# replace_value(if LOCAL { "secret" } else { "hidden" }) #}
+        token = "secret"
+    "#})?;
+    env.yolk().verify_templates()?;
+    env.yolk().with_canonical_state(|| {
+        env.egg_file("foo/foo.toml")
+            .assert(p::str::contains("token = hidden"));
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[test]
+fn test_verify_templates_uses_canonical_egg_data() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: "~",
+                strategy: "merge",
+                templates: ["foo.toml"],
+                data: #{ port: if LOCAL { 8080 } else { () } },
+            },
+        };
+    "#})?;
+    env.egg_file("foo/foo.toml")
+        .write_str("port=0 # {< replace_value(`${EGG.data.port + 1}`) >}")?;
+    env.yolk().sync_to_mode(EvalMode::Local, true)?;
+    // Only the canonical data of the egg can't be rendered, which has to be caught before anything is changed
+    assert!(env.yolk().verify_templates().is_err());
+    env.egg_file("foo/foo.toml")
+        .assert("port=8081 # {< replace_value(`${EGG.data.port + 1}`) >}");
+
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+        export let eggs = #{
+            foo: #{
+                targets: "~",
+                strategy: "merge",
+                templates: ["foo.toml"],
+                data: #{ port: if LOCAL { 8080 } else { 0 } },
+            },
+        };
+    "#})?;
+    env.yolk().verify_templates()?;
+    env.yolk().with_canonical_state(|| {
+        env.egg_file("foo/foo.toml")
+            .assert("port=1 # {< replace_value(`${EGG.data.port + 1}`) >}");
+        Ok(())
+    })?;
+    Ok(())
+}
@@ -1,12 +1,13 @@
 use std::{
     collections::HashSet,
     io::Write,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use cached::UnboundCache;
 use fs_err::OpenOptions;
 use miette::{Context as _, IntoDiagnostic as _};
+use normalize_path::NormalizePath as _;
 use regex::Regex;
 
 use crate::yolk_paths::default_yolk_dir;
@@ -29,6 +30,37 @@ pub fn rename_safely(original: impl AsRef<Path>, new: impl AsRef<Path>) -> miett
     Ok(())
 }
 
+/// Write a file by writing to a temporary file next to it and renaming that over the original,
+/// so the file is never left partially written.
+pub fn write_atomic(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> miette::Result<()> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create temporary file in {}", dir.abbr()))?;
+    file.write_all(content.as_ref()).into_diagnostic()?;
+    file.as_file().sync_all().into_diagnostic()?;
+    file.persist(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", path.abbr()))?;
+    Ok(())
+}
+
+/// Print an operation that would have been performed, used when running in dry-run mode.
+pub fn print_dry_run(action: impl std::fmt::Display) {
+    println!("[dry-run] would {action}");
+}
+
+/// Compute the hex-encoded sha256 hash of the contents of a file.
+pub fn file_content_hash(path: impl AsRef<Path>) -> miette::Result<String> {
+    use sha2::Digest as _;
+    let path = path.as_ref();
+    let content = fs_err::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {} for hashing", path.abbr()))?;
+    Ok(hex::encode(sha2::Sha256::digest(content)))
+}
+
 pub fn file_entries_recursive(
     path: impl AsRef<Path>,
 ) -> impl Iterator<Item = miette::Result<PathBuf>> {
@@ -104,6 +136,20 @@ impl Path {
         Ok(dunce::simplified(&fs_err::canonicalize(self).into_diagnostic()?).to_path_buf())
     }
 
+    /// Read the target of the symlink at this path.
+    ///
+    /// Relative targets are resolved against the directory containing the symlink,
+    /// so the result can be compared with absolute paths.
+    fn read_link_absolute(&self) -> miette::Result<PathBuf> {
+        let target = fs_err::read_link(self).into_diagnostic()?;
+        if target.is_absolute() {
+            return Ok(target);
+        }
+        let parent = self.parent().unwrap_or(Path::new(""));
+        let parent = parent.canonical().unwrap_or_else(|_| parent.to_path_buf());
+        Ok(parent.join(target).normalize())
+    }
+
     /// Stringify the path into an abbreviated form.
     ///
     /// This replaces the home path with `~`, as well as reducing paths that point into the eggs directory to `eggs/rest/of/path`.
@@ -160,6 +206,34 @@ impl Path {
     }
 }
 
+/// Express the absolute path `path` relative to the absolute directory `base`, i.e. `../foo/bar`.
+///
+/// Returns `None` if there is no relative path between them, such as for paths on different drives on windows.
+pub fn relative_path(path: impl AsRef<Path>, base: impl AsRef<Path>) -> Option<PathBuf> {
+    let mut path = path.as_ref().components().peekable();
+    let mut base = base.as_ref().components().peekable();
+    if path.peek() != base.peek() {
+        return None;
+    }
+    while path.peek().is_some() && path.peek() == base.peek() {
+        path.next();
+        base.next();
+    }
+    let mut relative = PathBuf::new();
+    for component in base {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    relative.extend(path);
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
 pub fn create_regex(s: impl AsRef<str>) -> miette::Result<Regex> {
     cached::cached_key! {
          REGEXES: UnboundCache<String, Result<Regex, regex::Error>> = UnboundCache::new();
@@ -221,8 +295,12 @@ pub mod test_util {
             use assert_fs::prelude::PathChild as _;
 
             let home = assert_fs::TempDir::new().into_diagnostic()?;
-            let paths = crate::yolk_paths::YolkPaths::new(home.join("yolk"), home.to_path_buf())?;
-            let yolk = crate::yolk::Yolk::new(paths);
+            let mut paths =
+                crate::yolk_paths::YolkPaths::new(home.join("yolk"), home.to_path_buf())?;
+            paths.set_data_dir(home.join(".local/share/yolk"));
+            let mut yolk = crate::yolk::Yolk::new(paths);
+            // Most tests don't care about the hook trust store, so all hooks are trusted by default.
+            yolk.set_trust_handler(|_| Ok(true));
             std::env::set_var("HOME", "/tmp/TEST_HOMEDIR_SHOULD_NOT_BE_USED");
             set_home_dir(home.to_path_buf());
 
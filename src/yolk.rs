@@ -3,29 +3,428 @@ use miette::miette;
 use miette::{Context, IntoDiagnostic, Result, Severity};
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
 
-use crate::deploy::Deployer;
+use crate::backup::RestoreHandler;
+use crate::deploy::{Deployer, DriftHandler, DriftResolution, DriftedCopy};
+use crate::hook_trust::{self, ShellHook, TrustHandler};
+use crate::manifest::{DeploymentManifest, ManifestEntry};
 use crate::multi_error::MultiError;
+use crate::permissions::PermissionDrift;
 use crate::{
-    eggs_config::{DeploymentStrategy, EggConfig},
-    script::{eval_ctx::EvalCtx, rhai_error::RhaiScriptError, sysinfo::SystemInfo},
-    templating::document::Document,
+    eggs_config::{
+        self, ConflictPolicy, DeploymentStrategy, EggConfig, EggFilter, ExpandedTarget,
+        GlobalHookContext, GlobalHookKey, GlobalHooks, HookCommand, HookContext, HookFailurePolicy,
+        HookKey, ShellHookKey,
+    },
+    script::{
+        eval_ctx::EvalCtx, rhai_error::RhaiScriptError, stdlib::CommandRunner, sysinfo::SystemInfo,
+    },
+    templating::{comment_style::CommentStyle, document::Document, error::RoundTripError},
     util::{self, PathExt as _},
     yolk_paths::{Egg, YolkPaths},
 };
 
-const GITIGNORE_ENTRIES: &[&str] = &["/.git", "/.deployed_cache", "/.yolk_git"];
+pub(crate) const GITIGNORE_ENTRIES: &[&str] = &[
+    "/.git",
+    "/.deployed_cache",
+    "/.deployment_manifest.json",
+    "/.yolk_git",
+    "/.backups",
+];
 
 pub struct Yolk {
     yolk_paths: YolkPaths,
+    /// When set, filesystem changes and hooks are only printed, not performed.
+    dry_run: bool,
+    /// Decides what to do with copied files that were modified since they were deployed.
+    drift_handler: Box<DriftHandler>,
+    /// Overrides the `on_conflict` policy of all eggs when set.
+    conflict_policy: Option<ConflictPolicy>,
+    /// Decides whether backed up files should be restored when undeploying an egg.
+    restore_handler: Box<RestoreHandler>,
+    /// Decides whether a shell hook that isn't in the trust store yet may run.
+    trust_handler: Arc<TrustHandler>,
 }
 
 impl Yolk {
     pub fn new(yolk_paths: YolkPaths) -> Self {
-        Self { yolk_paths }
+        Self {
+            yolk_paths,
+            dry_run: false,
+            drift_handler: Box::new(|_| Ok(DriftResolution::Skip)),
+            conflict_policy: None,
+            restore_handler: Box::new(|_, _| Ok(false)),
+            trust_handler: Arc::new(|_| Ok(false)),
+        }
+    }
+
+    /// Use the given conflict policy for all eggs, regardless of their `on_conflict` setting.
+    pub fn set_conflict_policy(&mut self, policy: ConflictPolicy) {
+        self.conflict_policy = Some(policy);
+    }
+
+    /// Set the callback that decides whether backed up files are restored when undeploying an egg.
+    /// By default, backups are kept in place and not restored.
+    pub fn set_restore_handler(
+        &mut self,
+        handler: impl Fn(&str, &[PathBuf]) -> Result<bool> + Send + Sync + 'static,
+    ) {
+        self.restore_handler = Box::new(handler);
+    }
+
+    /// Set the callback that decides how to resolve copied files whose deployed version was modified.
+    /// By default, modified copies are left alone.
+    pub fn set_drift_handler(
+        &mut self,
+        handler: impl Fn(&DriftedCopy) -> Result<DriftResolution> + Send + Sync + 'static,
+    ) {
+        self.drift_handler = Box::new(handler);
+    }
+
+    /// Set the callback that decides whether a shell hook that was never trusted before may run.
+    /// By default, untrusted hooks are refused.
+    pub fn set_trust_handler(
+        &mut self,
+        handler: impl Fn(&ShellHook) -> Result<bool> + Send + Sync + 'static,
+    ) {
+        self.trust_handler = Arc::new(handler);
+    }
+
+    /// Only print what syncing would do, without touching the filesystem or running any hooks.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn new_deployer(&self) -> Deployer {
+        Deployer::new().with_dry_run(self.dry_run)
+    }
+
+    /// Create a deployer for deploying the given egg, respecting its conflict policy, ignored files and symlink style.
+    fn deployer_for(&self, egg: &Egg) -> Result<Deployer> {
+        let deployer = self
+            .new_deployer()
+            .with_ignore(egg.ignore_matcher()?.clone())
+            .with_relative_symlinks(egg.config().relative_symlinks.unwrap_or_default());
+        Ok(
+            match self.conflict_policy.unwrap_or(egg.config().on_conflict) {
+                ConflictPolicy::Fail => deployer,
+                ConflictPolicy::Backup => deployer
+                    .with_backup_dir(self.yolk_paths.egg_backups(egg.name()).new_backup_dir()),
+            },
+        )
+    }
+
+    /// Run the given Rhai and shell hooks of an egg, or print them when in dry-run mode.
+    ///
+    /// The Rhai hook runs first, then the shell hook, even if the Rhai hook failed.
+    /// Failing post hooks are only reported as warnings if the `on_hook_failure` policy of the egg is `warn`.
+    /// `changed_files` is only passed on to the `post_template_change` hook.
+    fn run_egg_hook(
+        &self,
+        egg: &Egg,
+        key: ShellHookKey,
+        sync: &SyncContext,
+        changed_files: &[PathBuf],
+    ) -> Result<(), MultiError> {
+        let rhai_hook = egg.config().hooks.get(key);
+        let shell_hook = egg.config().unsafe_shell_hooks.get(key);
+        if self.dry_run {
+            if rhai_hook.is_some() {
+                util::print_dry_run(format!(
+                    "run {} rhai hook of egg {}",
+                    key.name(),
+                    egg.name()
+                ));
+            }
+            if let Some(command) = shell_hook {
+                util::print_dry_run(format!(
+                    "run {} hook of egg {}: {command}",
+                    key.name(),
+                    egg.name()
+                ));
+            }
+            return Ok(());
+        }
+        if rhai_hook.is_none() && shell_hook.is_none() {
+            return Ok(());
+        }
+        let mut errs = Vec::new();
+        let targets = egg
+            .enabled_targets()?
+            .into_iter()
+            .map(|target| target.deployed)
+            .collect::<Vec<_>>();
+        let to_rhai_array = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|x| x.to_string_lossy().to_string().into())
+                .collect::<rhai::Array>()
+        };
+        if let Some(hook) = rhai_hook {
+            match sync.eval_ctx {
+                Some(eval_ctx) => {
+                    let mut egg_object = egg_rhai_object(egg)?;
+                    egg_object.insert("targets".into(), to_rhai_array(&targets).into());
+                    if key == ShellHookKey::PostTemplateChange {
+                        egg_object
+                            .insert("changed_files".into(), to_rhai_array(changed_files).into());
+                    }
+                    tracing::debug!("Running {} rhai hook", key.name());
+                    let deadline = Instant::now() + egg.config().hook_timeout;
+                    let run_command =
+                        self.hook_command_runner(Some(egg.name()), key.into(), deadline);
+                    if let Err(e) =
+                        eval_ctx.call_hook(hook, egg_object.into(), run_command, deadline)
+                    {
+                        errs.push(e.into_report("yolk.rhai", "").wrap_err(format!(
+                            "{} rhai hook of egg {} failed",
+                            key.name(),
+                            egg.name()
+                        )));
+                    }
+                }
+                None => tracing::warn!(
+                    "Not running {} rhai hook of egg {}, as yolk.rhai is not loaded",
+                    key.name(),
+                    egg.name()
+                ),
+            }
+        }
+        if let Some(command) = shell_hook {
+            let context = HookContext {
+                egg_name: egg.name().to_string(),
+                egg_path: egg.path().to_path_buf(),
+                targets,
+                mode: sync.mode,
+                yolk_dir: self.yolk_paths.root_path().to_path_buf(),
+                data: sync.data.clone(),
+                changed_files: changed_files.to_vec(),
+                timeout: egg.config().hook_timeout,
+            };
+            let result = self
+                .ensure_hook_trusted(&ShellHook {
+                    egg_name: Some(egg.name().to_string()),
+                    hook: key.into(),
+                    command: command.clone(),
+                })
+                .and_then(|()| Ok(egg.config().unsafe_shell_hooks.run(key, &context)?));
+            if let Err(e) = result {
+                errs.push(e);
+            }
+        }
+        if errs.is_empty() {
+            return Ok(());
+        }
+        if key.is_post() && egg.config().on_hook_failure == HookFailurePolicy::Warn {
+            for e in errs {
+                tracing::warn!("{e:?}");
+            }
+            return Ok(());
+        }
+        Err(MultiError::new(
+            format!("Failed to run {} hooks of egg {}", key.name(), egg.name()),
+            errs,
+        ))
+    }
+
+    /// Make sure the user trusts the given shell hook, asking the trust handler if it isn't in the trust store yet.
+    fn ensure_hook_trusted(&self, hook: &ShellHook) -> Result<()> {
+        hook_trust::ensure_trusted(
+            &self.yolk_paths.trusted_hooks_path(),
+            &*self.trust_handler,
+            hook,
+        )
+    }
+
+    /// Build the callback that runs the programs passed to `io::run` by the given Rhai hook.
+    ///
+    /// Just like shell hooks, every command has to be in the trust store before it is run.
+    fn hook_command_runner(
+        &self,
+        egg_name: Option<&str>,
+        hook: HookKey,
+        deadline: Instant,
+    ) -> Arc<CommandRunner> {
+        let store_path = self.yolk_paths.trusted_hooks_path();
+        let trust_handler = self.trust_handler.clone();
+        let egg_name = egg_name.map(str::to_string);
+        Arc::new(move |args: Vec<String>| {
+            let shell_hook = ShellHook {
+                egg_name: egg_name.clone(),
+                hook,
+                command: HookCommand::Argv(args.clone()),
+            };
+            hook_trust::ensure_trusted(&store_path, &*trust_handler, &shell_hook)
+                .map_err(|e| format!("{e}"))?;
+            let mut cmd = std::process::Command::new(&args[0]);
+            cmd.args(&args[1..]);
+            let mut child = eggs_config::spawn_hook_process(&mut cmd)
+                .map_err(|e| format!("Failed to run {}: {e}", args[0]))?;
+            match eggs_config::wait_for_hook_process(&mut child, deadline) {
+                Ok(Some(status)) => Ok(status.code().unwrap_or(-1) as i64),
+                Ok(None) => Err(format!("{} timed out", args[0])),
+                Err(e) => Err(format!("Failed to wait for {}: {e}", args[0])),
+            }
+        })
+    }
+
+    /// List the shell hooks of the yolk.rhai file and of all eggs, together with whether they are trusted.
+    pub fn list_shell_hooks(&self) -> Result<Vec<(ShellHook, bool)>> {
+        let mut eval_ctx = self.prepare_eval_ctx_for_templates(EvalMode::Local)?;
+        let egg_configs = self.load_egg_configs(&mut eval_ctx)?;
+        let global_hooks = self.load_global_hooks(&eval_ctx)?;
+        let mut hooks = GlobalHookKey::ALL
+            .into_iter()
+            .filter_map(|key| {
+                Some(ShellHook {
+                    egg_name: None,
+                    hook: key.into(),
+                    command: global_hooks.unsafe_shell_hooks.get(&key)?.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        for name in eggs_config::sync_order(&egg_configs)? {
+            for key in ShellHookKey::ALL {
+                if let Some(command) = egg_configs[&name].unsafe_shell_hooks.get(key) {
+                    hooks.push(ShellHook {
+                        egg_name: Some(name.clone()),
+                        hook: key.into(),
+                        command: command.clone(),
+                    });
+                }
+            }
+        }
+        let store = self.yolk_paths.load_hook_trust_store()?;
+        Ok(hooks
+            .into_iter()
+            .map(|hook| {
+                let trusted = store.is_trusted(&hook);
+                (hook, trusted)
+            })
+            .collect())
+    }
+
+    /// Add the shell hooks of the given egg, or of the yolk.rhai file and all eggs, to the trust store.
+    ///
+    /// Returns the hooks that were not trusted before.
+    pub fn trust_hooks(&self, egg_name: Option<&str>) -> Result<Vec<ShellHook>> {
+        let hooks = self.list_shell_hooks()?;
+        if let Some(egg_name) = egg_name {
+            if !hooks
+                .iter()
+                .any(|(hook, _)| hook.egg_name.as_deref() == Some(egg_name))
+            {
+                tracing::warn!("Egg {egg_name} has no shell hooks");
+            }
+        }
+        let mut store = self.yolk_paths.load_hook_trust_store()?;
+        let newly_trusted = hooks
+            .into_iter()
+            .map(|(hook, _)| hook)
+            .filter(|hook| egg_name.is_none() || hook.egg_name.as_deref() == egg_name)
+            .filter(|hook| store.trust(hook))
+            .collect::<Vec<_>>();
+        if !newly_trusted.is_empty() {
+            self.yolk_paths.save_hook_trust_store(&store)?;
+        }
+        Ok(newly_trusted)
+    }
+
+    /// Load the `hooks` and `unsafe_shell_hooks` declared at the top level of the yolk.rhai file.
+    fn load_global_hooks(&self, eval_ctx: &EvalCtx) -> Result<GlobalHooks> {
+        let (_, yolk_file_module) = eval_ctx
+            .yolk_file_module()
+            .expect("Tried to load global hooks before loading yolk file. This is a bug.");
+        GlobalHooks::from_dynamic(
+            yolk_file_module.get_var("hooks").as_ref(),
+            yolk_file_module.get_var("unsafe_shell_hooks").as_ref(),
+        )
+        .map_err(|e| e.into_report("yolk.rhai", ""))
+        .wrap_err("Failed to load hooks of yolk.rhai")
+    }
+
+    /// Run the given Rhai and shell hooks declared at the top level of the yolk.rhai file, or print them when in dry-run mode.
+    ///
+    /// The Rhai hook runs first, then the shell hook, even if the Rhai hook failed.
+    fn run_global_hook(
+        &self,
+        hooks: &GlobalHooks,
+        key: GlobalHookKey,
+        sync: &SyncContext,
+        summary: Option<&SyncSummary>,
+    ) -> Result<(), MultiError> {
+        let rhai_hook = hooks.hooks.get(&key);
+        let shell_hook = hooks.unsafe_shell_hooks.get(&key);
+        if self.dry_run {
+            if rhai_hook.is_some() {
+                util::print_dry_run(format!("run {} rhai hook", key.name()));
+            }
+            if let Some(command) = shell_hook {
+                util::print_dry_run(format!("run {} hook: {command}", key.name()));
+            }
+            return Ok(());
+        }
+        let mut errs = Vec::new();
+        if let (Some(hook), Some(eval_ctx)) = (rhai_hook, sync.eval_ctx) {
+            tracing::debug!("Running {} rhai hook", key.name());
+            let arg = summary.map(SyncSummary::to_rhai).unwrap_or_default();
+            let deadline = Instant::now() + eggs_config::DEFAULT_HOOK_TIMEOUT;
+            let run_command = self.hook_command_runner(None, key.into(), deadline);
+            if let Err(e) = eval_ctx.call_hook(hook, arg.into(), run_command, deadline) {
+                errs.push(
+                    e.into_report("yolk.rhai", "")
+                        .wrap_err(format!("{} rhai hook of yolk.rhai failed", key.name())),
+                );
+            }
+        }
+        if let Some(command) = shell_hook {
+            let context = GlobalHookContext {
+                mode: sync.mode,
+                yolk_dir: self.yolk_paths.root_path().to_path_buf(),
+                data: sync.data.clone(),
+                summary,
+            };
+            let result = self
+                .ensure_hook_trusted(&ShellHook {
+                    egg_name: None,
+                    hook: key.into(),
+                    command: command.clone(),
+                })
+                .and_then(|()| Ok(context.run(key, command)?));
+            if let Err(e) = result {
+                errs.push(e);
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(MultiError::new(
+                format!("Failed to run {} hooks of yolk.rhai", key.name()),
+                errs,
+            ))
+        }
+    }
+
+    /// Run the `pre_git` or `post_git` hooks declared in the yolk.rhai file.
+    pub fn run_git_hook(&self, key: GlobalHookKey) -> Result<()> {
+        if !self.yolk_paths.yolk_rhai_path().exists() {
+            return Ok(());
+        }
+        let mut eval_ctx = self.prepare_eval_ctx_for_templates(EvalMode::Local)?;
+        // Loading the egg configs makes sure the yolk.rhai file is valid before running anything.
+        self.load_egg_configs(&mut eval_ctx)?;
+        let hooks = self.load_global_hooks(&eval_ctx)?;
+        let sync = SyncContext::from_eval_ctx(EvalMode::Local, &eval_ctx)?;
+        Ok(self.run_global_hook(&hooks, key, &sync.with_eval_ctx(&eval_ctx), None)?)
     }
 
     /// Init the yolk directory, setting up the required git structure and files.
@@ -83,28 +482,37 @@ impl Yolk {
         &self,
         deployer: &mut Deployer,
         egg: &Egg,
-        mappings: &HashMap<PathBuf, PathBuf>,
+        targets: &[ExpandedTarget],
+        sync: &SyncContext,
+        manifest: &DeploymentManifest,
     ) -> Result<(), MultiError> {
         let mut errs = Vec::new();
-        egg.config().unsafe_shell_hooks.run_pre_deploy()?;
-        for (in_egg, deployed) in mappings {
+        self.run_egg_hook(egg, ShellHookKey::PreDeploy, sync, &[])?;
+        let previous_copies = manifest.copies(egg.name());
+        for target in targets {
+            let (in_egg, deployed) = (&target.in_egg, &target.deployed);
             let mut deploy_mapping = || -> miette::Result<()> {
-                match egg.config().strategy {
+                match target.strategy {
                     DeploymentStrategy::Merge => {
                         cov_mark::hit!(deploy_merge);
-                        deployer.symlink_recursive(egg.path(), in_egg, deployed)?;
+                        deployer.symlink_recursive(egg.path(), in_egg, deployed, target.no_fold)?;
                     }
                     DeploymentStrategy::Put => {
                         cov_mark::hit!(deploy_put);
                         if deployed.is_symlink() {
-                            let target = deployed.fs_err_read_link().into_diagnostic()?;
+                            let target = deployed.read_link_absolute()?;
                             if target.starts_with(egg.path()) {
                                 deployer.delete_symlink(deployed)?;
                                 tracing::info!("Removed dead symlink {}", deployed.abbr());
+                            } else if deployer.backs_up_conflicts() {
+                                deployer.backup_file(deployed)?;
                             }
+                        } else if deployed.exists() && deployer.backs_up_conflicts() {
+                            cov_mark::hit!(backup_conflicting_file);
+                            deployer.backup_file(deployed)?;
                         }
                         if let Some(parent) = deployed.parent() {
-                            fs_err::create_dir_all(parent).map_err(|e| {
+                            deployer.create_dir_all(parent).map_err(|e| {
                                 miette!(
                                     severity = Severity::Warning,
                                     "Failed to create parent dir for deployment of {}: {e:?}",
@@ -112,7 +520,22 @@ impl Yolk {
                                 )
                             })?;
                         }
-                        deployer.create_symlink(in_egg, deployed)?;
+                        if in_egg.is_dir() && deployer.contains_ignored(in_egg) {
+                            // Symlinking the directory would deploy the ignored files within it.
+                            cov_mark::hit!(deploy_put_unfold_ignored);
+                            deployer.symlink_recursive(egg.path(), in_egg, deployed, false)?;
+                        } else {
+                            deployer.create_symlink(in_egg, deployed)?;
+                        }
+                    }
+                    DeploymentStrategy::Copy => {
+                        cov_mark::hit!(deploy_copy);
+                        deployer.copy_recursive(
+                            in_egg,
+                            deployed,
+                            &previous_copies,
+                            &*self.drift_handler,
+                        )?;
                     }
                 }
                 Result::Ok(())
@@ -121,18 +544,25 @@ impl Yolk {
                 errs.push(e.wrap_err(format!("Failed to deploy {}", in_egg.abbr())));
             }
         }
+        if errs.is_empty() {
+            if let Err(e) = self.apply_permissions(deployer, egg) {
+                errs.push(e);
+            }
+        }
 
         if !errs.is_empty() {
+            if let Err(e) = deployer.rollback() {
+                errs.push(e.wrap_err("Failed to roll back partial deployment"));
+            }
             return Err(MultiError::new(
                 format!("Failed to deploy egg {}", egg.name()),
                 errs,
             ));
         }
         debug_assert!(
-            !errs.is_empty() || egg.is_deployed()?,
+            self.dry_run || !errs.is_empty() || egg.is_deployed()?,
             "Egg::is_deployed should return true after deploying"
         );
-        egg.config().unsafe_shell_hooks.run_post_deploy()?;
         Ok(())
     }
 
@@ -140,27 +570,45 @@ impl Yolk {
         &self,
         deployer: &mut Deployer,
         egg: &Egg,
-        mappings: &HashMap<PathBuf, PathBuf>,
+        targets: &[ExpandedTarget],
+        sync: &SyncContext,
+        manifest: &mut DeploymentManifest,
     ) -> Result<(), MultiError> {
-        egg.config().unsafe_shell_hooks.run_pre_undeploy()?;
+        self.run_egg_hook(egg, ShellHookKey::PreUndeploy, sync, &[])?;
         let mut errs = Vec::new();
-        for (in_egg, deployed) in mappings {
-            if let Err(e) = deployer.remove_symlink_recursive(in_egg, &deployed) {
-                errs.push(e.wrap_err(format!("Failed to remove deployment of {}", in_egg.abbr())));
+        for target in targets.iter().filter(|target| !target.is_copy()) {
+            if let Err(e) = deployer.remove_symlink_recursive(&target.in_egg, &target.deployed) {
+                errs.push(e.wrap_err(format!(
+                    "Failed to remove deployment of {}",
+                    target.in_egg.abbr()
+                )));
+            }
+        }
+        let has_copies = targets.iter().any(ExpandedTarget::is_copy);
+        if has_copies && errs.is_empty() {
+            if let Err(e) = self.cleanup_stale_deployments_for(deployer, egg.name(), &[], manifest)
+            {
+                errs.push(miette::Report::from_err(e));
             }
         }
+        if errs.is_empty() {
+            if let Err(e) = self.restore_backups(egg) {
+                errs.push(e);
+            }
+        } else if let Err(e) = deployer.rollback() {
+            errs.push(e.wrap_err("Failed to roll back partial undeployment"));
+        }
         if !errs.is_empty() {
             return Err(MultiError::new(
                 format!("Failed to undeploy egg {}", egg.name()),
                 errs,
             ));
         }
+        // Modified copies are intentionally left in place, so the egg may still look deployed.
         debug_assert!(
-            !errs.is_empty() || !egg.is_deployed()?,
+            self.dry_run || has_copies || !errs.is_empty() || !egg.is_deployed()?,
             "Egg::is_deployed should return false after undeploying"
         );
-        egg.config().unsafe_shell_hooks.run_post_undeploy()?;
-
         Ok(())
     }
 
@@ -218,8 +666,23 @@ impl Yolk {
 
     /// Deploy or undeploy the given egg, depending on the current system state and the given Egg data.
     /// Returns true if the egg is now deployed, false if it is not.
-    #[tracing::instrument(skip_all, fields(egg.name = %egg.name()))]
     pub fn sync_egg_deployment(&self, egg: &Egg) -> Result<bool, MultiError> {
+        let mut manifest = self.yolk_paths.load_deployment_manifest()?;
+        let result = self.sync_egg_deployment_in(egg, &SyncContext::default(), &mut manifest);
+        self.save_deployment_manifest(&manifest)?;
+        result
+    }
+
+    /// Like [`Yolk::sync_egg_deployment`], but passing the given context of the current sync on to the egg's hooks.
+    ///
+    /// The changes to the deployment are recorded in the given `manifest`, which the caller is responsible for saving.
+    #[tracing::instrument(skip_all, fields(egg.name = %egg.name()))]
+    pub fn sync_egg_deployment_in(
+        &self,
+        egg: &Egg,
+        sync: &SyncContext,
+        manifest: &mut DeploymentManifest,
+    ) -> Result<bool, MultiError> {
         let deployed = egg
             .is_deployed()
             .with_context(|| format!("Failed to check deployment state for egg {}", egg.name()))?;
@@ -229,79 +692,245 @@ impl Yolk {
             egg.enabled = egg.config().enabled,
             "Checking egg deployment"
         );
-        let mappings = egg
-            .config()
-            .targets_expanded(self.yolk_paths.home_path(), egg.path())
+        let targets = egg
+            .enabled_targets()
             .context("Failed to expand targets config for egg")?;
 
         if egg.config().enabled && !deployed {
-            if mappings.is_empty() {
+            if targets.is_empty() {
                 tracing::warn!(
                     "Egg {} has no deployment targets; skipping deploy",
                     egg.name()
                 );
                 return Ok(false);
             }
-            let mut deployer = Deployer::new();
+            let mut deployer = self.deployer_for(egg)?;
             tracing::debug!("Deploying egg {}", egg.name());
 
-            let result = self.deploy_egg(&mut deployer, egg, &mappings);
+            let result = self.deploy_egg(&mut deployer, egg, &targets, sync, manifest);
             if result.is_ok() {
                 tracing::info!("Successfully deployed egg {}", egg.name());
             }
 
             if result.is_ok() {
-                let mut deployed_symlinks = deployer.created_symlinks().clone();
-                deployed_symlinks.extend(
-                    deployer
-                        .failed_creations()
-                        .iter()
-                        .map(|(_, symlink)| symlink.clone()),
-                );
-                deployer.try_run_elevated()?;
-                if let Err(e) =
-                    self.cleanup_stale_symlinks_for(&mut deployer, egg.name(), &deployed_symlinks)
-                {
+                run_elevated_or_rollback(&mut deployer)?;
+                let deployed = manifest_entries_for(&deployer, egg, &targets);
+                if let Err(e) = self.cleanup_stale_deployments_for(
+                    &mut deployer,
+                    egg.name(),
+                    &deployed,
+                    manifest,
+                ) {
                     tracing::error!("{e:?}");
                 }
                 deployer.try_run_elevated()?;
-            } else {
-                deployer.try_run_elevated()?;
+                self.run_egg_hook(egg, ShellHookKey::PostDeploy, sync, &[])?;
             }
             result.map(|()| true)
-        } else if !egg.config().enabled && deployed {
-            let mut deployer = Deployer::new();
+        } else if egg.config().enabled {
+            // The egg is already deployed, but things may have changed since the last sync:
+            // Copies may be outdated, permissions may have been changed, targets may have been disabled,
+            // and symlinks may have to switch between relative and absolute targets.
+            let mut deployer = self.deployer_for(egg)?;
+            let previous_copies = manifest.copies(egg.name());
+            let still_deployed = self.still_deployed_entries(egg, &targets, manifest)?;
+            let mut errs = Vec::new();
+            for entry in &still_deployed {
+                if entry.directory || !entry.path.is_symlink() {
+                    continue;
+                }
+                if let Err(e) = deployer.update_symlink_style(&entry.path) {
+                    errs.push(
+                        e.wrap_err(format!("Failed to update symlink {}", entry.path.abbr())),
+                    );
+                }
+            }
+            for target in targets.iter().filter(|target| target.is_copy()) {
+                if let Err(e) = deployer.copy_recursive(
+                    &target.in_egg,
+                    &target.deployed,
+                    &previous_copies,
+                    &*self.drift_handler,
+                ) {
+                    errs.push(
+                        e.wrap_err(format!("Failed to update copy of {}", target.in_egg.abbr())),
+                    );
+                }
+            }
+            if errs.is_empty() {
+                if let Err(e) = self.apply_permissions(&mut deployer, egg) {
+                    errs.push(e);
+                }
+            }
+            if !errs.is_empty() {
+                if let Err(e) = deployer.rollback() {
+                    errs.push(e.wrap_err("Failed to roll back partial update"));
+                }
+                return Err(MultiError::new(
+                    format!("Failed to update deployment of egg {}", egg.name()),
+                    errs,
+                ));
+            }
+            run_elevated_or_rollback(&mut deployer)?;
+            let mut deployed = still_deployed;
+            for entry in manifest_entries_for(&deployer, egg, &targets) {
+                if !deployed.iter().any(|existing| existing.path == entry.path) {
+                    deployed.push(entry);
+                }
+            }
+            let unchanged = {
+                let recorded = manifest.entries(egg.name());
+                recorded.len() == deployed.len()
+                    && deployed
+                        .iter()
+                        .all(|entry| recorded.iter().any(|old| old.path == entry.path))
+            };
+            if unchanged {
+                // Nothing can have become stale, so only the hashes of updated copies need to be recorded.
+                cov_mark::hit!(skip_cleanup_of_unchanged_deployment);
+                manifest.set_entries(egg.name(), deployed);
+            } else {
+                self.cleanup_stale_deployments_for(&mut deployer, egg.name(), &deployed, manifest)?;
+            }
+            deployer.try_run_elevated()?;
+            Ok(true)
+        } else if deployed {
+            let mut deployer = self
+                .new_deployer()
+                .with_ignore(egg.ignore_matcher()?.clone());
             cov_mark::hit!(undeploy);
             tracing::debug!("Removing egg {}", egg.name());
-            let result = self.undeploy_egg(&mut deployer, egg, &mappings);
+            let result = self.undeploy_egg(&mut deployer, egg, &targets, sync, manifest);
             if result.is_ok() {
                 tracing::info!("Successfully undeployed egg {}", egg.name());
+                run_elevated_or_rollback(&mut deployer)?;
             }
 
-            deployer.try_run_elevated()?;
-            let mut deployer = Deployer::new();
-            if let Err(e) = self.cleanup_stale_symlinks_for(&mut deployer, egg.name(), &[]) {
+            let mut deployer = self.new_deployer();
+            if let Err(e) =
+                self.cleanup_stale_deployments_for(&mut deployer, egg.name(), &[], manifest)
+            {
                 tracing::error!("{e:?}");
             }
             deployer.try_run_elevated()?;
 
+            if result.is_ok() {
+                self.run_egg_hook(egg, ShellHookKey::PostUndeploy, sync, &[])?;
+            }
             result.map(|()| false)
         } else {
-            Ok(deployed)
+            Ok(false)
+        }
+    }
+
+    /// The symlinks and directories recorded in the deployment manifest for the given egg that belong to one of its enabled `targets`.
+    ///
+    /// Entries that do not record which file in the egg they point to are kept as well, as it is impossible to tell where they belong.
+    fn still_deployed_entries(
+        &self,
+        egg: &Egg,
+        targets: &[ExpandedTarget],
+        manifest: &DeploymentManifest,
+    ) -> Result<Vec<ManifestEntry>> {
+        let all_targets = egg.targets()?;
+        Ok(manifest
+            .entries(egg.name())
+            .iter()
+            .filter(|entry| entry.hash.is_none())
+            .filter(|entry| {
+                entry.source.as_os_str().is_empty()
+                    || ExpandedTarget::find_innermost(&all_targets, &entry.source)
+                        .is_some_and(|target| !target.is_copy() && targets.contains(target))
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Write the deployment manifest back to disk, unless this is a dry run.
+    fn save_deployment_manifest(&self, manifest: &DeploymentManifest) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        self.yolk_paths.save_deployment_manifest(manifest)
+    }
+
+    /// Apply the mode and owner configured for the targets of the given egg.
+    fn apply_permissions(&self, deployer: &mut Deployer, egg: &Egg) -> Result<()> {
+        for (path, permissions) in egg.permission_targets()? {
+            deployer
+                .set_permissions(&path, &permissions)
+                .wrap_err_with(|| format!("Failed to apply permissions of {}", path.abbr()))?;
+        }
+        Ok(())
+    }
+
+    /// Offer to move the files that were backed up when deploying the given egg back into their original location.
+    fn restore_backups(&self, egg: &Egg) -> Result<()> {
+        let backup_sets = self.yolk_paths.egg_backups(egg.name()).list()?;
+        if backup_sets.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<PathBuf> = backup_sets
+            .iter()
+            .flat_map(|set| set.paths.iter().cloned())
+            .collect();
+        if self.dry_run {
+            util::print_dry_run(format!(
+                "offer to restore {} backed up files of egg {}",
+                paths.len(),
+                egg.name()
+            ));
+            return Ok(());
+        }
+        if !(self.restore_handler)(egg.name(), &paths)? {
+            return Ok(());
+        }
+        for set in backup_sets {
+            let mut fully_restored = true;
+            for original in &set.paths {
+                let backup_path = set.backup_path_of(original);
+                if !backup_path.exists() && !backup_path.is_symlink() {
+                    continue;
+                }
+                if original.exists() || original.is_symlink() {
+                    tracing::warn!(
+                        "Not restoring backup of {}, as something else exists there now",
+                        original.abbr()
+                    );
+                    fully_restored = false;
+                    continue;
+                }
+                if let Some(parent) = original.parent() {
+                    fs_err::create_dir_all(parent).into_diagnostic()?;
+                }
+                util::rename_safely(&backup_path, original)?;
+                cov_mark::hit!(restore_backup);
+                tracing::info!("Restored backup of {}", original.abbr());
+            }
+            if fully_restored {
+                fs_err::remove_dir_all(&set.dir).into_diagnostic()?;
+            } else {
+                tracing::warn!("Remaining backups are kept in {}", set.dir.abbr());
+            }
         }
+        Ok(())
     }
 
-    /// Check through the old symlinks from the cache file of a given egg,
-    /// and remove any that are not included in the `deployed_symlinks` list.
-    pub fn cleanup_stale_symlinks_for(
+    /// Check through the entries recorded in the deployment `manifest` for a given egg,
+    /// remove any that are not included in the `deployed` list, and record `deployed` in the manifest.
+    ///
+    /// Symlinks are only removed if they still point into the egg,
+    /// and copies are only removed if they were not modified since yolk deployed them.
+    /// Directories that yolk created are removed once they are empty.
+    /// As long as they still contain something that is part of `deployed`, they are kept in the manifest.
+    pub fn cleanup_stale_deployments_for(
         &self,
         deployer: &mut Deployer,
         egg_name: &str,
-        deployed_symlinks: &[PathBuf],
+        deployed: &[ManifestEntry],
+        manifest: &mut DeploymentManifest,
     ) -> Result<(), MultiError> {
         let mut errs = Vec::new();
-        let old_symlinks_db = self.yolk_paths.previous_egg_deployment_locations_db()?;
-        let old_symlinks = old_symlinks_db.read(egg_name)?;
 
         // Compare paths in their canonical form — otherwise on platforms where
         // the temp/home dir contains symlinks (e.g. macOS `/var` → `/private/var`)
@@ -309,51 +938,183 @@ impl Yolk {
         // `/private/var/...` across syncs, leading us to mistakenly treat a
         // freshly-deployed link as stale or fail to recognize a symlink-to-egg.
         let canonical_egg_path = self.paths().egg_path(egg_name).canonical().ok();
-        let deployed_canonical: Vec<PathBuf> = deployed_symlinks
+        let deployed_canonical: Vec<PathBuf> = deployed
             .iter()
-            .map(|p| canonicalize_parent(p))
+            .map(|entry| canonicalize_parent(&entry.path))
             .collect();
-        for old_symlink in old_symlinks {
-            let old_canonical = canonicalize_parent(&old_symlink);
-            if !deployed_canonical.contains(&old_canonical) {
-                let is_symlink_to_egg = if old_symlink.exists() && old_symlink.is_symlink() {
-                    match old_symlink.fs_err_read_link() {
-                        Ok(x) => canonical_egg_path
-                            .as_ref()
-                            .is_some_and(|p| x.starts_with(p)),
-                        Err(e) => {
-                            errs.push(miette::Report::from_err(e));
-                            false
+        let mut stale_dirs = Vec::new();
+        for old_entry in manifest.entries(egg_name) {
+            let old_path = &old_entry.path;
+            if deployed_canonical.contains(&canonicalize_parent(old_path)) {
+                continue;
+            }
+            if old_entry.directory {
+                stale_dirs.push(old_entry.clone());
+                continue;
+            }
+            if let Some(old_hash) = &old_entry.hash {
+                if !old_path.is_file() || old_path.is_symlink() {
+                    continue;
+                }
+                match util::file_content_hash(old_path) {
+                    Ok(hash) if hash == *old_hash => {
+                        tracing::info!("Removing stale copy at {}", old_path.abbr());
+                        cov_mark::hit!(delete_stale_copy);
+                        if let Err(e) = deployer.delete_copy(old_path) {
+                            errs.push(e.wrap_err(format!(
+                                "Failed to remove old copy {}",
+                                old_path.abbr()
+                            )));
                         }
                     }
-                } else {
-                    false
-                };
-                if is_symlink_to_egg {
-                    tracing::info!("Removing stale symlink at {}", old_symlink.abbr());
-                    cov_mark::hit!(delete_stale_symlink);
-                    if let Err(e) = deployer.delete_symlink(&old_symlink) {
-                        errs.push(e.wrap_err(format!(
-                            "Failed to remove old symlink {}",
-                            old_symlink.abbr()
-                        )));
+                    Ok(_) => tracing::warn!(
+                        "Not removing {}, as it was modified since yolk copied it there",
+                        old_path.abbr()
+                    ),
+                    Err(e) => errs.push(e),
+                }
+                continue;
+            }
+            let is_symlink_to_egg = if old_path.exists() && old_path.is_symlink() {
+                match old_path.read_link_absolute() {
+                    Ok(x) => canonical_egg_path
+                        .as_ref()
+                        .is_some_and(|p| x.starts_with(p)),
+                    Err(e) => {
+                        errs.push(e);
+                        false
+                    }
+                }
+            } else {
+                false
+            };
+            if is_symlink_to_egg {
+                tracing::info!("Removing stale symlink at {}", old_path.abbr());
+                cov_mark::hit!(delete_stale_symlink);
+                if let Err(e) = deployer.delete_symlink(old_path) {
+                    errs.push(
+                        e.wrap_err(format!("Failed to remove old symlink {}", old_path.abbr())),
+                    );
+                }
+            }
+        }
+
+        // Remove nested directories before their parents.
+        stale_dirs.sort_by_key(|entry| std::cmp::Reverse(entry.path.components().count()));
+        let mut entries = deployed.to_vec();
+        for old_entry in stale_dirs {
+            let old_path = &old_entry.path;
+            match deployer.remove_dir_if_empty(old_path) {
+                Ok(true) => {
+                    cov_mark::hit!(delete_empty_created_dir);
+                    tracing::info!("Removed empty directory {}", old_path.abbr());
+                }
+                Ok(false) if old_path.is_dir() => {
+                    let still_used = deployed_canonical
+                        .iter()
+                        .any(|path| path.starts_with(canonicalize_parent(old_path)));
+                    if still_used {
+                        entries.push(old_entry);
+                    } else {
+                        tracing::info!(
+                            "Leaving directory {} in place, as it is not empty",
+                            old_path.abbr()
+                        );
                     }
                 }
+                Ok(false) => {}
+                Err(e) => {
+                    errs.push(e.wrap_err(format!("Failed to remove directory {}", old_path.abbr())))
+                }
             }
         }
-        old_symlinks_db.write(egg_name, deployed_symlinks)?;
+        manifest.set_entries(egg_name, entries);
 
         if errs.is_empty() {
             Ok(())
         } else {
             Err(MultiError::new(
-                format!("Failed to clean up some stale symlinks for egg {egg_name}"),
+                format!("Failed to clean up some stale deployments for egg {egg_name}"),
                 errs,
             ))
         }
     }
 
+    /// Find all copied files whose deployed version was modified since yolk copied it there.
+    pub fn find_drifted_copies(&self) -> Result<Vec<DriftedCopy>> {
+        let manifest = self.yolk_paths.load_deployment_manifest()?;
+        let mut drifted = Vec::new();
+        for egg in self.list_eggs()? {
+            if !egg.config().enabled || !egg.has_copy_targets()? {
+                continue;
+            }
+            let previous_copies = manifest.copies(egg.name());
+            for (in_egg, deployed) in egg.copy_targets()? {
+                let Some(previous_hash) = previous_copies.get(&deployed) else {
+                    continue;
+                };
+                if deployed.is_file() && util::file_content_hash(&deployed)? != *previous_hash {
+                    drifted.push(DriftedCopy { in_egg, deployed });
+                }
+            }
+        }
+        drifted.sort_by(|a, b| a.deployed.cmp(&b.deployed));
+        Ok(drifted)
+    }
+
+    /// Find all deployed files whose mode or owner differ from what is configured for them.
+    pub fn find_permission_drift(&self) -> Result<Vec<PermissionDrift>> {
+        let mut drifted = Vec::new();
+        for egg in self.list_eggs()? {
+            if !egg.config().enabled {
+                continue;
+            }
+            for (path, permissions) in egg.permission_targets()? {
+                if !path.exists() {
+                    continue;
+                }
+                let differences = permissions.differences(&path)?;
+                if !differences.is_empty() {
+                    drifted.push(PermissionDrift {
+                        egg_name: egg.name().to_string(),
+                        path,
+                        differences,
+                    });
+                }
+            }
+        }
+        drifted.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(drifted)
+    }
+
+    /// Find all entries of the deployment manifest, together with the name of their egg,
+    /// whose deployed file was removed or replaced since yolk deployed it.
+    pub fn find_missing_deployments(&self) -> Result<Vec<(String, ManifestEntry)>> {
+        let manifest = self.yolk_paths.load_deployment_manifest()?;
+        let mut missing = Vec::new();
+        for (egg_name, entries) in &manifest.eggs {
+            for entry in entries {
+                let still_deployed = if entry.hash.is_some() {
+                    entry.path.is_file()
+                } else if entry.directory {
+                    entry.path.is_dir() && !entry.path.is_symlink()
+                } else {
+                    entry.path.is_symlink()
+                        && (entry.source.as_os_str().is_empty()
+                            || entry.path.read_link_absolute().ok().as_ref() == Some(&entry.source))
+                };
+                if !still_deployed {
+                    missing.push((egg_name.clone(), entry.clone()));
+                }
+            }
+        }
+        Ok(missing)
+    }
+
     /// fetch the `eggs` variable from a given EvalCtx.
+    ///
+    /// Settings that eggs don't declare themselves are filled in from the global settings in the yolk.rhai file,
+    /// such as `relative_symlinks`.
     pub fn load_egg_configs(&self, eval_ctx: &mut EvalCtx) -> Result<HashMap<String, EggConfig>> {
         let (_, yolk_file_module) = eval_ctx
             .yolk_file_module()
@@ -361,30 +1122,151 @@ impl Yolk {
         let eggs_map = yolk_file_module
             .get_var_value::<rhai::Map>("eggs")
             .ok_or_else(|| miette!("Could not find an `eggs` variable in scope"))?;
+        let relative_symlinks = match yolk_file_module.get_var("relative_symlinks") {
+            Some(value) => value
+                .as_bool()
+                .map_err(|t| miette!("`relative_symlinks` must be a boolean, but got {t}"))?,
+            None => false,
+        };
         Ok(eggs_map
             .into_iter()
-            .map(|(x, v)| Ok((x.into(), EggConfig::from_dynamic(v)?)))
+            .map(|(x, v)| {
+                let mut config = EggConfig::from_dynamic(v)?;
+                config.relative_symlinks.get_or_insert(relative_symlinks);
+                Ok((x.into(), config))
+            })
             .collect::<Result<HashMap<_, _>, RhaiScriptError>>()?)
     }
 
     /// First, sync the deployment of all eggs to the local system.
     /// Then, update any templated files in the eggs to the given mode.
-    #[tracing::instrument(skip_all, fields(?mode, %update_deployments))]
+    ///
+    /// Eggs are synced after the eggs they depend on.
+    /// When updating deployments, an enabled egg whose dependency is disabled or failed to sync is skipped.
     pub fn sync_to_mode(&self, mode: EvalMode, update_deployments: bool) -> Result<(), MultiError> {
+        self.sync_eggs_to_mode(mode, update_deployments, &EggFilter::default())
+    }
+
+    /// Like [`Yolk::sync_to_mode`], but only sync the eggs selected by the given filter.
+    #[tracing::instrument(skip_all, fields(?mode, %update_deployments, ?filter))]
+    pub fn sync_eggs_to_mode(
+        &self,
+        mode: EvalMode,
+        update_deployments: bool,
+        filter: &EggFilter,
+    ) -> Result<(), MultiError> {
+        self.sync_eggs(mode, update_deployments, filter, false)
+    }
+
+    /// Sync all templates to the given mode, without touching deployments or running any sync or template hooks.
+    ///
+    /// Used to temporarily switch between canonical and local state.
+    fn flip_templates_to_mode(&self, mode: EvalMode) -> Result<(), MultiError> {
+        self.sync_eggs(mode, false, &EggFilter::default(), true)
+    }
+
+    /// Sync the eggs selected by the filter.
+    ///
+    /// Unless the sync is `temporary`, the `pre_sync` and `post_sync` hooks of the yolk.rhai file
+    /// and the `post_template_change` hooks of the eggs are run.
+    fn sync_eggs(
+        &self,
+        mode: EvalMode,
+        update_deployments: bool,
+        filter: &EggFilter,
+        temporary: bool,
+    ) -> Result<(), MultiError> {
         tracing::debug!("Syncing eggs to {mode:?}");
         let mut eval_ctx = self.prepare_eval_ctx_for_templates(mode)?;
 
         let mut errs = Vec::new();
         let egg_configs = self.load_egg_configs(&mut eval_ctx)?;
+        filter.validate(&egg_configs)?;
+        if !temporary {
+            errs.extend(eggs_config::unknown_dependency_errors(&egg_configs));
+        }
+        let sync = SyncContext {
+            temporary,
+            ..SyncContext::from_eval_ctx(mode, &eval_ctx)?
+        };
+        let global_hooks = match temporary {
+            true => GlobalHooks::default(),
+            false => self.load_global_hooks(&eval_ctx)?,
+        };
+        self.run_global_hook(
+            &global_hooks,
+            GlobalHookKey::PreSync,
+            &sync.with_eval_ctx(&eval_ctx),
+            None,
+        )?;
+        let order = eggs_config::sync_order(&egg_configs)?
+            .into_iter()
+            .filter(|name| filter.matches(name, &egg_configs[name]))
+            .collect::<Vec<_>>();
+        if order.is_empty() && !filter.is_empty() {
+            tracing::warn!("No eggs match the given filter");
+        }
 
-        for (name, egg_config) in egg_configs.into_iter() {
+        let mut manifest = match update_deployments {
+            true => Some(self.yolk_paths.load_deployment_manifest()?),
+            false => None,
+        };
+        let mut failed = HashSet::new();
+        let mut summary = SyncSummary::default();
+        for name in order {
+            let egg_config = &egg_configs[&name];
+            if update_deployments && egg_config.enabled {
+                let failed_dependency = egg_config.depends_on.iter().find_map(|dependency| {
+                    match egg_configs.get(dependency) {
+                        None => Some((dependency, "does not exist")),
+                        Some(_) if failed.contains(dependency) => {
+                            Some((dependency, "failed to sync"))
+                        }
+                        Some(dependency_config) if !dependency_config.enabled => {
+                            Some((dependency, "is disabled"))
+                        }
+                        Some(_) => None,
+                    }
+                });
+                if let Some((dependency, reason)) = failed_dependency {
+                    cov_mark::hit!(skip_egg_with_failed_dependency);
+                    tracing::warn!(
+                        "Skipping egg `{name}`, as its dependency `{dependency}` {reason}"
+                    );
+                    summary.failed.push(name.clone());
+                    failed.insert(name);
+                    continue;
+                }
+            }
             if let Err(e) = self
-                .sync_egg_to_mode(&mut eval_ctx, &name, egg_config, update_deployments)
+                .sync_egg_to_mode(
+                    &mut eval_ctx,
+                    &sync,
+                    &name,
+                    egg_config.clone(),
+                    &mut summary,
+                    manifest.as_mut(),
+                )
                 .wrap_err_with(|| format!("Failed to sync egg `{name}`"))
             {
                 errs.push(e);
+                summary.failed.push(name.clone());
+                failed.insert(name);
+            }
+        }
+        if let Some(manifest) = &manifest {
+            if let Err(e) = self.save_deployment_manifest(manifest) {
+                errs.push(e);
             }
         }
+        if let Err(e) = self.run_global_hook(
+            &global_hooks,
+            GlobalHookKey::PostSync,
+            &sync.with_eval_ctx(&eval_ctx),
+            Some(&summary),
+        ) {
+            errs.push(miette::Report::from_err(e));
+        }
         if errs.is_empty() {
             Ok(())
         } else {
@@ -403,33 +1285,203 @@ impl Yolk {
         let egg_config = egg_configs
             .remove(name)
             .ok_or_else(|| miette!("No egg with name {name}"))?;
-        self.sync_egg_to_mode(&mut eval_ctx, name, egg_config, update_deployments)
+        let sync = SyncContext::from_eval_ctx(mode, &eval_ctx)?;
+        let mut manifest = match update_deployments {
+            true => Some(self.yolk_paths.load_deployment_manifest()?),
+            false => None,
+        };
+        let result = self.sync_egg_to_mode(
+            &mut eval_ctx,
+            &sync,
+            name,
+            egg_config,
+            &mut SyncSummary::default(),
+            manifest.as_mut(),
+        );
+        if let Some(manifest) = &manifest {
+            self.save_deployment_manifest(manifest)?;
+        }
+        result
     }
 
-    #[tracing::instrument(skip_all, fields(%name, %sync_deployment, ?egg_config))]
+    /// Sync the templates of the given egg, and its deployment if a deployment `manifest` to record it in is given.
+    #[tracing::instrument(skip_all, fields(%name, sync_deployment = manifest.is_some(), ?egg_config))]
     fn sync_egg_to_mode(
         &self,
         eval_ctx: &mut EvalCtx,
+        sync: &SyncContext,
         name: &str,
         egg_config: EggConfig,
-        sync_deployment: bool,
+        summary: &mut SyncSummary,
+        mut manifest: Option<&mut DeploymentManifest>,
     ) -> Result<()> {
         let egg = self.yolk_paths.get_egg(name, egg_config)?;
-        if sync_deployment {
-            self.sync_egg_deployment(&egg)?;
-        }
-        let templates_expanded = egg.config().templates_globexpanded(egg.path())?;
-        for tmpl_path in templates_expanded {
-            if tmpl_path.is_file() {
-                self.sync_template_file(eval_ctx, &tmpl_path)?;
-            } else if !tmpl_path.exists() {
+        let was_deployed = manifest.is_some() && egg.is_deployed()?;
+        let mut record_deployment = |deployed: bool| match (was_deployed, deployed) {
+            (false, true) => summary.deployed.push(name.to_string()),
+            (true, false) => summary.undeployed.push(name.to_string()),
+            _ => {}
+        };
+        // Copies need to be made from the already synced templates, while symlinked eggs
+        // should still be deployed even if rendering some template fails.
+        let copy_after_templates = egg.has_copy_targets()?;
+        if let Some(manifest) = manifest.as_deref_mut().filter(|_| !copy_after_templates) {
+            record_deployment(self.sync_egg_deployment_in(
+                &egg,
+                &sync.with_eval_ctx(eval_ctx),
+                manifest,
+            )?);
+        }
+        let templates_expanded = egg.templates_globexpanded()?;
+        let changed_files =
+            eval_ctx.with_scoped_global("EGG", egg_rhai_object(&egg)?, |eval_ctx| {
+                let mut changed_files = Vec::new();
+                for tmpl_path in &templates_expanded {
+                    if tmpl_path.is_file() {
+                        let comment_style = egg.config().comment_style_for(egg.path(), tmpl_path);
+                        if self.sync_template_file(eval_ctx, tmpl_path, comment_style)? {
+                            changed_files.push(tmpl_path.clone());
+                        }
+                    } else if !tmpl_path.exists() {
+                        tracing::warn!(
+                            "{} was specified as templated file, but doesn't exist",
+                            tmpl_path.abbr()
+                        );
+                    }
+                }
+                Ok::<_, miette::Report>(changed_files)
+            })?;
+        if !sync.temporary {
+            let untemplated = egg.config().untemplated_files_with_tags(
+                egg.path(),
+                &templates_expanded,
+                egg.ignore_matcher()?,
+            )?;
+            for path in untemplated {
+                cov_mark::hit!(warn_untemplated_file);
                 tracing::warn!(
-                    "{} was specified as templated file, but doesn't exist",
-                    tmpl_path.abbr()
+                    "{} contains yolk tags, but is not a template of egg {name}. Add it to the `templates` of the egg, or exclude it with a `!` pattern",
+                    path.abbr()
                 );
             }
         }
-        Ok(())
+        if let Some(manifest) = manifest.filter(|_| copy_after_templates) {
+            record_deployment(self.sync_egg_deployment_in(
+                &egg,
+                &sync.with_eval_ctx(eval_ctx),
+                manifest,
+            )?);
+        }
+        if !changed_files.is_empty() {
+            summary.templates_changed.push(name.to_string());
+        }
+        self.run_template_change_hook(&egg, &sync.with_eval_ctx(eval_ctx), &changed_files)
+    }
+
+    /// Run the `post_template_change` hook of an egg, if any of its templates changed in local mode.
+    fn run_template_change_hook(
+        &self,
+        egg: &Egg,
+        sync: &SyncContext,
+        changed_files: &[PathBuf],
+    ) -> Result<()> {
+        if changed_files.is_empty() || sync.mode != EvalMode::Local || sync.temporary {
+            return Ok(());
+        }
+        Ok(self.run_egg_hook(egg, ShellHookKey::PostTemplateChange, sync, changed_files)?)
+    }
+
+    /// Sync the given template files to the given mode, as done by `yolk watch`.
+    ///
+    /// The `eval_ctx` must have been prepared for the same mode.
+    /// Files that are not a template of any egg are ignored.
+    /// The `post_template_change` hook of each egg runs once for all of its changed templates.
+    pub fn sync_template_files(
+        &self,
+        eval_ctx: &mut EvalCtx,
+        mode: EvalMode,
+        paths: &HashSet<PathBuf>,
+    ) -> Result<(), MultiError> {
+        let egg_configs = self.load_egg_configs(eval_ctx)?;
+        let sync = SyncContext::from_eval_ctx(mode, eval_ctx)?;
+        let mut errs = Vec::new();
+        for name in eggs_config::sync_order(&egg_configs)? {
+            let egg = self.yolk_paths.get_egg(&name, egg_configs[&name].clone())?;
+            let templates = egg.templates_globexpanded()?;
+            let changed_files =
+                eval_ctx.with_scoped_global("EGG", egg_rhai_object(&egg)?, |eval_ctx| {
+                    let mut changed_files = Vec::new();
+                    for tmpl_path in templates {
+                        if !paths.contains(&tmpl_path) {
+                            continue;
+                        }
+                        let comment_style = egg.config().comment_style_for(egg.path(), &tmpl_path);
+                        match self.sync_template_file(eval_ctx, &tmpl_path, comment_style) {
+                            Ok(true) => changed_files.push(tmpl_path),
+                            Ok(false) => {}
+                            Err(e) => errs.push(e),
+                        }
+                    }
+                    changed_files
+                });
+            if let Err(e) =
+                self.run_template_change_hook(&egg, &sync.with_eval_ctx(eval_ctx), &changed_files)
+            {
+                errs.push(e);
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(MultiError::new("Failed to sync some templates", errs))
+        }
+    }
+
+    /// Render the given templates without writing them back, i.e. to check them for errors.
+    ///
+    /// Like in [`Self::sync_template_files`], every template is rendered with the `EGG` object and the comment style of the egg it belongs to.
+    /// Paths that aren't a template of any egg are skipped.
+    pub fn eval_template_files(
+        &self,
+        eval_ctx: &mut EvalCtx,
+        paths: &HashSet<PathBuf>,
+    ) -> Result<HashMap<PathBuf, String>, MultiError> {
+        let egg_configs = self.load_egg_configs(eval_ctx)?;
+        let mut rendered = HashMap::new();
+        let mut errs = Vec::new();
+        for name in eggs_config::sync_order(&egg_configs)? {
+            let egg = self.yolk_paths.get_egg(&name, egg_configs[&name].clone())?;
+            let templates = egg.templates_globexpanded()?;
+            eval_ctx.with_scoped_global("EGG", egg_rhai_object(&egg)?, |eval_ctx| {
+                for tmpl_path in templates {
+                    if !paths.contains(&tmpl_path) {
+                        continue;
+                    }
+                    let comment_style = egg.config().comment_style_for(egg.path(), &tmpl_path);
+                    let result = fs_err::read_to_string(&tmpl_path)
+                        .into_diagnostic()
+                        .and_then(|content| {
+                            self.eval_template_with_comment_style(
+                                eval_ctx,
+                                &tmpl_path.to_string_lossy(),
+                                &content,
+                                comment_style,
+                            )
+                        });
+                    match result {
+                        Ok(output) => {
+                            rendered.insert(tmpl_path, output);
+                        }
+                        Err(e) => errs.push(e),
+                    }
+                }
+            });
+        }
+        if errs.is_empty() {
+            Ok(rendered)
+        } else {
+            Err(MultiError::new("Failed to render some templates", errs))
+        }
     }
 
     pub fn prepare_eval_ctx_for_templates(&self, mode: EvalMode) -> Result<EvalCtx> {
@@ -465,7 +1517,18 @@ impl Yolk {
         file_path: &str,
         content: &str,
     ) -> Result<String> {
-        let doc = Document::parse_string_named(file_path, content)
+        self.eval_template_with_comment_style(eval_ctx, file_path, content, None)
+    }
+
+    /// Like [`Self::eval_template`], but using the given comment style instead of inferring it from the template, if set.
+    pub fn eval_template_with_comment_style(
+        &self,
+        eval_ctx: &mut EvalCtx,
+        file_path: &str,
+        content: &str,
+        comment_style: Option<&CommentStyle>,
+    ) -> Result<String> {
+        let doc = Document::parse_string_named(file_path, content, comment_style.cloned())
             .with_context(|| format!("Failed to parse document `{file_path}`"))?;
         tracing::debug!("Rendering template");
         doc.render(eval_ctx)
@@ -473,32 +1536,153 @@ impl Yolk {
     }
 
     /// Sync a single template file in place on the filesystem.
-    pub fn sync_template_file(&self, eval_ctx: &mut EvalCtx, path: impl AsRef<Path>) -> Result<()> {
+    ///
+    /// Returns whether the file was changed, or would have been changed in dry-run mode.
+    pub fn sync_template_file(
+        &self,
+        eval_ctx: &mut EvalCtx,
+        path: impl AsRef<Path>,
+        comment_style: Option<&CommentStyle>,
+    ) -> Result<bool> {
         let path = path.as_ref();
         tracing::debug!("Syncing file {}", path.abbr());
         let content = fs_err::read_to_string(path).into_diagnostic()?;
         let rendered = self
-            .eval_template(eval_ctx, &path.to_string_lossy(), &content)
+            .eval_template_with_comment_style(
+                eval_ctx,
+                &path.to_string_lossy(),
+                &content,
+                comment_style,
+            )
             .with_context(|| format!("Failed to eval template file: {}", path.abbr()))?;
         if rendered == content {
             tracing::debug!("No changes needed in {}", path.abbr());
-            return Ok(());
+            return Ok(false);
+        }
+        if self.dry_run {
+            util::print_dry_run(format!("update templated file {}", path.abbr()));
+            return Ok(true);
         }
         fs_err::write(path, rendered).into_diagnostic()?;
         tracing::info!("Synced templated file {}", path.abbr());
+        Ok(true)
+    }
+
+    /// Check that every template survives being synced to its canonical state and back.
+    ///
+    /// Rendering a template to canonical and back to local must result in exactly its local state,
+    /// and rendering its canonical state again must not change it.
+    /// The local state is what syncing the template results in. For a template that was synced already,
+    /// that is the exact content of the file, otherwise the round trip is compared against what syncing it would result in.
+    ///
+    /// Like during an actual sync, the `EGG` object of the canonical render comes from the egg configs evaluated in canonical mode.
+    pub fn verify_templates(&self) -> Result<(), MultiError> {
+        let mut local_ctx = self.prepare_eval_ctx_for_templates(EvalMode::Local)?;
+        let mut canonical_ctx = self.prepare_eval_ctx_for_templates(EvalMode::Canonical)?;
+        let egg_configs = self.load_egg_configs(&mut local_ctx)?;
+        let canonical_egg_configs = self.load_egg_configs(&mut canonical_ctx)?;
+        let mut errs = Vec::new();
+        for name in eggs_config::sync_order(&egg_configs)? {
+            let egg = self.yolk_paths.get_egg(&name, egg_configs[&name].clone())?;
+            let canonical_config = canonical_egg_configs
+                .get(&name)
+                .unwrap_or(&egg_configs[&name]);
+            let canonical_egg = self.yolk_paths.get_egg(&name, canonical_config.clone())?;
+            for tmpl_path in egg.templates_globexpanded()? {
+                if !tmpl_path.is_file() {
+                    continue;
+                }
+                if let Err(e) = self.verify_template(
+                    &mut local_ctx,
+                    &mut canonical_ctx,
+                    &egg,
+                    &canonical_egg,
+                    &tmpl_path,
+                ) {
+                    errs.push(e);
+                }
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(MultiError::new(
+                "Some templates don't survive a round trip through their canonical state",
+                errs,
+            ))
+        }
+    }
+
+    fn verify_template(
+        &self,
+        local_ctx: &mut EvalCtx,
+        canonical_ctx: &mut EvalCtx,
+        egg: &Egg,
+        canonical_egg: &Egg,
+        path: &Path,
+    ) -> Result<()> {
+        let name = path.to_string_lossy();
+        let comment_style = egg.config().comment_style_for(egg.path(), path);
+        let local_egg_object = egg_rhai_object(egg)?;
+        let canonical_egg_object = egg_rhai_object(canonical_egg)?;
+        let render_local = |eval_ctx: &mut EvalCtx, content: &str| {
+            eval_ctx.with_scoped_global("EGG", local_egg_object.clone(), |eval_ctx| {
+                self.eval_template_with_comment_style(eval_ctx, &name, content, comment_style)
+            })
+        };
+        let render_canonical = |eval_ctx: &mut EvalCtx, content: &str| {
+            eval_ctx.with_scoped_global("EGG", canonical_egg_object.clone(), |eval_ctx| {
+                self.eval_template_with_comment_style(eval_ctx, &name, content, comment_style)
+            })
+        };
+        let content = fs_err::read_to_string(path).into_diagnostic()?;
+        let local = render_local(local_ctx, &content)?;
+        let canonical = render_canonical(canonical_ctx, &local)?;
+        let restored = render_local(local_ctx, &canonical)?;
+        if let Some(offset) = first_difference(&local, &restored) {
+            cov_mark::hit!(template_round_trip_mismatch);
+            return Err(round_trip_error(
+                format!(
+                    "{} changes when rendered to its canonical state and back",
+                    path.abbr()
+                ),
+                &name,
+                local,
+                offset,
+                comment_style,
+                "the local state differs after this tag",
+            ));
+        }
+        let canonical_again = render_canonical(canonical_ctx, &canonical)?;
+        if let Some(offset) = first_difference(&canonical, &canonical_again) {
+            cov_mark::hit!(template_canonical_unstable);
+            return Err(round_trip_error(
+                format!(
+                    "The canonical state of {} changes when rendered again",
+                    path.abbr()
+                ),
+                &name,
+                canonical,
+                offset,
+                comment_style,
+                "the canonical state differs after this tag",
+            ));
+        }
         Ok(())
     }
 
     /// Run a given closure with all templates in their canonical state.
     ///
-    /// First syncs them to canonical then runs the closure, then syncs them back to local.
+    /// First verifies that all templates survive the round trip through their canonical state,
+    /// then syncs them to canonical then runs the closure, then syncs them back to local.
     pub fn with_canonical_state<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.verify_templates()?;
         tracing::info!("Converting all templates into their canonical state");
-        if let Err(canonical_err) = self.sync_to_mode(EvalMode::Canonical, false) {
+        if let Err(canonical_err) = self.flip_templates_to_mode(EvalMode::Canonical) {
             tracing::warn!(
                 "Failed to fully convert templates to canonical state; attempting local restore"
             );
-            if let Err(restore_err) = self.sync_to_mode(EvalMode::Local, false) {
+            if let Err(restore_err) = self.flip_templates_to_mode(EvalMode::Local) {
                 return Err(MultiError::new(
                     "Failed to enter canonical state and failed to restore local state",
                     vec![
@@ -512,7 +1696,7 @@ impl Yolk {
         }
         let result = f();
         tracing::info!("Converting all templates back to the local state");
-        let restore_result = self.sync_to_mode(EvalMode::Local, false);
+        let restore_result = self.flip_templates_to_mode(EvalMode::Local);
         match (result, restore_result) {
             (Ok(value), Ok(())) => Ok(value),
             (Err(err), Ok(())) => Err(err),
@@ -527,10 +1711,17 @@ impl Yolk {
 
     /// Run the yolk.rhai script, load the egg configs and return a list of all eggs.
     pub fn list_eggs(&self) -> Result<Vec<Egg>> {
+        self.list_filtered_eggs(&EggFilter::default())
+    }
+
+    /// Run the yolk.rhai script, load the egg configs and return a list of the eggs selected by the given filter.
+    pub fn list_filtered_eggs(&self, filter: &EggFilter) -> Result<Vec<Egg>> {
         let mut eval_ctx = self.prepare_eval_ctx_for_templates(EvalMode::Local)?;
         let egg_configs = self.load_egg_configs(&mut eval_ctx)?;
+        filter.validate(&egg_configs)?;
         let eggs: Vec<Egg> = egg_configs
             .into_iter()
+            .filter(|(name, config)| filter.matches(name, config))
             .map(|(name, config)| self.yolk_paths.get_egg(&name, config))
             .collect::<Result<_>>()
             .context("Failed to find egg that was configured in yolk.rhai")?;
@@ -550,11 +1741,9 @@ impl Yolk {
             })
             .map(|egg| {
                 egg.and_then(|egg| {
-                    egg.config()
-                        .templates_globexpanded(egg.path())
-                        .with_context(|| {
-                            format!("Failed to globexpand template dirs for egg {}", egg.name())
-                        })
+                    egg.templates_globexpanded().with_context(|| {
+                        format!("Failed to globexpand template dirs for egg {}", egg.name())
+                    })
                 })
             })
             .collect::<Result<Vec<Vec<PathBuf>>>>()?
@@ -614,10 +1803,16 @@ impl Yolk {
                 .get(&name)
                 .ok_or_else(|| miette!("Egg {name} was not found in canonical yolk.rhai"))?;
             miette::ensure!(
-                local_config.templates == canonical_config.templates ,
+                local_config.templates == canonical_config.templates
+                    && local_config.auto_templates == canonical_config.auto_templates,
                 help = "Make sure that template configuration does not depend on the LOCAL or CANONICAL mode",
                 "Egg {name} has a different set of templated files in canonical mode compared to local mode"
             );
+            miette::ensure!(
+                local_config.comment_styles == canonical_config.comment_styles,
+                help = "Make sure that comment styles do not depend on the LOCAL or CANONICAL mode",
+                "Egg {name} has different comment styles in canonical mode compared to local mode"
+            );
         }
         Ok(())
     }
@@ -629,6 +1824,168 @@ pub enum EvalMode {
     Canonical,
 }
 
+/// The mode and data of the sync an egg is deployed in, passed on to the hooks of the egg.
+#[derive(Debug, Clone)]
+pub struct SyncContext<'a> {
+    pub mode: EvalMode,
+    /// The `data` variable of the yolk.rhai file, or `null` if there is none.
+    pub data: serde_json::Value,
+    /// The context the yolk.rhai file was loaded in, used to run Rhai hooks.
+    pub eval_ctx: Option<&'a EvalCtx>,
+    /// Whether the templates are only switched to another mode temporarily, i.e. while committing.
+    /// Temporary syncs don't run `post_template_change` hooks or warn about untemplated files.
+    pub temporary: bool,
+}
+
+impl Default for SyncContext<'_> {
+    fn default() -> Self {
+        Self {
+            mode: EvalMode::Local,
+            data: serde_json::Value::Null,
+            eval_ctx: None,
+            temporary: false,
+        }
+    }
+}
+
+impl<'a> SyncContext<'a> {
+    /// Use the given [`EvalCtx`] to run Rhai hooks.
+    pub fn with_eval_ctx<'b>(&self, eval_ctx: &'b EvalCtx) -> SyncContext<'b> {
+        SyncContext {
+            mode: self.mode,
+            data: self.data.clone(),
+            eval_ctx: Some(eval_ctx),
+            temporary: self.temporary,
+        }
+    }
+
+    /// Take the `data` from the yolk.rhai file loaded into the given [`EvalCtx`].
+    ///
+    /// The returned context does not keep the [`EvalCtx`] itself, see [`SyncContext::with_eval_ctx`].
+    pub fn from_eval_ctx(mode: EvalMode, eval_ctx: &EvalCtx) -> Result<Self> {
+        let data = match eval_ctx
+            .yolk_file_module()
+            .and_then(|(_, module)| module.get_var("data"))
+        {
+            Some(data) => serde_json::to_value(&data)
+                .into_diagnostic()
+                .wrap_err("Failed to convert `data` to JSON")?,
+            None => serde_json::Value::Null,
+        };
+        Ok(Self {
+            mode,
+            data,
+            eval_ctx: None,
+            temporary: false,
+        })
+    }
+}
+
+/// Return the byte offset of the first character in which the two strings differ.
+fn first_difference(a: &str, b: &str) -> Option<usize> {
+    if a == b {
+        return None;
+    }
+    let offset = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((offset, _), _)| offset);
+    Some(offset)
+}
+
+/// Build an error that points at the tag around the given offset in `source`,
+/// or at the line containing it if it's not part of any tag.
+fn round_trip_error(
+    message: String,
+    name: &str,
+    source: String,
+    offset: usize,
+    comment_style: Option<&CommentStyle>,
+    label: &'static str,
+) -> miette::Report {
+    let offset = offset.min(source.len());
+    let span = Document::parse_string_named(name, &source, comment_style.cloned())
+        .ok()
+        .and_then(|doc| doc.tag_line_at(offset))
+        .unwrap_or_else(|| {
+            let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+            let end = source[offset..]
+                .find('\n')
+                .map_or(source.len(), |i| offset + i);
+            start..end
+        });
+    RoundTripError {
+        message,
+        input: miette::NamedSource::new(name, source),
+        span: span.into(),
+        label,
+    }
+    .into()
+}
+
+/// The `EGG` object available to the templates of an egg, containing its `name`, `path` and `data`.
+fn egg_rhai_object(egg: &Egg) -> Result<rhai::Map> {
+    let data = rhai::serde::to_dynamic(&egg.config().data)
+        .map_err(|e| miette!("Failed to convert data of egg {}: {e}", egg.name()))?;
+    Ok(rhai::Map::from([
+        ("name".into(), egg.name().into()),
+        (
+            "path".into(),
+            egg.path().to_string_lossy().to_string().into(),
+        ),
+        ("data".into(), data),
+    ]))
+}
+
+/// The eggs that changed during a sync, passed on to the `post_sync` hook.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    pub deployed: Vec<String>,
+    pub undeployed: Vec<String>,
+    /// Eggs with templates that were changed.
+    pub templates_changed: Vec<String>,
+    /// Eggs that failed to sync, or were skipped because a dependency failed.
+    pub failed: Vec<String>,
+}
+
+impl SyncSummary {
+    /// All eggs that were deployed, undeployed or had their templates changed.
+    pub fn changed(&self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for name in [&self.deployed, &self.undeployed, &self.templates_changed]
+            .into_iter()
+            .flatten()
+        {
+            if !changed.contains(name) {
+                changed.push(name.clone());
+            }
+        }
+        changed
+    }
+
+    /// The lists of eggs in this summary, together with their names.
+    pub fn lists(&self) -> [(&'static str, Vec<String>); 5] {
+        [
+            ("changed", self.changed()),
+            ("deployed", self.deployed.clone()),
+            ("undeployed", self.undeployed.clone()),
+            ("templates_changed", self.templates_changed.clone()),
+            ("failed", self.failed.clone()),
+        ]
+    }
+
+    pub fn to_rhai(&self) -> rhai::Map {
+        self.lists()
+            .into_iter()
+            .map(|(name, eggs)| {
+                let eggs = eggs.into_iter().map(Into::into).collect::<rhai::Array>();
+                (name.into(), eggs.into())
+            })
+            .collect()
+    }
+}
+
 /// Canonicalize the parent of a path, leaving the final component untouched.
 ///
 /// This produces a stable form even when the path itself does not exist (e.g.
@@ -643,3 +2000,33 @@ fn canonicalize_parent(p: &Path) -> PathBuf {
         _ => p.to_path_buf(),
     }
 }
+
+/// Everything deployed by the given deployer, as entries for the deployment manifest of `egg`.
+fn manifest_entries_for(
+    deployer: &Deployer,
+    egg: &Egg,
+    targets: &[ExpandedTarget],
+) -> Vec<ManifestEntry> {
+    deployer.manifest_entries(|source| {
+        ExpandedTarget::find_innermost(targets, source)
+            .map(|target| target.strategy)
+            .unwrap_or(egg.config().strategy)
+    })
+}
+
+/// Run any pending elevated symlink operations, rolling back all changes made by the deployer if that fails.
+fn run_elevated_or_rollback(deployer: &mut Deployer) -> Result<()> {
+    let Err(err) = deployer.try_run_elevated() else {
+        return Ok(());
+    };
+    match deployer.rollback() {
+        Ok(()) => {
+            Err(err.wrap_err("Rolled back all changes, as elevated symlink operations failed"))
+        }
+        Err(rollback_err) => Err(MultiError::new(
+            "Failed to run elevated symlink operations",
+            vec![err, rollback_err.wrap_err("Failed to roll back")],
+        )
+        .into()),
+    }
+}
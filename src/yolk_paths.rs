@@ -1,18 +1,30 @@
 use std::{
+    collections::BTreeSet,
+    ops::Bound,
     path::{Path, PathBuf},
     process::Command,
+    sync::OnceLock,
 };
 
 use fs_err::PathExt;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use miette::{Context as _, IntoDiagnostic, Result};
 use normalize_path::NormalizePath;
 
 use crate::{
-    eggs_config::EggConfig,
+    backup::EggBackups,
+    deploy,
+    eggs_config::{DeploymentStrategy, EggConfig, ExpandedTarget},
     git_utils::Git,
+    hook_trust::HookTrustStore,
+    manifest::DeploymentManifest,
+    permissions::FilePermissions,
     util::{self, PathExt as _},
 };
 
+/// Name of the file inside an egg that lists gitignore-style patterns of files that should not be deployed.
+pub const YOLKIGNORE_FILE: &str = ".yolkignore";
+
 const DEFAULT_YOLK_RHAI: &str = indoc::indoc! {r#"
     export let data = #{
         for_vcs: LOCAL,
@@ -27,6 +39,8 @@ pub struct YolkPaths {
     /// Path to the yolk directory.
     root_path: PathBuf,
     home: PathBuf,
+    /// Directory for local state that must not be part of the yolk directory, such as the hook trust store.
+    data_dir: PathBuf,
 }
 
 pub fn default_yolk_dir() -> PathBuf {
@@ -49,11 +63,16 @@ pub fn default_yolk_dir() -> PathBuf {
 
 impl YolkPaths {
     pub fn new(path: PathBuf, home: PathBuf) -> Result<Self> {
+        let home = home
+            .canonical()
+            .wrap_err("Failed to canonicalize home directory")?;
+        let data_dir = dirs::data_dir()
+            .unwrap_or_else(|| home.join(".local/share"))
+            .join("yolk");
         Ok(YolkPaths {
             root_path: path,
-            home: home
-                .canonical()
-                .wrap_err("Failed to canonicalize home directory")?,
+            home,
+            data_dir,
         })
     }
 
@@ -67,6 +86,10 @@ impl YolkPaths {
             .canonical()
             .expect("Failed to canonicalize home directory");
     }
+    pub fn set_data_dir(&mut self, path: PathBuf) {
+        tracing::trace!("Updating data-dir to {}", path.display());
+        self.data_dir = path;
+    }
 
     #[allow(unused)]
     pub fn check(&self) -> Result<()> {
@@ -192,52 +215,62 @@ impl YolkPaths {
         Egg::open(self.home.clone(), self.egg_path(name), config)
     }
 
-    pub fn previous_egg_deployment_locations_db_path(&self) -> PathBuf {
-        self.root_path.join(".previous_deployment_targets")
+    /// Path to the directory containing backups of files that were in the way of a deployment.
+    pub fn backups_dir_path(&self) -> PathBuf {
+        self.root_path.join(".backups")
     }
 
-    pub fn previous_egg_deployment_locations_db(&self) -> Result<PreviousEggDeploymentLocationsDb> {
-        PreviousEggDeploymentLocationsDb::open(self.root_path.join(".deployed_cache"))
+    pub fn egg_backups(&self, egg_name: &str) -> EggBackups {
+        EggBackups::new(self.backups_dir_path().join(egg_name))
     }
-}
 
-pub struct PreviousEggDeploymentLocationsDb {
-    path: PathBuf,
-}
+    /// Path to the manifest of everything yolk has deployed.
+    pub fn deployment_manifest_path(&self) -> PathBuf {
+        self.root_path.join(".deployment_manifest.json")
+    }
+
+    /// Path to the local store of shell hooks the user trusts to run.
+    ///
+    /// The store is kept in the data directory rather than the yolk directory,
+    /// so nothing inside the repository can mark its own hooks as trusted.
+    /// Every yolk directory has its own store, identified by the hash of its canonical path.
+    pub fn trusted_hooks_path(&self) -> PathBuf {
+        use sha2::Digest as _;
+        let root_path = self
+            .root_path
+            .canonical()
+            .unwrap_or_else(|_| self.root_path.clone());
+        let key = hex::encode(sha2::Sha256::digest(root_path.to_string_lossy().as_bytes()));
+        self.data_dir
+            .join("trusted_hooks")
+            .join(format!("{key}.json"))
+    }
 
-impl PreviousEggDeploymentLocationsDb {
-    fn open(path: PathBuf) -> Result<Self> {
-        fs_err::create_dir_all(&path).into_diagnostic()?;
-        Ok(Self { path })
+    /// Path to the plain-text deployment cache used by older versions of yolk, which gets migrated into the manifest.
+    pub fn legacy_deployment_cache_path(&self) -> PathBuf {
+        self.root_path.join(".deployed_cache")
     }
 
-    pub fn egg_data_path(&self, egg_name: &str) -> PathBuf {
-        self.path.join(egg_name)
+    pub fn load_deployment_manifest(&self) -> Result<DeploymentManifest> {
+        DeploymentManifest::load(
+            self.deployment_manifest_path(),
+            self.legacy_deployment_cache_path(),
+        )
     }
 
-    pub fn read(&self, egg_name: &str) -> Result<Vec<PathBuf>> {
-        let cache_path = self.egg_data_path(egg_name);
-        if cache_path.exists() {
-            Ok(fs_err::read_to_string(cache_path)
-                .into_diagnostic()?
-                .lines()
-                .map(PathBuf::from)
-                .collect())
-        } else {
-            Ok(Vec::new())
-        }
+    pub fn save_deployment_manifest(&self, manifest: &DeploymentManifest) -> Result<()> {
+        manifest.save(
+            self.deployment_manifest_path(),
+            self.legacy_deployment_cache_path(),
+        )
     }
 
-    pub fn write(&self, egg_name: &str, symlinks: &[PathBuf]) -> Result<()> {
-        let cache_path = self.egg_data_path(egg_name);
-        let content = symlinks
-            .iter()
-            .map(|x| x.to_string_lossy())
-            .collect::<Vec<_>>()
-            .join("\n");
-        fs_err::write(cache_path, content)
-            .into_diagnostic()
-            .with_context(|| format!("Failed to update egg deployment cache for egg {egg_name}"))
+    pub fn load_hook_trust_store(&self) -> Result<HookTrustStore> {
+        HookTrustStore::load(self.trusted_hooks_path())
+    }
+
+    pub fn save_hook_trust_store(&self, store: &HookTrustStore) -> Result<()> {
+        store.save(self.trusted_hooks_path())
     }
 }
 
@@ -246,6 +279,8 @@ pub struct Egg {
     egg_dir: PathBuf,
     config: EggConfig,
     home_path: PathBuf,
+    /// The ignore matcher of the egg, built on first use.
+    ignore: OnceLock<EggIgnore>,
 }
 
 impl Egg {
@@ -255,6 +290,7 @@ impl Egg {
             home_path: home.canonical()?,
             egg_dir: egg_path.canonical()?,
             config,
+            ignore: OnceLock::new(),
         })
     }
 
@@ -290,9 +326,19 @@ impl Egg {
     }
 
     /// Check if the egg is _fully_ deployed (-> All contained entries have corresponding symlinks)
+    ///
+    /// For targets using the [`DeploymentStrategy::Copy`] strategy, this checks that all files have been copied to their target.
+    /// Disabled targets are not taken into account.
     #[tracing::instrument(skip_all, fields(egg.name = %self.name()))]
     pub fn is_deployed(&self) -> Result<bool> {
-        if self.config.targets.is_empty() {
+        if self.enabled_targets()?.is_empty() {
+            return Ok(false);
+        }
+        let copies_deployed = self
+            .copy_targets()?
+            .iter()
+            .all(|(_, target)| target.is_file() && !target.is_symlink());
+        if !copies_deployed {
             return Ok(false);
         }
         for x in self.find_deployed_symlinks()? {
@@ -313,16 +359,93 @@ impl Egg {
             .unwrap_or_default()
     }
 
+    /// All targets of this egg, including disabled ones, with their paths expanded.
+    pub fn targets(&self) -> Result<Vec<ExpandedTarget>> {
+        self.config
+            .expanded_targets(&self.home_path, self.path())
+            .context("Failed to expand targets map")
+    }
+
+    /// All targets of this egg that should be deployed, with their paths expanded.
+    pub fn enabled_targets(&self) -> Result<Vec<ExpandedTarget>> {
+        Ok(self
+            .targets()?
+            .into_iter()
+            .filter(|target| target.enabled)
+            .collect())
+    }
+
+    /// Whether any of the enabled targets of this egg is deployed using the [`DeploymentStrategy::Copy`] strategy.
+    pub fn has_copy_targets(&self) -> Result<bool> {
+        Ok(self.enabled_targets()?.iter().any(ExpandedTarget::is_copy))
+    }
+
     /// Iterate over the deployed symlinks of this egg.
     ///
     /// See [`TraverseDeployment`] for more information.
     #[tracing::instrument(skip_all)]
     pub fn find_deployed_symlinks(&self) -> Result<TraverseDeployment> {
-        let targets = self
-            .config
-            .targets_expanded(&self.home_path, self.path())
-            .context("Failed to expand targets map")?;
-        Ok(TraverseDeployment::new(targets))
+        let targets: Vec<_> = self
+            .enabled_targets()?
+            .into_iter()
+            .filter(|target| !target.is_copy())
+            .map(|target| {
+                let no_fold = target.no_fold && target.strategy == DeploymentStrategy::Merge;
+                (target.in_egg, target.deployed, no_fold)
+            })
+            .collect();
+        Ok(TraverseDeployment::new(targets).with_ignore(self.ignore_matcher()?.clone()))
+    }
+
+    /// List all (source, target) file pairs this egg deploys using the [`DeploymentStrategy::Copy`] strategy.
+    pub fn copy_targets(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let ignore = self.ignore_matcher()?;
+        let mut copy_targets = Vec::new();
+        for target in self.enabled_targets()? {
+            if !target.is_copy() {
+                continue;
+            }
+            copy_targets.extend(deploy::expand_copy_targets(
+                target.in_egg,
+                target.deployed,
+                Some(ignore),
+            )?);
+        }
+        Ok(copy_targets)
+    }
+
+    /// List the deployed copies whose mode and owner are managed by this egg, together with the permissions they should have.
+    ///
+    /// Permissions are only supported for copied targets:
+    /// the permissions of a symlink are not used by anything,
+    /// and changing the files inside the egg would affect the yolk repository itself.
+    pub fn permission_targets(&self) -> Result<Vec<(PathBuf, FilePermissions)>> {
+        let mut permissions = self
+            .enabled_targets()?
+            .into_iter()
+            .filter(|target| target.is_copy() && !target.permissions.is_empty())
+            .map(|target| (target.deployed, target.permissions))
+            .collect::<Vec<_>>();
+        permissions.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(permissions)
+    }
+
+    /// The matcher for files that should not be deployed,
+    /// from the `ignore` patterns of the egg config and the `.yolkignore` file in the egg directory.
+    ///
+    /// It is only built once per egg.
+    pub fn ignore_matcher(&self) -> Result<&EggIgnore> {
+        if let Some(ignore) = self.ignore.get() {
+            return Ok(ignore);
+        }
+        let ignore = EggIgnore::new(&self.egg_dir, &self.config.ignore)?;
+        Ok(self.ignore.get_or_init(|| ignore))
+    }
+
+    /// The templates of this egg, see [`EggConfig::templates_globexpanded`].
+    pub fn templates_globexpanded(&self) -> Result<Vec<PathBuf>> {
+        self.config
+            .templates_globexpanded_with_ignore(&self.egg_dir, self.ignore_matcher()?)
     }
 
     /// Find the first deployed symlink of a deployment.
@@ -344,10 +467,92 @@ impl Egg {
     /// Get a mutable reference to the egg configuration. Deliberately only available for tests.
     #[cfg(test)]
     pub fn config_mut(&mut self) -> &mut EggConfig {
+        self.ignore = OnceLock::new();
         &mut self.config
     }
 }
 
+/// Matches files inside an egg against the gitignore-style patterns of files that should not be deployed.
+#[derive(Debug, Clone)]
+pub struct EggIgnore {
+    egg_dir: PathBuf,
+    matcher: Gitignore,
+    /// All ignored files and directories inside the egg, not including the contents of ignored directories.
+    ignored: BTreeSet<PathBuf>,
+}
+
+impl EggIgnore {
+    pub fn new(egg_dir: impl AsRef<Path>, patterns: &[String]) -> Result<Self> {
+        let egg_dir = egg_dir.as_ref();
+        let ignore_file = egg_dir.join(YOLKIGNORE_FILE);
+        if patterns.is_empty() && !ignore_file.is_file() {
+            return Ok(Self {
+                egg_dir: egg_dir.to_path_buf(),
+                matcher: Gitignore::empty(),
+                ignored: BTreeSet::new(),
+            });
+        }
+        let mut builder = GitignoreBuilder::new(egg_dir);
+        if ignore_file.is_file() {
+            if let Some(err) = builder.add(&ignore_file) {
+                return Err(err)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to read {}", ignore_file.abbr()));
+            }
+        }
+        builder
+            .add_line(None, &format!("/{YOLKIGNORE_FILE}"))
+            .into_diagnostic()?;
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid ignore pattern `{pattern}`"))?;
+        }
+        let mut ignore = Self {
+            egg_dir: egg_dir.to_path_buf(),
+            matcher: builder.build().into_diagnostic()?,
+            ignored: BTreeSet::new(),
+        };
+        cov_mark::hit!(collect_ignored_paths);
+        let mut walker = walkdir::WalkDir::new(egg_dir).min_depth(1).into_iter();
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else { continue };
+            if ignore.is_ignored(entry.path()) {
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                ignore.ignored.insert(entry.into_path());
+            }
+        }
+        Ok(ignore)
+    }
+
+    /// Check whether the given path inside the egg should not be deployed.
+    pub fn is_ignored(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        let Ok(relative) = path.strip_prefix(&self.egg_dir) else {
+            return false;
+        };
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        self.matcher
+            .matched_path_or_any_parents(relative, path.is_dir())
+            .is_ignore()
+    }
+
+    /// Check whether anything inside the given directory should not be deployed.
+    pub fn contains_ignored(&self, dir: impl AsRef<Path>) -> bool {
+        let dir = dir.as_ref();
+        // Paths are ordered component-wise, so everything inside `dir` directly follows it.
+        self.ignored
+            .range::<Path, _>((Bound::Excluded(dir), Bound::Unbounded))
+            .next()
+            .is_some_and(|path| path.starts_with(dir))
+    }
+}
+
 /// An iterator that traverses a deployed egg and yields paths to all symlinks of the deployment.
 ///
 /// Returns
@@ -355,20 +560,34 @@ impl Egg {
 /// - `Ok(Err(path_in_egg))` for a path inside an egg that does not have a corresponding deployed symlink
 /// - `Err(err)` if there is an error
 /// - `None` if the traversal is finished
+///
+/// For targets deployed without folding, and for directories containing ignored files,
+/// directories inside the egg are expected to be deployed as real directories, so a symlink to a whole directory counts as not deployed.
 pub struct TraverseDeployment {
-    stack: Vec<(PathBuf, PathBuf)>,
+    /// (in_egg, link, no_fold)
+    stack: Vec<(PathBuf, PathBuf, bool)>,
+    ignore: Option<EggIgnore>,
 }
 impl TraverseDeployment {
-    fn new(stack: impl IntoIterator<Item = (PathBuf, PathBuf)>) -> Self {
+    fn new(stack: impl IntoIterator<Item = (PathBuf, PathBuf, bool)>) -> Self {
         let stack: Vec<_> = stack.into_iter().collect();
-        Self { stack }
+        Self {
+            stack,
+            ignore: None,
+        }
+    }
+
+    /// Skip over files that are ignored, as they are not deployed.
+    fn with_ignore(mut self, ignore: EggIgnore) -> Self {
+        self.ignore = Some(ignore);
+        self
     }
 }
 
 impl Iterator for TraverseDeployment {
     type Item = miette::Result<Result<PathBuf, PathBuf>>;
     fn next(&mut self) -> Option<miette::Result<Result<PathBuf, PathBuf>>> {
-        let (in_egg, link) = self.stack.pop()?;
+        let (in_egg, link, no_fold) = self.stack.pop()?;
         let in_egg = in_egg.normalize();
         let link = link.normalize();
 
@@ -383,11 +602,16 @@ impl Iterator for TraverseDeployment {
             in_egg.abbr(),
         );
 
-        if link.is_symlink() {
-            match (
-                in_egg.canonical(),
-                link.fs_err_read_link().into_diagnostic(),
-            ) {
+        let contains_ignored = || {
+            self.ignore
+                .as_ref()
+                .is_some_and(|ignore| ignore.contains_ignored(&in_egg))
+        };
+        if link.is_symlink() && in_egg.is_dir() && (no_fold || contains_ignored()) {
+            cov_mark::hit!(traverse_folded_dir_without_folding);
+            Some(Ok(Err(in_egg)))
+        } else if link.is_symlink() {
+            match (in_egg.canonical(), link.read_link_absolute()) {
                 (Ok(in_egg), Ok(link)) if in_egg.normalize() == link.normalize() => {
                     Some(Ok(Ok(link)))
                 }
@@ -401,8 +625,15 @@ impl Iterator for TraverseDeployment {
                     Ok(x) => x,
                     Err(e) => return Some(Err(miette::miette!(e))),
                 };
+                if self
+                    .ignore
+                    .as_ref()
+                    .is_some_and(|ignore| ignore.is_ignored(in_egg_entry.path()))
+                {
+                    continue;
+                }
                 let link_entry = link.join(in_egg_entry.file_name());
-                self.stack.push((in_egg_entry.path(), link_entry));
+                self.stack.push((in_egg_entry.path(), link_entry, no_fold));
             }
             self.next()
         } else {
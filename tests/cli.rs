@@ -11,7 +11,7 @@
 
 use assert_cmd::{assert, Command};
 use assert_fs::prelude::{FileWriteStr as _, PathChild as _, PathCreateDir as _};
-use predicates::str::contains;
+use predicates::{prelude::PredicateBooleanExt as _, str::contains};
 
 use yolk::{
     yolk::{EvalMode, Yolk},
@@ -42,7 +42,8 @@ struct TestEnv {
 impl TestEnv {
     pub fn init() -> TestResult<Self> {
         let home = assert_fs::TempDir::new()?;
-        let paths = YolkPaths::new(home.join("yolk"), home.to_path_buf())?;
+        let mut paths = YolkPaths::new(home.join("yolk"), home.to_path_buf())?;
+        paths.set_data_dir(home.join(".local/share/yolk"));
         let yolk = Yolk::new(paths);
         // Ensure neither the in-process library nor the spawned binary touch the
         // real home directory. The binary is additionally given `--home-dir`
@@ -105,6 +106,8 @@ impl TestEnv {
             &self.yolk_root().to_string_lossy(),
             "--home-dir",
             &self.yolk.paths().home_path().to_string_lossy(),
+            "--data-dir",
+            &self.home.join(".local/share/yolk").to_string_lossy(),
         ]);
         yolk_command
     }
@@ -227,3 +230,242 @@ fn test_adopt_can_append_config_and_sync() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn test_sync_dry_run_prints_plan() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{
+                foo: #{ targets: `~/foo`, templates: ["foo.toml"] },
+            };
+        "#})?;
+    env.egg_file("foo/foo.toml")
+        .write_str("foo # {< if false >}")?;
+
+    let mut cmd = env.yolk_cmd();
+    cmd.args(["sync", "--dry-run"]);
+    cmd.assert()
+        .success()
+        .stdout(contains("[dry-run] would create symlink"))
+        .stdout(contains("[dry-run] would update templated file"));
+    assert!(!env.home_file("foo").path().exists());
+    Ok(())
+}
+
+#[test]
+fn test_sync_on_drift_overwrite() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{
+                foo: #{ targets: #{ "foo.toml": "~/foo.toml" }, strategy: "copy" },
+            };
+        "#})?;
+    env.egg_file("foo/foo.toml").write_str("original")?;
+    env.yolk_cmd().arg("sync").assert().success();
+    env.home_file("foo.toml").write_str("modified")?;
+
+    env.yolk_cmd()
+        .args(["sync", "--on-drift", "overwrite"])
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(env.home_file("foo.toml"))?,
+        "original"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sync_on_conflict_backup() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{
+                foo: #{ targets: #{ "foo.toml": "~/foo.toml" } },
+            };
+        "#})?;
+    env.egg_file("foo/foo.toml").write_str("from egg")?;
+    env.home_file("foo.toml").write_str("existing")?;
+    env.yolk_cmd().arg("sync").assert().failure();
+
+    env.yolk_cmd()
+        .args(["sync", "--on-conflict", "backup"])
+        .assert()
+        .success();
+    assert!(env.home_file("foo.toml").path().is_symlink());
+    let gitignore = std::fs::read_to_string(env.yolk_file(".gitignore"))?;
+    assert!(gitignore.lines().any(|line| line == "/.backups"));
+    Ok(())
+}
+
+#[test]
+fn test_doctor_fix() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str("export let eggs = #{};")?;
+    env.yolk_file(".gitignore").write_str("/.git\n")?;
+    env.yolk_cmd()
+        .arg("doctor")
+        .assert()
+        .failure()
+        .stdout(contains(".gitignore is missing"))
+        .stdout(contains("yolk doctor --fix"));
+
+    env.yolk_cmd()
+        .args(["doctor", "--fix"])
+        .assert()
+        .success()
+        .stdout(contains("fixed:"));
+    env.yolk_cmd()
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(contains("No problems found"));
+
+    // Safeguarding git changes how other tools see the repository, so it's never applied without confirmation
+    std::fs::rename(env.yolk_file(".yolk_git"), env.yolk_file(".git"))?;
+    env.yolk_cmd()
+        .args(["doctor", "--fix"])
+        .assert()
+        .failure()
+        .stdout(contains("Yolk git is not safeguarded"))
+        .stdout(contains("fixed:").not());
+    assert!(env.yolk_file(".git").exists());
+    Ok(())
+}
+
+#[test]
+fn test_sync_and_list_selected_eggs() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{
+                nvim: #{ targets: `~/nvim`, tags: ["work"] },
+                zsh: #{ targets: `~/zsh` },
+                waybar: #{ targets: `~/waybar`, tags: ["desktop"] },
+            };
+        "#})?;
+    for egg in ["nvim", "zsh", "waybar"] {
+        env.egg_file(egg).create_dir_all()?;
+        env.egg_file(format!("{egg}/config")).write_str("")?;
+    }
+
+    env.yolk_cmd()
+        .args(["sync", "--egg", "zsh", "--tag", "desktop"])
+        .assert()
+        .success();
+    assert!(env.home_file("zsh").is_symlink());
+    assert!(env.home_file("waybar").is_symlink());
+    assert!(!env.home_file("nvim").exists());
+
+    env.yolk_cmd()
+        .args(["list", "--tag", "work"])
+        .assert()
+        .success()
+        .stdout("✗ nvim\n");
+
+    env.yolk_cmd()
+        .args(["sync", "--egg", "missing"])
+        .assert()
+        .failure()
+        .stderr(contains("No egg with name missing"));
+    Ok(())
+}
+
+#[cfg(not(windows))]
+#[test]
+fn test_untrusted_hooks_require_trust() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{
+                foo: #{ targets: `~/foo`, unsafe_shell_hooks: #{ pre_deploy: "touch hook_ran" } },
+            };
+        "#})?;
+    env.egg_file("foo").create_dir_all()?;
+
+    env.yolk_cmd()
+        .arg("sync")
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Refusing to run untrusted pre_deploy hook of egg foo",
+        ));
+    assert!(!env.egg_file("foo/hook_ran").exists());
+
+    env.yolk_cmd()
+        .args(["hooks", "list"])
+        .assert()
+        .success()
+        .stdout("✗ foo pre_deploy: touch hook_ran\n");
+    env.yolk_cmd()
+        .args(["sync", "--trust-hooks"])
+        .assert()
+        .success();
+    assert!(env.egg_file("foo/hook_ran").exists());
+    env.yolk_cmd()
+        .args(["hooks", "list"])
+        .assert()
+        .success()
+        .stdout("✓ foo pre_deploy: touch hook_ran\n");
+    env.yolk_cmd()
+        .args(["hooks", "trust"])
+        .assert()
+        .success()
+        .stdout("All hooks are already trusted\n");
+    Ok(())
+}
+
+#[test]
+fn test_git_hooks() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{};
+            export let unsafe_shell_hooks = #{
+                pre_git: "touch pre_git_ran",
+                post_git: ["touch", "post_git_ran"],
+            };
+        "#})?;
+
+    env.yolk_git(&["status"]).failure().stderr(contains(
+        "Refusing to run untrusted pre_git hook of yolk.rhai",
+    ));
+    assert!(!env.yolk_file("pre_git_ran").exists());
+
+    env.yolk_cmd()
+        .args(["hooks", "list"])
+        .assert()
+        .success()
+        .stdout(indoc::indoc! {r#"
+            ✗ yolk.rhai pre_git: touch pre_git_ran
+            ✗ yolk.rhai post_git: ["touch", "post_git_ran"]
+        "#});
+    env.yolk_cmd().args(["hooks", "trust"]).assert().success();
+    env.yolk_git(&["status"]).success();
+    assert!(env.yolk_file("pre_git_ran").exists());
+    assert!(env.yolk_file("post_git_ran").exists());
+    Ok(())
+}
+
+#[test]
+fn test_verify_refuses_lossy_templates() -> TestResult {
+    let env = TestEnv::init()?;
+    env.yolk_rhai().write_str(indoc::indoc! {r#"
+            export let eggs = #{ foo: #{ targets: "~", strategy: "merge", templates: ["foo.toml"] } };
+        "#})?;
+    env.yolk_file("eggs/foo/foo.toml").write_str(
+        "# {# (if LOCAL { get_yolk_text() } else { \"token = hidden\" }) #}\ntoken = secret\n",
+    )?;
+
+    env.yolk_cmd()
+        .args(["verify"])
+        .assert()
+        .failure()
+        .stderr(contains("don't survive a round trip"));
+    env.yolk_git(&["status"])
+        .failure()
+        .stderr(contains("don't survive a round trip"));
+
+    env.yolk_file("eggs/foo/foo.toml").write_str(
+        "# {# replace_value(if LOCAL { \"secret\" } else { \"hidden\" }) #}\ntoken = secret\n",
+    )?;
+    env.yolk_cmd().args(["verify"]).assert().success();
+    env.yolk_git(&["status"]).success();
+    Ok(())
+}